@@ -2,7 +2,10 @@ use atomic_shim::AtomicU64;
 use getopts::Options;
 use hdrhistogram::Histogram;
 use log::{error, info};
-use metrics::{gauge, histogram, increment};
+use metrics::{
+    gauge, histogram, increment, register_counter, register_gauge, register_histogram, Counter,
+    Gauge,
+};
 use metrics_util::DebuggingRecorder;
 use std::{
     env,
@@ -17,22 +20,45 @@ use std::{
 
 const LOOP_SAMPLE: u64 = 1000;
 
+/// Handles registered up front, which skip the registry lookup on every update.
+struct Handles {
+    counter: Counter,
+    gauge: Gauge,
+    histogram: metrics::Histogram,
+}
+
+impl Handles {
+    fn register() -> Handles {
+        Handles {
+            counter: register_counter!("ok"),
+            gauge: register_gauge!("total"),
+            histogram: register_histogram!("ok"),
+        }
+    }
+}
+
 struct Generator {
     t0: Option<Instant>,
     gauge: i64,
     hist: Histogram<u64>,
     done: Arc<AtomicBool>,
     rate_counter: Arc<AtomicU64>,
+    handles: Option<Handles>,
 }
 
 impl Generator {
-    fn new(done: Arc<AtomicBool>, rate_counter: Arc<AtomicU64>) -> Generator {
+    fn new(
+        done: Arc<AtomicBool>,
+        rate_counter: Arc<AtomicU64>,
+        handles: Option<Handles>,
+    ) -> Generator {
         Generator {
             t0: None,
             gauge: 0,
-            hist: Histogram::<u64>::new_with_bounds(1, u64::max_value(), 3).unwrap(),
+            hist: Histogram::<u64>::new_with_bounds(1, u64::MAX, 3).unwrap(),
             done,
             rate_counter,
+            handles,
         }
     }
 
@@ -56,9 +82,18 @@ impl Generator {
                     None
                 };
 
-                increment!("ok");
-                gauge!("total", self.gauge as f64);
-                histogram!("ok", t1.sub(t0));
+                match &self.handles {
+                    Some(handles) => {
+                        handles.counter.increment(1);
+                        handles.gauge.update(self.gauge as f64);
+                        handles.histogram.record(t1.sub(t0));
+                    }
+                    None => {
+                        increment!("ok");
+                        gauge!("total", self.gauge as f64);
+                        histogram!("ok", t1.sub(t0));
+                    }
+                }
 
                 if let Some(val) = start {
                     let delta = Instant::now() - val;
@@ -104,6 +139,16 @@ pub fn opts() -> Options {
         "INTEGER",
     );
     opts.optopt("p", "producers", "number of producers", "INTEGER");
+    opts.optflag(
+        "",
+        "handles",
+        "update metrics through registered handles rather than the macros",
+    );
+    opts.optflag(
+        "",
+        "sharded-counters",
+        "store counters as sharded counters, to avoid contention between producers",
+    );
     opts.optflag("h", "help", "print this help menu");
 
     opts
@@ -143,10 +188,19 @@ fn main() {
         .parse()
         .unwrap();
 
+    let use_handles = matches.opt_present("handles");
+    let sharded_counters = matches.opt_present("sharded-counters");
+
     info!("duration: {}s", seconds);
     info!("producers: {}", producers);
+    info!("handles: {}", use_handles);
+    info!("sharded counters: {}", sharded_counters);
 
-    let recorder = DebuggingRecorder::new();
+    let recorder = if sharded_counters {
+        DebuggingRecorder::with_sharded_counters()
+    } else {
+        DebuggingRecorder::new()
+    };
     let snapshotter = recorder.snapshotter();
     recorder.install().expect("failed to install recorder");
 
@@ -161,7 +215,12 @@ fn main() {
         let d = done.clone();
         let r = rate_counter.clone();
         let handle = thread::spawn(move || {
-            let mut gen = Generator::new(d, r);
+            let handles = if use_handles {
+                Some(Handles::register())
+            } else {
+                None
+            };
+            let mut gen = Generator::new(d, r, handles);
             gen.run();
         });
 
@@ -172,7 +231,7 @@ fn main() {
     let mut total = 0;
     let mut t0 = Instant::now();
 
-    let mut snapshot_hist = Histogram::<u64>::new_with_bounds(1, u64::max_value(), 3).unwrap();
+    let mut snapshot_hist = Histogram::<u64>::new_with_bounds(1, u64::MAX, 3).unwrap();
     for _ in 0..seconds {
         let t1 = Instant::now();
 
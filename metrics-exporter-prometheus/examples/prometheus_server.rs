@@ -1,7 +1,7 @@
 use std::thread;
 use std::time::Duration;
 
-use metrics::{histogram, increment, register_counter, register_histogram};
+use metrics::{describe_counter, describe_histogram, histogram, increment, Unit};
 use metrics_exporter_prometheus::PrometheusBuilder;
 
 use quanta::Clock;
@@ -14,18 +14,17 @@ fn main() {
         .install()
         .expect("failed to install Prometheus recorder");
 
-    // We register these metrics, which gives us a chance to specify a description for them.  The
-    // Prometheus exporter records this description and adds it as HELP text when the endpoint is
-    // scraped.
-    //
-    // Registering metrics ahead of using them is not required, but is the only way to specify the
-    // description of a metric.
-    register_counter!(
+    // We describe these metrics, which gives us a chance to specify a unit and description for
+    // them.  The Prometheus exporter adds the description as HELP text when the endpoint is
+    // scraped, and uses the unit to render the histogram in seconds, as
+    // `tcp_server_loop_delta_seconds`, even though we record durations in nanoseconds.
+    describe_counter!(
         "tcp_server_loops",
         "The iterations of the TCP server event loop so far."
     );
-    register_histogram!(
-        "tcp_server_loop_delta_ns",
+    describe_histogram!(
+        "tcp_server_loop_delta",
+        Unit::Nanoseconds,
         "The time taken for iterations of the TCP server event loop."
     );
 
@@ -38,7 +37,7 @@ fn main() {
 
         if let Some(t) = last {
             let delta: Duration = clock.now() - t;
-            histogram!("tcp_server_loop_delta_ns", delta, "system" => "foo");
+            histogram!("tcp_server_loop_delta", delta, "system" => "foo");
         }
 
         last = Some(clock.now());
@@ -6,22 +6,27 @@ use hyper::{
     service::{make_service_fn, service_fn},
     {Body, Error as HyperError, Response, Server},
 };
-use metrics::{Key, Recorder, SetRecorderError, Unit};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, HistogramFn, Key, Recorder, SetRecorderError, SharedString,
+    Unit,
+};
 use metrics_util::{
-    parse_quantiles, CompositeKey, Handle, Histogram, MetricKind, Quantile, Registry,
+    layers::SAMPLE_RATE_LABEL, parse_quantiles, CompositeKey, Generation, Handle, Histogram,
+    MetricKind, Quantile, Registry,
 };
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use sketches_ddsketch::{Config as SketchConfig, DDSketch};
 use std::io;
 use std::iter::FromIterator;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use std::{collections::HashMap, time::SystemTime};
 use thiserror::Error as ThisError;
 use tokio::{pin, runtime, select};
 
 type PrometheusRegistry = Registry<CompositeKey, Handle>;
-type HdrHistogram = hdrhistogram::Histogram<u64>;
 
 /// Errors that could occur while installing a Prometheus recorder/exporter.
 #[derive(ThisError, Debug)]
@@ -52,22 +57,140 @@ enum Distribution {
     /// Computes and exposes value quantiles directly to Prometheus i.e. 50% of
     /// requests were faster than 200ms, and 99% of requests were faster than
     /// 1000ms, etc.
-    Summary(HdrHistogram, u64),
+    ///
+    /// Quantiles are computed with a sketch, so are only accurate to within 1% of the true
+    /// value, and are `NaN` until the summary has seen any samples.
+    Summary(DDSketch),
+}
+
+/// The distributions of a single series, one per sample rate it was recorded at.
+///
+/// A series can be recorded both with and without sampling, such as when only some of the code
+/// paths recording it go through a `SamplingLayer`, so each part is kept apart and scaled up by its
+/// own rate when rendering.
+type Parts = Vec<(f64, Distribution)>;
+
+/// Scales a count from a part sampled at `rate` back up to the number of samples it stands for.
+fn scale_count(count: u64, rate: f64) -> u64 {
+    if rate == 1.0 {
+        count
+    } else {
+        (count as f64 / rate).round() as u64
+    }
+}
+
+/// How long metrics of each kind can go without being updated before they're removed.
+#[derive(Clone, Copy, Default)]
+struct IdleTimeouts {
+    counter: Option<Duration>,
+    gauge: Option<Duration>,
+    histogram: Option<Duration>,
 }
 
+impl IdleTimeouts {
+    fn get(&self, kind: MetricKind) -> Option<Duration> {
+        match kind {
+            MetricKind::Counter => self.counter,
+            MetricKind::Gauge => self.gauge,
+            MetricKind::Histogram => self.histogram,
+        }
+    }
+
+    fn set(&mut self, kind: MetricKind, timeout: Option<Duration>) {
+        match kind {
+            MetricKind::Counter => self.counter = timeout,
+            MetricKind::Gauge => self.gauge = timeout,
+            MetricKind::Histogram => self.histogram = timeout,
+        }
+    }
+}
+
+/// Tracks when each metric was last seen to be updated, so that idle metrics can be removed.
+///
+/// Updates are spotted by comparing the generation of a metric between renders, so a metric is
+/// only considered idle once it has gone unchanged for the idle timeout across renders.
+struct Recency {
+    idle_timeouts: IdleTimeouts,
+    last_updated: HashMap<MetricKind, HashMap<Key, (Generation, Instant)>>,
+}
+
+impl Recency {
+    fn new(idle_timeouts: IdleTimeouts) -> Recency {
+        Recency {
+            idle_timeouts,
+            last_updated: HashMap::new(),
+        }
+    }
+
+    /// Whether metrics of the given kind expire once idle, and so need their updates tracked.
+    fn tracks(&self, kind: MetricKind) -> bool {
+        self.idle_timeouts.get(kind).is_some()
+    }
+
+    /// Checks whether the given metric has gone without updates for longer than its idle timeout.
+    ///
+    /// Idle metrics are still tracked until they're forgotten about, so that a metric which fails
+    /// to be deleted, because it's still held elsewhere, stays idle until it's updated again.
+    fn is_idle(
+        &mut self,
+        kind: MetricKind,
+        key: &Key,
+        generation: Generation,
+        now: Instant,
+    ) -> bool {
+        let idle_timeout = match self.idle_timeouts.get(kind) {
+            Some(idle_timeout) => idle_timeout,
+            None => return false,
+        };
+
+        let last_updated = self.last_updated.entry(kind).or_default();
+        match last_updated.get_mut(key) {
+            Some((last_generation, last_update)) => {
+                if *last_generation != generation {
+                    *last_generation = generation;
+                    *last_update = now;
+                    false
+                } else {
+                    now.saturating_duration_since(*last_update) >= idle_timeout
+                }
+            }
+            None => {
+                last_updated.insert(key.clone(), (generation, now));
+                false
+            }
+        }
+    }
+
+    /// Forgets about the given metric, once it has been deleted.
+    fn forget(&mut self, kind: MetricKind, key: &Key) {
+        if let Some(last_updated) = self.last_updated.get_mut(&kind) {
+            last_updated.remove(key);
+        }
+    }
+}
+
+/// Called with every metric removed for being idle.
+type IdleRemovalFn = Arc<dyn Fn(MetricKind, &Key) + Send + Sync>;
+
+/// Counters and gauges read from the registry.
+///
+/// Histograms are merged into the distributions kept by the recorder, and are rendered from there.
 struct Snapshot {
     pub counters: HashMap<String, HashMap<Vec<String>, u64>>,
     pub gauges: HashMap<String, HashMap<Vec<String>, f64>>,
-    pub distributions: HashMap<String, HashMap<Vec<String>, Distribution>>,
 }
 
 struct Inner {
     registry: PrometheusRegistry,
-    distributions: RwLock<HashMap<String, HashMap<Vec<String>, Distribution>>>,
+    distributions: RwLock<HashMap<String, HashMap<Vec<String>, Parts>>>,
     quantiles: Vec<Quantile>,
-    buckets: Vec<u64>,
-    buckets_by_name: Option<HashMap<String, Vec<u64>>>,
-    descriptions: RwLock<HashMap<String, &'static str>>,
+    buckets: Vec<f64>,
+    buckets_by_name: Option<HashMap<String, Vec<f64>>>,
+    descriptions: RwLock<HashMap<String, SharedString>>,
+    units: RwLock<HashMap<String, Unit>>,
+    counter: fn() -> Handle,
+    recency: Mutex<Recency>,
+    on_idle_removal: Option<IdleRemovalFn>,
 }
 
 impl Inner {
@@ -76,89 +199,132 @@ impl Inner {
     }
 
     fn get_recent_metrics(&self) -> Snapshot {
-        let metrics = self.registry.get_handles();
-
+        let now = Instant::now();
+        let mut recency = self.recency.lock();
+
+        // Counters and gauges are read in a single pass over the registry.  Histograms are merged
+        // into our distributions once we're done, so that the registry isn't kept locked while we
+        // do so, and idle metrics are only deleted then, as deleting a metric fails while its
+        // handle is held.  Idle metrics are rendered as usual in the meantime, in case deleting
+        // them fails.
         let mut counters = HashMap::new();
         let mut gauges = HashMap::new();
+        let mut collected_histograms = Vec::new();
+        let mut idle = Vec::new();
+        self.registry.visit(|key, handle| {
+            let kind = key.kind();
+            if recency.tracks(kind) {
+                let generation = handle.get_generation();
+                if recency.is_idle(kind, key.key(), generation, now) {
+                    idle.push((key.clone(), generation));
+                }
+            }
 
-        let mut sorted_overrides = self
-            .buckets_by_name
-            .as_ref()
-            .map(|h| Vec::from_iter(h.iter()))
-            .unwrap_or_else(|| vec![]);
-        sorted_overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
-
-        for (key, handle) in metrics.into_iter() {
-            let (kind, key) = key.into_parts();
-            let (name, labels) = key_to_parts(key);
-
+            let (name, labels) = key_to_parts(key.key(), kind == MetricKind::Histogram);
             match kind {
                 MetricKind::Counter => {
-                    let entry = counters
+                    counters
                         .entry(name)
-                        .or_insert_with(|| HashMap::new())
-                        .entry(labels)
-                        .or_insert(0);
-
-                    *entry = handle.read_counter();
+                        .or_insert_with(HashMap::new)
+                        .insert(labels, handle.read_counter());
                 }
                 MetricKind::Gauge => {
-                    let entry = gauges
+                    gauges
                         .entry(name)
-                        .or_insert_with(|| HashMap::new())
-                        .entry(labels)
-                        .or_insert(0.0);
+                        .or_insert_with(HashMap::new)
+                        .insert(labels, handle.read_gauge());
+                }
+                MetricKind::Histogram => {
+                    let rate = sample_rate(key.key()).unwrap_or(1.0);
+                    collected_histograms.push((rate, name, labels, handle.clone()));
+                }
+            }
+        });
 
-                    *entry = handle.read_gauge();
+        let mut sorted_overrides = self
+            .buckets_by_name
+            .as_ref()
+            .map(|h| Vec::from_iter(h.iter()))
+            .unwrap_or_default();
+        sorted_overrides.sort_by_key(|(a, _)| std::cmp::Reverse(a.len()));
+
+        let mut wg = self.distributions.write();
+        for (rate, name, labels, handle) in collected_histograms {
+            let buckets = sorted_overrides
+                .iter()
+                .find(|(k, _)| name.ends_with(*k))
+                .map(|(_, buckets)| *buckets)
+                .unwrap_or(&self.buckets);
+
+            let parts = wg.entry(name).or_default().entry(labels).or_default();
+            let idx = match parts.iter().position(|(r, _)| *r == rate) {
+                Some(idx) => idx,
+                None => {
+                    let distribution = match buckets.is_empty() {
+                        false => {
+                            let histogram = Histogram::new(buckets)
+                                .expect("failed to create histogram with buckets defined");
+                            Distribution::Histogram(histogram)
+                        }
+                        true => {
+                            let summary = DDSketch::new(SketchConfig::defaults());
+                            Distribution::Summary(summary)
+                        }
+                    };
+                    parts.push((rate, distribution));
+                    parts.len() - 1
+                }
+            };
+            let entry = &mut parts[idx].1;
+
+            match entry {
+                Distribution::Histogram(histogram) => {
+                    handle.read_histogram_with_clear(|samples| histogram.record_many(samples))
                 }
+                Distribution::Summary(summary) => handle.read_histogram_with_clear(|samples| {
+                    for sample in samples {
+                        summary.add(*sample);
+                    }
+                }),
+            }
+        }
+
+        let mut removed = Vec::new();
+        for (key, generation) in idle {
+            if !self.registry.delete(&key, generation) {
+                continue;
+            }
+
+            // Only metrics which are actually gone are forgotten about, along with their samples,
+            // as histograms keep those outside of the registry.
+            recency.forget(key.kind(), key.key());
+            let strip_sample_rate = key.kind() == MetricKind::Histogram;
+            let (name, labels) = key_to_parts(key.key(), strip_sample_rate);
+            match key.kind() {
+                MetricKind::Counter => remove_series(&mut counters, &name, &labels),
+                MetricKind::Gauge => remove_series(&mut gauges, &name, &labels),
                 MetricKind::Histogram => {
-                    let buckets = sorted_overrides
-                        .iter()
-                        .find(|(k, _)| name.ends_with(*k))
-                        .map(|(_, buckets)| *buckets)
-                        .unwrap_or(&self.buckets);
-
-                    let mut wg = self.distributions.write();
-                    let entry = wg
-                        .entry(name.clone())
-                        .or_insert_with(|| HashMap::new())
-                        .entry(labels)
-                        .or_insert_with(|| match buckets.is_empty() {
-                            false => {
-                                let histogram = Histogram::new(buckets)
-                                    .expect("failed to create histogram with buckets defined");
-                                Distribution::Histogram(histogram)
-                            }
-                            true => {
-                                let summary =
-                                    HdrHistogram::new(3).expect("failed to create histogram");
-                                Distribution::Summary(summary, 0)
-                            }
-                        });
-
-                    match entry {
-                        Distribution::Histogram(histogram) => handle
-                            .read_histogram_with_clear(|samples| histogram.record_many(samples)),
-                        Distribution::Summary(summary, sum) => {
-                            handle.read_histogram_with_clear(|samples| {
-                                for sample in samples {
-                                    let _ = summary.record(*sample);
-                                    *sum += *sample;
-                                }
-                            })
+                    let rate = sample_rate(key.key()).unwrap_or(1.0);
+                    if let Some(parts) = wg.get_mut(&name).and_then(|l| l.get_mut(&labels)) {
+                        parts.retain(|(r, _)| *r != rate);
+                        if parts.is_empty() {
+                            remove_series(&mut wg, &name, &labels);
                         }
                     }
                 }
             }
+            removed.push(key);
         }
+        drop(wg);
+        drop(recency);
 
-        let distributions = self.distributions.read().clone();
-
-        Snapshot {
-            counters,
-            gauges,
-            distributions,
+        if let Some(on_idle_removal) = &self.on_idle_removal {
+            for key in removed {
+                on_idle_removal(key.kind(), key.key());
+            }
         }
+
+        Snapshot { counters, gauges }
     }
 
     pub fn render(&self) -> String {
@@ -166,13 +332,12 @@ impl Inner {
             .buckets_by_name
             .as_ref()
             .map(|h| Vec::from_iter(h.iter()))
-            .unwrap_or_else(|| vec![]);
-        sorted_overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+            .unwrap_or_default();
+        sorted_overrides.sort_by_key(|(a, _)| std::cmp::Reverse(a.len()));
 
         let Snapshot {
             mut counters,
             mut gauges,
-            mut distributions,
         } = self.get_recent_metrics();
 
         let ts = SystemTime::now()
@@ -186,103 +351,157 @@ impl Inner {
         );
 
         let descriptions = self.descriptions.read();
+        let units = self.units.read();
 
         for (name, mut by_labels) in counters.drain() {
+            let unit = base_unit(units.get(name.as_str()));
+            let rendered_name = render_metric_name(&name, MetricKind::Counter, unit);
             if let Some(desc) = descriptions.get(name.as_str()) {
-                output.push_str("# HELP ");
-                output.push_str(name.as_str());
-                output.push_str(" ");
-                output.push_str(desc);
-                output.push_str("\n");
+                render_help_line(&mut output, &rendered_name, desc);
             }
 
             output.push_str("# TYPE ");
-            output.push_str(name.as_str());
+            output.push_str(rendered_name.as_str());
             output.push_str(" counter\n");
             for (labels, value) in by_labels.drain() {
-                let full_name = render_labeled_name(&name, &labels);
+                let full_name = render_labeled_name(&rendered_name, &labels);
                 output.push_str(full_name.as_str());
-                output.push_str(" ");
-                output.push_str(value.to_string().as_str());
-                output.push_str("\n");
+                output.push(' ');
+                match unit {
+                    Some(unit) if unit.conversion_factor() != 1.0 => {
+                        output.push_str(unit.convert_to_base(value as f64).to_string().as_str())
+                    }
+                    _ => output.push_str(value.to_string().as_str()),
+                }
+                output.push('\n');
             }
-            output.push_str("\n");
+            output.push('\n');
         }
 
         for (name, mut by_labels) in gauges.drain() {
+            let unit = base_unit(units.get(name.as_str()));
+            let rendered_name = render_metric_name(&name, MetricKind::Gauge, unit);
             if let Some(desc) = descriptions.get(name.as_str()) {
-                output.push_str("# HELP ");
-                output.push_str(name.as_str());
-                output.push_str(" ");
-                output.push_str(desc);
-                output.push_str("\n");
+                render_help_line(&mut output, &rendered_name, desc);
             }
 
             output.push_str("# TYPE ");
-            output.push_str(name.as_str());
+            output.push_str(rendered_name.as_str());
             output.push_str(" gauge\n");
             for (labels, value) in by_labels.drain() {
-                let full_name = render_labeled_name(&name, &labels);
+                let value = unit.map_or(value, |u| u.convert_to_base(value));
+                let full_name = render_labeled_name(&rendered_name, &labels);
                 output.push_str(full_name.as_str());
-                output.push_str(" ");
+                output.push(' ');
                 output.push_str(value.to_string().as_str());
-                output.push_str("\n");
+                output.push('\n');
             }
-            output.push_str("\n");
+            output.push('\n');
         }
 
         let mut sorted_overrides = self
             .buckets_by_name
             .as_ref()
             .map(|h| Vec::from_iter(h.iter()))
-            .unwrap_or_else(|| vec![]);
-        sorted_overrides.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
-
-        for (name, mut by_labels) in distributions.drain() {
+            .unwrap_or_default();
+        sorted_overrides.sort_by_key(|(a, _)| std::cmp::Reverse(a.len()));
+
+        let distributions = self.distributions.read();
+        for (name, by_labels) in distributions.iter() {
+            // Samples, and thus bucket boundaries, are in the declared unit, so we only scale
+            // them to the base unit at the very end.
+            let unit = base_unit(units.get(name.as_str()));
+            let convert = |value: f64| unit.map_or(value, |u| u.convert_to_base(value));
+            let rendered_name = render_metric_name(name, MetricKind::Histogram, unit);
             if let Some(desc) = descriptions.get(name.as_str()) {
-                output.push_str("# HELP ");
-                output.push_str(name.as_str());
-                output.push_str(" ");
-                output.push_str(desc);
-                output.push_str("\n");
+                render_help_line(&mut output, &rendered_name, desc);
             }
 
             let has_buckets = sorted_overrides
                 .iter()
                 .any(|(k, _)| !self.buckets.is_empty() || name.ends_with(*k));
+            let name = rendered_name;
 
             output.push_str("# TYPE ");
             output.push_str(name.as_str());
-            output.push_str(" ");
+            output.push(' ');
             output.push_str(if has_buckets { "histogram" } else { "summary" });
-            output.push_str("\n");
+            output.push('\n');
+
+            for (labels, parts) in by_labels.iter() {
+                // Sampled parts only saw a fraction of the samples, so their counts and sums are
+                // scaled back up before being added together.  Quantiles are unaffected by
+                // sampling, so summaries are merged as-is.
+                let mut parts = parts.iter();
+                let (first_rate, first) = match parts.next() {
+                    Some(&(rate, ref part)) => (rate, part),
+                    None => continue,
+                };
+
+                let (sum, count) = match first {
+                    Distribution::Summary(first) => {
+                        let mut sum = first.sum().unwrap_or(0.0) / first_rate;
+                        let mut count = scale_count(first.count() as u64, first_rate);
+                        // Summaries are only copied when there are several parts to merge.
+                        let mut merged = None;
+                        for &(rate, ref part) in parts {
+                            if let Distribution::Summary(part) = part {
+                                sum += part.sum().unwrap_or(0.0) / rate;
+                                count += scale_count(part.count() as u64, rate);
+                                merged
+                                    .get_or_insert_with(|| first.clone())
+                                    .merge(part)
+                                    .expect("summaries share the same configuration");
+                            }
+                        }
+                        let summary = merged.as_ref().unwrap_or(first);
 
-            for (labels, distribution) in by_labels.drain() {
-                let (sum, count) = match distribution {
-                    Distribution::Summary(summary, sum) => {
                         for quantile in &self.quantiles {
-                            let value = summary.value_at_quantile(quantile.value());
+                            let value = summary
+                                .quantile(quantile.value())
+                                .ok()
+                                .flatten()
+                                .unwrap_or(f64::NAN);
+                            let value = convert(value);
                             let mut labels = labels.clone();
                             labels.push(format!("quantile=\"{}\"", quantile.value()));
                             let full_name = render_labeled_name(&name, &labels);
                             output.push_str(full_name.as_str());
-                            output.push_str(" ");
+                            output.push(' ');
                             output.push_str(value.to_string().as_str());
-                            output.push_str("\n");
+                            output.push('\n');
                         }
 
-                        (sum, summary.len())
+                        (convert(sum), count)
                     }
                     Distribution::Histogram(histogram) => {
-                        for (le, count) in histogram.buckets() {
+                        let scale = |(le, count): (f64, u64)| (le, scale_count(count, first_rate));
+                        let mut buckets = histogram
+                            .buckets()
+                            .into_iter()
+                            .map(scale)
+                            .collect::<Vec<_>>();
+                        let mut sum = histogram.sum() / first_rate;
+                        let mut count = scale_count(histogram.count(), first_rate);
+                        for &(rate, ref part) in parts {
+                            if let Distribution::Histogram(part) = part {
+                                for (bucket, (_, n)) in buckets.iter_mut().zip(part.buckets()) {
+                                    bucket.1 += scale_count(n, rate);
+                                }
+                                sum += part.sum() / rate;
+                                count += scale_count(part.count(), rate);
+                            }
+                        }
+
+                        for (le, count) in buckets {
                             let mut labels = labels.clone();
-                            labels.push(format!("le=\"{}\"", le));
+                            labels.push(format!("le=\"{}\"", convert(le)));
                             let bucket_name = format!("{}_bucket", name);
                             let full_name = render_labeled_name(&bucket_name, &labels);
                             output.push_str(full_name.as_str());
-                            output.push_str(" ");
+                            output.push(' ');
                             output.push_str(count.to_string().as_str());
-                            output.push_str("\n");
+                            output.push('\n');
                         }
 
                         let mut labels = labels.clone();
@@ -290,29 +509,29 @@ impl Inner {
                         let bucket_name = format!("{}_bucket", name);
                         let full_name = render_labeled_name(&bucket_name, &labels);
                         output.push_str(full_name.as_str());
-                        output.push_str(" ");
-                        output.push_str(histogram.count().to_string().as_str());
-                        output.push_str("\n");
+                        output.push(' ');
+                        output.push_str(count.to_string().as_str());
+                        output.push('\n');
 
-                        (histogram.sum(), histogram.count())
+                        (convert(sum), count)
                     }
                 };
 
                 let sum_name = format!("{}_sum", name);
-                let full_sum_name = render_labeled_name(&sum_name, &labels);
+                let full_sum_name = render_labeled_name(&sum_name, labels);
                 output.push_str(full_sum_name.as_str());
-                output.push_str(" ");
+                output.push(' ');
                 output.push_str(sum.to_string().as_str());
-                output.push_str("\n");
+                output.push('\n');
                 let count_name = format!("{}_count", name);
-                let full_count_name = render_labeled_name(&count_name, &labels);
+                let full_count_name = render_labeled_name(&count_name, labels);
                 output.push_str(full_count_name.as_str());
-                output.push_str(" ");
+                output.push(' ');
                 output.push_str(count.to_string().as_str());
-                output.push_str("\n");
+                output.push('\n');
             }
 
-            output.push_str("\n");
+            output.push('\n');
         }
 
         output
@@ -324,19 +543,48 @@ impl Inner {
 /// This recorder should be composed with other recorders or installed globally via
 /// [`metrics::set_boxed_recorder`][set_boxed_recorder].
 ///
+/// Units given when registering or describing a metric are used to follow the Prometheus naming
+/// conventions: time-based metrics are rendered in seconds and data-based metrics in bytes, with
+/// a matching `_seconds` or `_bytes` suffix added to the name, such that a histogram of
+/// [`Duration`](std::time::Duration)s declared in nanoseconds is exposed in seconds.  Counters
+/// are always suffixed with `_total`, so counters which were exposed without it before are
+/// renamed, and queries against them need to be updated.
 ///
+/// Histograms sampled by [`SamplingLayer`](metrics_util::layers::SamplingLayer) have their
+/// bucket counts, count and sum scaled back up by the sample rate, and the sample rate label is
+/// not exposed.
 pub struct PrometheusRecorder {
     inner: Arc<Inner>,
 }
 
 impl PrometheusRecorder {
-    fn add_description_if_missing(&self, key: &Key, description: Option<&'static str>) {
+    fn add_metadata_if_missing(
+        &self,
+        key: &Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) {
+        let name = sanitize_name(key.name());
+        if let Some(unit) = unit {
+            let mut units = self.inner.units.write();
+            units.entry(name.clone()).or_insert(unit);
+        }
         if let Some(description) = description {
             let mut descriptions = self.inner.descriptions.write();
-            if !descriptions.contains_key(key.name().as_ref()) {
-                descriptions.insert(key.name().to_string(), description);
-            }
+            descriptions
+                .entry(name)
+                .or_insert_with(|| description.into());
+        }
+    }
+
+    fn set_metadata(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        let name = sanitize_name(&name);
+        if let Some(unit) = unit {
+            let mut units = self.inner.units.write();
+            units.insert(name.clone(), unit);
         }
+        let mut descriptions = self.inner.descriptions.write();
+        descriptions.insert(name, description);
     }
 }
 
@@ -344,8 +592,17 @@ impl PrometheusRecorder {
 pub struct PrometheusBuilder {
     listen_address: SocketAddr,
     quantiles: Vec<Quantile>,
-    buckets: Vec<u64>,
-    buckets_by_name: Option<HashMap<String, Vec<u64>>>,
+    buckets: Vec<f64>,
+    buckets_by_name: Option<HashMap<String, Vec<f64>>>,
+    sharded_counters: bool,
+    idle_timeouts: IdleTimeouts,
+    on_idle_removal: Option<IdleRemovalFn>,
+}
+
+impl Default for PrometheusBuilder {
+    fn default() -> Self {
+        PrometheusBuilder::new()
+    }
 }
 
 impl PrometheusBuilder {
@@ -358,6 +615,9 @@ impl PrometheusBuilder {
             quantiles,
             buckets: vec![],
             buckets_by_name: None,
+            sharded_counters: false,
+            idle_timeouts: IdleTimeouts::default(),
+            on_idle_removal: None,
         }
     }
 
@@ -381,6 +641,10 @@ impl PrometheusBuilder {
     ///
     /// If buckets are set (via [`set_buckets`] or [`set_buckets_for_metric`]) then all histograms will
     /// be exposed as summaries instead.
+    ///
+    /// Quantiles are approximate, with a relative error of at most 1%, so that summaries can hold
+    /// any `f64` sample in a bounded amount of memory.  Summaries without any samples render their
+    /// quantiles as `NaN`.
     pub fn set_quantiles(mut self, quantiles: &[f64]) -> Self {
         self.quantiles = parse_quantiles(quantiles);
         self
@@ -388,9 +652,10 @@ impl PrometheusBuilder {
 
     /// Sets the buckets to use when rendering histograms.
     ///
-    /// Buckets values represent the higher bound of each buckets.  If buckets are set, then all
-    /// histograms will be rendered as true Prometheus histograms, instead of summaries.
-    pub fn set_buckets(mut self, values: &[u64]) -> Self {
+    /// Buckets values represent the higher bound of each buckets, in the unit each histogram was
+    /// declared with, if any.  If buckets are set, then all histograms will be rendered as true
+    /// Prometheus histograms, instead of summaries.
+    pub fn set_buckets(mut self, values: &[f64]) -> Self {
         self.buckets = values.to_vec();
         self
     }
@@ -399,17 +664,67 @@ impl PrometheusBuilder {
     ///
     /// The match is suffix-based, and the longest match found will be used.
     ///
-    /// Buckets values represent the higher bound of each buckets.  If buckets are set, then any
-    /// histograms that match will be rendered as true Prometheus histograms, instead of summaries.
+    /// Buckets values represent the higher bound of each buckets, in the unit the histogram was
+    /// declared with, if any.  If buckets are set, then any histograms that match will be rendered
+    /// as true Prometheus histograms, instead of summaries.
     ///
     /// This option changes the observer's output of histogram-type metric into summaries.
     /// It only affects matching metrics if set_buckets was not used.
-    pub fn set_buckets_for_metric(mut self, name: &str, values: &[u64]) -> Self {
-        let buckets = self.buckets_by_name.get_or_insert_with(|| HashMap::new());
+    pub fn set_buckets_for_metric(mut self, name: &str, values: &[f64]) -> Self {
+        let buckets = self.buckets_by_name.get_or_insert_with(HashMap::new);
         buckets.insert(name.to_owned(), values.to_vec());
         self
     }
 
+    /// Sets whether counters are stored as [`ShardedCounter`]s.
+    ///
+    /// Sharded counters avoid contention when many threads update the same counter, at the cost of
+    /// slower rendering and of memory: every counter takes up a cache line, typically 128 bytes,
+    /// for each CPU, rounded up to the next power of two.  With 64 CPUs, that's 8 KiB per counter,
+    /// rather than 8 bytes, for every combination of name and labels, so this is best left off
+    /// when there are many counters, or counters with many sets of labels.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`ShardedCounter`]: metrics_util::ShardedCounter
+    pub fn sharded_counters(mut self, enabled: bool) -> Self {
+        self.sharded_counters = enabled;
+        self
+    }
+
+    /// Sets how long metrics of the given kind can go without being updated before they're removed.
+    ///
+    /// Removed metrics no longer show up when scraped, which keeps metrics for short-lived labels,
+    /// such as per-connection or per-job labels, from piling up.  A metric which is updated again
+    /// after being removed starts over, from zero for counters.  Metrics are only checked for
+    /// updates when scraped, so a metric is removed on the first scrape after the timeout has
+    /// elapsed without it being updated.
+    ///
+    /// Metrics are never removed while a handle returned during registration is still held, as
+    /// updates through the handle would otherwise be lost.
+    ///
+    /// `None` keeps metrics of the given kind around forever, which is the default for every kind.
+    pub fn idle_timeout(mut self, kind: MetricKind, timeout: Option<Duration>) -> Self {
+        self.idle_timeouts.set(kind, timeout);
+        self
+    }
+
+    /// Sets a function to call with every metric removed for being idle.
+    ///
+    /// The function is called during scrapes, once the metric is gone.  This lets layers which
+    /// keep track of series, such as [`CardinalityLimit`], know that a series no longer exists, by
+    /// passing [`SeriesReleaser::release`] here.
+    ///
+    /// [`CardinalityLimit`]: metrics_util::layers::CardinalityLimit
+    /// [`SeriesReleaser::release`]: metrics_util::layers::SeriesReleaser::release
+    pub fn on_idle_removal<F>(mut self, f: F) -> Self
+    where
+        F: Fn(MetricKind, &Key) + Send + Sync + 'static,
+    {
+        self.on_idle_removal = Some(Arc::new(f));
+        self
+    }
+
     /// Builds the recorder and exporter and installs them globally.
     ///
     /// An error will be returned if there's an issue with creating the HTTP server or with
@@ -461,6 +776,14 @@ impl PrometheusBuilder {
             buckets: self.buckets.clone(),
             buckets_by_name: self.buckets_by_name.clone(),
             descriptions: RwLock::new(HashMap::new()),
+            units: RwLock::new(HashMap::new()),
+            counter: if self.sharded_counters {
+                Handle::sharded_counter
+            } else {
+                Handle::counter
+            },
+            recency: Mutex::new(Recency::new(self.idle_timeouts)),
+            on_idle_removal: self.on_idle_removal,
         });
 
         let recorder = PrometheusRecorder {
@@ -494,65 +817,140 @@ impl PrometheusBuilder {
 }
 
 impl Recorder for PrometheusRecorder {
-    fn register_counter(&self, key: Key, _unit: Option<Unit>, description: Option<&'static str>) {
-        self.add_description_if_missing(&key, description);
-        self.inner.registry().op(
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        self.add_metadata_if_missing(&key, unit, description);
+        let handle = self.inner.registry().op(
             CompositeKey::new(MetricKind::Counter, key),
-            |_| {},
-            || Handle::counter(),
+            |h| h.clone(),
+            self.inner.counter,
         );
+        Counter::from_arc(Arc::new(handle))
     }
 
-    fn register_gauge(&self, key: Key, _unit: Option<Unit>, description: Option<&'static str>) {
-        self.add_description_if_missing(&key, description);
-        self.inner.registry().op(
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        self.add_metadata_if_missing(&key, unit, description);
+        let handle = self.inner.registry().op(
             CompositeKey::new(MetricKind::Gauge, key),
-            |_| {},
-            || Handle::gauge(),
+            |h| h.clone(),
+            Handle::gauge,
         );
+        Gauge::from_arc(Arc::new(handle))
     }
 
-    fn register_histogram(&self, key: Key, _unit: Option<Unit>, description: Option<&'static str>) {
-        self.add_description_if_missing(&key, description);
-        self.inner.registry().op(
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> metrics::Histogram {
+        self.add_metadata_if_missing(&key, unit, description);
+        let handle = self.inner.registry().op(
             CompositeKey::new(MetricKind::Histogram, key),
-            |_| {},
-            || Handle::histogram(),
+            |h| h.clone(),
+            Handle::histogram,
         );
+        metrics::Histogram::from_arc(Arc::new(handle))
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.set_metadata(name, unit, description);
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.set_metadata(name, unit, description);
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.set_metadata(name, unit, description);
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
         self.inner.registry().op(
             CompositeKey::new(MetricKind::Counter, key),
-            |h| h.increment_counter(value),
-            || Handle::counter(),
+            |h| CounterFn::increment(h, value),
+            self.inner.counter,
+        );
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        self.inner.registry().op(
+            CompositeKey::new(MetricKind::Counter, key),
+            |h| CounterFn::absolute(h, value),
+            self.inner.counter,
         );
     }
 
     fn update_gauge(&self, key: Key, value: f64) {
         self.inner.registry().op(
             CompositeKey::new(MetricKind::Gauge, key),
-            |h| h.update_gauge(value),
-            || Handle::gauge(),
+            |h| GaugeFn::update(h, value),
+            Handle::gauge,
         );
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        self.inner.registry().op(
+            CompositeKey::new(MetricKind::Gauge, key),
+            |h| GaugeFn::increment(h, value),
+            Handle::gauge,
+        );
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        self.inner.registry().op(
+            CompositeKey::new(MetricKind::Gauge, key),
+            |h| GaugeFn::decrement(h, value),
+            Handle::gauge,
+        );
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         self.inner.registry().op(
             CompositeKey::new(MetricKind::Histogram, key),
-            |h| h.record_histogram(value),
-            || Handle::histogram(),
+            |h| HistogramFn::record(h, value),
+            Handle::histogram,
         );
     }
 }
 
-fn key_to_parts(key: Key) -> (String, Vec<String>) {
-    let name = key.name();
-    let labels = key.labels();
-    let sanitize = |c| c == '.' || c == '=' || c == '{' || c == '}' || c == '+' || c == '-';
-    let name = name.replace(sanitize, "_");
-    let labels = labels
-        .into_iter()
+/// Removes a single series from a map of series by name and labels.
+fn remove_series<V>(
+    series: &mut HashMap<String, HashMap<Vec<String>, V>>,
+    name: &str,
+    labels: &[String],
+) {
+    if let Some(by_labels) = series.get_mut(name) {
+        by_labels.remove(labels);
+        if by_labels.is_empty() {
+            series.remove(name);
+        }
+    }
+}
+
+/// Gets the sanitized name and rendered labels of a key.
+///
+/// If `strip_sample_rate` is `true`, the sample rate added by
+/// `metrics_util::layers::SamplingLayer` is left out of the labels.
+fn key_to_parts(key: &Key, strip_sample_rate: bool) -> (String, Vec<String>) {
+    let name = sanitize_name(key.name());
+    let labels = key
+        .labels()
+        .filter(|label| !strip_sample_rate || label.key() != SAMPLE_RATE_LABEL)
         .map(|label| {
             let k = label.key();
             let v = label.value();
@@ -569,13 +967,408 @@ fn key_to_parts(key: Key) -> (String, Vec<String>) {
     (name, labels)
 }
 
+/// Gets the sample rate added by `metrics_util::layers::SamplingLayer` to the labels of a key.
+fn sample_rate(key: &Key) -> Option<f64> {
+    key.labels()
+        .filter(|l| l.key() == SAMPLE_RATE_LABEL)
+        .filter_map(|l| l.value().parse::<f64>().ok())
+        .find(|rate| *rate > 0.0 && *rate <= 1.0)
+}
+
+fn sanitize_name(name: &str) -> String {
+    let sanitize = |c| c == '.' || c == '=' || c == '{' || c == '}' || c == '+' || c == '-';
+    name.replace(sanitize, "_")
+}
+
+/// Gets the unit a metric should be rendered in, if it differs from how it was recorded.
+///
+/// Prometheus expects time in seconds and data in bytes, with the unit spelled out as a suffix of
+/// the metric name.  Other units are rendered as-is.
+fn base_unit(unit: Option<&Unit>) -> Option<&Unit> {
+    unit.filter(|u| u.is_time_based() || (u.is_data_based() && !u.is_data_rate_based()))
+}
+
+fn render_metric_name(name: &str, kind: MetricKind, unit: Option<&Unit>) -> String {
+    let mut rendered = name.to_string();
+    if let Some(unit) = unit {
+        let suffix = format!("_{}", unit.base_unit().as_str());
+        if !rendered.ends_with(&suffix) {
+            rendered.push_str(&suffix);
+        }
+    }
+    if kind == MetricKind::Counter && !rendered.ends_with("_total") {
+        rendered.push_str("_total");
+    }
+    rendered
+}
+
+fn render_help_line(output: &mut String, name: &str, description: &str) {
+    output.push_str("# HELP ");
+    output.push_str(name);
+    output.push(' ');
+    // Descriptions may be built at runtime, so escape them as the exposition format requires.
+    for c in description.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            c => output.push(c),
+        }
+    }
+    output.push('\n');
+}
+
 fn render_labeled_name(name: &str, labels: &[String]) -> String {
     let mut output = name.to_string();
     if !labels.is_empty() {
         let joined = labels.join(",");
-        output.push_str("{");
+        output.push('{');
         output.push_str(&joined);
-        output.push_str("}");
+        output.push('}');
     }
     output
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{IdleTimeouts, PrometheusBuilder, PrometheusRecorder, PrometheusRegistry, Recency};
+    use metrics::{CounterFn, GaugeFn, IntoF64, Key, KeyData, Label, Recorder, Unit};
+    use metrics_util::layers::{CardinalityLimitLayer, Layer, SAMPLE_RATE_LABEL};
+    use metrics_util::{CompositeKey, Handle, MetricKind};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use tokio::runtime;
+
+    fn build_recorder(builder: PrometheusBuilder) -> PrometheusRecorder {
+        // Binding the listener requires a runtime, even though we never drive the exporter.
+        let runtime = runtime::Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime");
+        let (recorder, _exporter) = runtime
+            .enter(|| {
+                builder
+                    .listen_address(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0))
+                    .build()
+            })
+            .expect("failed to build recorder");
+        recorder
+    }
+
+    #[test]
+    fn test_describe_sets_help() {
+        let recorder = build_recorder(PrometheusBuilder::new());
+
+        // Descriptions given at registration only fill in missing ones, while describing a
+        // metric always takes precedence.
+        recorder.register_counter(Key::Owned("requests".into()), None, Some("old"));
+        recorder.describe_counter(
+            "requests".into(),
+            None,
+            format!("requests for {}\nby path", "C:\\").into(),
+        );
+        recorder.register_counter(Key::Owned("requests".into()), None, Some("ignored"));
+        recorder.describe_gauge("connections".into(), None, "open connections".into());
+
+        let output = recorder.inner.render();
+        assert!(output.contains("# HELP requests_total requests for C:\\\\\\nby path\n"));
+        assert!(output.contains("# TYPE requests_total counter\nrequests_total 0\n"));
+
+        // Describing a metric doesn't create it.
+        assert!(!output.contains("connections"));
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let recorder = build_recorder(PrometheusBuilder::new().set_buckets(&[10.0]));
+
+        let key = || {
+            Key::Owned(KeyData::from_parts(
+                "latency",
+                vec![
+                    Label::new("path", "/"),
+                    Label::new(SAMPLE_RATE_LABEL, "0.25"),
+                ],
+            ))
+        };
+        recorder.record_histogram(key(), 5.0);
+        recorder.record_histogram(key(), 20.0);
+
+        let output = recorder.inner.render();
+        assert!(output.contains("latency_bucket{path=\"/\",le=\"10\"} 4\n"));
+        assert!(output.contains("latency_bucket{path=\"/\",le=\"+Inf\"} 8\n"));
+        assert!(output.contains("latency_sum{path=\"/\"} 100\n"));
+        assert!(output.contains("latency_count{path=\"/\"} 8\n"));
+        assert!(!output.contains(SAMPLE_RATE_LABEL));
+    }
+
+    #[test]
+    fn test_sample_rate_mixed() {
+        let recorder = build_recorder(PrometheusBuilder::new().set_buckets(&[10.0]));
+
+        // Only some of the samples for the series went through sampling, so only those are scaled.
+        let sampled = Key::Owned(KeyData::from_parts(
+            "latency",
+            vec![Label::new(SAMPLE_RATE_LABEL, "0.5")],
+        ));
+        recorder.record_histogram(sampled, 20.0);
+        recorder.record_histogram(Key::Owned("latency".into()), 5.0);
+        recorder.record_histogram(Key::Owned("latency".into()), 20.0);
+
+        let output = recorder.inner.render();
+        assert!(output.contains("latency_bucket{le=\"10\"} 1\n"));
+        assert!(output.contains("latency_bucket{le=\"+Inf\"} 4\n"));
+        assert!(output.contains("latency_sum 65\n"));
+        assert!(output.contains("latency_count 4\n"));
+    }
+
+    #[test]
+    fn test_summary() {
+        let recorder = build_recorder(PrometheusBuilder::new().set_quantiles(&[0.5]));
+
+        recorder.register_histogram(Key::Owned("empty".into()), None, None);
+        for value in &[0.25, 0.5, 0.75] {
+            recorder.record_histogram(Key::Owned("latency".into()), *value);
+        }
+
+        let output = recorder.inner.render();
+        assert!(output.contains("empty{quantile=\"0.5\"} NaN\n"));
+        assert!(output.contains("empty_count 0\n"));
+
+        let prefix = "latency{quantile=\"0.5\"} ";
+        let median = output
+            .lines()
+            .find(|line| line.starts_with(prefix))
+            .and_then(|line| line[prefix.len()..].parse::<f64>().ok())
+            .expect("missing median");
+        assert!((median - 0.5).abs() <= 0.5 * 0.01);
+        assert!(output.contains("latency_count 3\n"));
+    }
+
+    #[test]
+    fn test_sharded_counters() {
+        let recorder = build_recorder(PrometheusBuilder::new().sharded_counters(true));
+
+        let counter = recorder.register_counter(Key::Owned("requests".into()), None, None);
+        counter.increment(2);
+        recorder.increment_counter(Key::Owned("requests".into()), 3);
+        recorder.absolute_counter(Key::Owned("connections".into()), 7);
+
+        let output = recorder.inner.render();
+        assert!(output.contains("requests_total 5\n"));
+        assert!(output.contains("connections_total 7\n"));
+    }
+
+    #[test]
+    fn test_recency() {
+        let mut idle_timeouts = IdleTimeouts::default();
+        idle_timeouts.set(MetricKind::Counter, Some(Duration::from_secs(10)));
+        let mut recency = Recency::new(idle_timeouts);
+        let registry = PrometheusRegistry::new();
+
+        let counter = CompositeKey::new(MetricKind::Counter, Key::Owned("requests".into()));
+        let gauge = CompositeKey::new(MetricKind::Gauge, Key::Owned("connections".into()));
+        let increment = || {
+            registry.op(
+                counter.clone(),
+                |h| CounterFn::increment(h, 1),
+                Handle::counter,
+            )
+        };
+        let generation = |key| registry.get_handles()[key].get_generation();
+        increment();
+        registry.op(gauge.clone(), |h| GaugeFn::update(h, 1.0), Handle::gauge);
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(!recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(0)
+        ));
+
+        // Updating a metric restarts its timeout.
+        increment();
+        assert!(!recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(5)
+        ));
+        assert!(!recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(14)
+        ));
+
+        assert!(recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(24)
+        ));
+
+        // Idle metrics stay idle until they're forgotten about, and start over if they're seen
+        // again after that.
+        assert!(recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(25)
+        ));
+        recency.forget(MetricKind::Counter, counter.key());
+        assert!(!recency.is_idle(
+            MetricKind::Counter,
+            counter.key(),
+            generation(&counter),
+            at(26)
+        ));
+
+        // Gauges have no timeout.
+        assert!(!recency.is_idle(MetricKind::Gauge, gauge.key(), generation(&gauge), at(1000)));
+        assert!(!recency.is_idle(MetricKind::Gauge, gauge.key(), generation(&gauge), at(2000)));
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let recorder = build_recorder(
+            PrometheusBuilder::new()
+                .set_buckets(&[10.0])
+                .idle_timeout(MetricKind::Counter, Some(Duration::from_millis(1)))
+                .idle_timeout(MetricKind::Histogram, Some(Duration::from_millis(1))),
+        );
+
+        recorder.increment_counter(Key::Owned("requests".into()), 1);
+        recorder.update_gauge(Key::Owned("connections".into()), 1.0);
+        recorder.record_histogram(Key::Owned("latency".into()), 5.0);
+
+        let output = recorder.inner.render();
+        assert!(output.contains("requests_total 1\n"));
+        assert!(output.contains("connections 1\n"));
+        assert!(output.contains("latency_count 1\n"));
+
+        thread::sleep(Duration::from_millis(5));
+        let output = recorder.inner.render();
+        assert!(!output.contains("requests"));
+        assert!(output.contains("connections 1\n"));
+        assert!(!output.contains("latency"));
+
+        // Metrics updated after being removed start over.
+        recorder.increment_counter(Key::Owned("requests".into()), 2);
+        let output = recorder.inner.render();
+        assert!(output.contains("requests_total 2\n"));
+    }
+
+    #[test]
+    fn test_idle_timeout_held_handle() {
+        let recorder = build_recorder(
+            PrometheusBuilder::new()
+                .idle_timeout(MetricKind::Counter, Some(Duration::from_millis(1))),
+        );
+
+        let counter = recorder.register_counter(Key::Owned("requests".into()), None, None);
+        counter.increment(1);
+        assert!(recorder.inner.render().contains("requests_total 1\n"));
+
+        // A held handle keeps its metric around, even once it has gone idle, so later updates
+        // through the handle still show up.
+        thread::sleep(Duration::from_millis(5));
+        assert!(recorder.inner.render().contains("requests_total 1\n"));
+        thread::sleep(Duration::from_millis(5));
+        assert!(recorder.inner.render().contains("requests_total 1\n"));
+        counter.increment(2);
+        assert!(recorder.inner.render().contains("requests_total 3\n"));
+
+        // Once the handle is dropped, the metric can go.
+        drop(counter);
+        thread::sleep(Duration::from_millis(5));
+        recorder.inner.render();
+        thread::sleep(Duration::from_millis(5));
+        assert!(!recorder.inner.render().contains("requests"));
+    }
+
+    #[test]
+    fn test_idle_removal_releases_series() {
+        let limit = CardinalityLimitLayer::new(1);
+        let releaser = limit.releaser();
+        let recorder = build_recorder(
+            PrometheusBuilder::new()
+                .idle_timeout(MetricKind::Counter, Some(Duration::from_millis(1)))
+                .on_idle_removal(move |_, key| {
+                    releaser.release(key);
+                }),
+        );
+        let inner = recorder.inner.clone();
+        let recorder = limit.layer(recorder);
+        let conn_key =
+            |id: &str| Key::Owned(("bytes", vec![Label::new("conn", id.to_string())]).into());
+
+        recorder.increment_counter(conn_key("a"), 1);
+        recorder.increment_counter(conn_key("b"), 1);
+        let rendered = inner.render();
+        assert!(rendered.contains("bytes_total{conn=\"a\"} 1\n"));
+        assert!(!rendered.contains("conn=\"b\""));
+
+        // Once the first series is removed for being idle, its slot goes to the next one.
+        thread::sleep(Duration::from_millis(5));
+        inner.render();
+        thread::sleep(Duration::from_millis(5));
+        assert!(!inner.render().contains("bytes_total"));
+        recorder.increment_counter(conn_key("b"), 1);
+        assert!(inner.render().contains("bytes_total{conn=\"b\"} 1\n"));
+    }
+
+    #[test]
+    fn test_idle_timeout_held_histogram() {
+        let recorder = build_recorder(
+            PrometheusBuilder::new()
+                .set_buckets(&[10.0])
+                .idle_timeout(MetricKind::Histogram, Some(Duration::from_millis(1))),
+        );
+
+        let histogram = recorder.register_histogram(Key::Owned("latency".into()), None, None);
+        histogram.record(5.0);
+        assert!(recorder.inner.render().contains("latency_count 1\n"));
+
+        // Samples of a held histogram which has gone idle are kept, since the histogram is too.
+        thread::sleep(Duration::from_millis(5));
+        assert!(recorder.inner.render().contains("latency_count 1\n"));
+        histogram.record(5.0);
+        assert!(recorder.inner.render().contains("latency_count 2\n"));
+    }
+
+    #[test]
+    fn test_units() {
+        // Buckets are given in the declared unit of the histogram.
+        let recorder = build_recorder(
+            PrometheusBuilder::new().set_buckets_for_metric("latency", &[300_000_000.0]),
+        );
+
+        recorder.describe_histogram("http.latency".into(), Some(Unit::Nanoseconds), "".into());
+        recorder.record_histogram(
+            Key::Owned("http.latency".into()),
+            Duration::from_millis(250).into_f64(),
+        );
+        recorder.record_histogram(
+            Key::Owned("http.latency".into()),
+            Duration::from_millis(500).into_f64(),
+        );
+        recorder.register_gauge(Key::Owned("cache.size".into()), Some(Unit::Kilobytes), None);
+        recorder.update_gauge(Key::Owned("cache.size".into()), 1.5);
+        recorder.register_counter(Key::Owned("sent_bytes".into()), Some(Unit::Bytes), None);
+        recorder.increment_counter(Key::Owned("sent_bytes".into()), 42);
+        recorder.register_gauge(Key::Owned("totalbytes".into()), Some(Unit::Bytes), None);
+
+        let output = recorder.inner.render();
+        assert!(output.contains("# TYPE http_latency_seconds histogram\n"));
+        assert!(output.contains("http_latency_seconds_bucket{le=\"0.3\"} 1\n"));
+        assert!(output.contains("http_latency_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(output.contains("http_latency_seconds_sum 0.75\n"));
+        assert!(output.contains("# TYPE cache_size_bytes gauge\ncache_size_bytes 1536\n"));
+        assert!(output.contains("# TYPE sent_bytes_total counter\nsent_bytes_total 42\n"));
+        assert!(output.contains("# TYPE totalbytes_bytes gauge\n"));
+    }
+}
@@ -56,7 +56,10 @@ use std::time::SystemTime;
 
 use bytes::Bytes;
 use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
-use metrics::{Key, Recorder, SetRecorderError, Unit};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, Recorder, SetRecorderError,
+    SharedString, Unit,
+};
 use mio::{
     net::{TcpListener, TcpStream},
     Events, Interest, Poll, Token, Waker,
@@ -77,12 +80,16 @@ use self::proto::metadata::MetricType;
 
 enum MetricValue {
     Counter(u64),
+    AbsoluteCounter(u64),
     Gauge(f64),
-    Histogram(u64),
+    GaugeDelta(f64),
+    Histogram(f64),
 }
 
+type Metadata = HashMap<(MetricType, SharedString), (Option<Unit>, Option<SharedString>)>;
+
 enum Event {
-    Metadata(Key, MetricType, Option<Unit>, Option<&'static str>),
+    Metadata(SharedString, MetricType, Option<Unit>, Option<SharedString>),
     Metric(Key, MetricValue),
 }
 
@@ -159,12 +166,66 @@ pub struct TcpRecorder {
     state: State,
 }
 
+/// A handle to a metric registered with a [`TcpRecorder`].
+///
+/// Updates are pushed to the transport exactly like updates made through the recorder.
+struct TcpHandle {
+    key: Key,
+    tx: Sender<Event>,
+    state: State,
+}
+
+impl TcpHandle {
+    fn push_metric(&self, value: MetricValue) {
+        if self.state.should_send() {
+            let _ = self.tx.try_send(Event::Metric(self.key.clone(), value));
+            self.state.wake();
+        }
+    }
+}
+
+impl CounterFn for TcpHandle {
+    fn increment(&self, value: u64) {
+        self.push_metric(MetricValue::Counter(value));
+    }
+
+    fn absolute(&self, value: u64) {
+        self.push_metric(MetricValue::AbsoluteCounter(value));
+    }
+}
+
+impl GaugeFn for TcpHandle {
+    fn update(&self, value: f64) {
+        self.push_metric(MetricValue::Gauge(value));
+    }
+
+    fn increment(&self, value: f64) {
+        self.push_metric(MetricValue::GaugeDelta(value));
+    }
+
+    fn decrement(&self, value: f64) {
+        self.push_metric(MetricValue::GaugeDelta(-value));
+    }
+}
+
+impl HistogramFn for TcpHandle {
+    fn record(&self, value: f64) {
+        self.push_metric(MetricValue::Histogram(value));
+    }
+}
+
 /// Builder for creating and installing a TCP recorder/exporter.
 pub struct TcpBuilder {
     listen_addr: SocketAddr,
     buffer_size: Option<usize>,
 }
 
+impl Default for TcpBuilder {
+    fn default() -> Self {
+        TcpBuilder::new()
+    }
+}
+
 impl TcpBuilder {
     /// Creates a new `TcpBuilder`.
     pub fn new() -> TcpBuilder {
@@ -253,10 +314,27 @@ impl TcpRecorder {
         metric_type: MetricType,
         unit: Option<Unit>,
         description: Option<&'static str>,
+    ) -> Arc<TcpHandle> {
+        let description = description.map(SharedString::from);
+        self.push_metadata(key.name().clone(), metric_type, unit, description);
+
+        Arc::new(TcpHandle {
+            key,
+            tx: self.tx.clone(),
+            state: self.state.clone(),
+        })
+    }
+
+    fn push_metadata(
+        &self,
+        name: SharedString,
+        metric_type: MetricType,
+        unit: Option<Unit>,
+        description: Option<SharedString>,
     ) {
         let _ = self
             .tx
-            .try_send(Event::Metadata(key, metric_type, unit, description));
+            .try_send(Event::Metadata(name, metric_type, unit, description));
         self.state.wake();
     }
 
@@ -269,27 +347,71 @@ impl TcpRecorder {
 }
 
 impl Recorder for TcpRecorder {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.register_metric(key, MetricType::Counter, unit, description);
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        Counter::from_arc(self.register_metric(key, MetricType::Counter, unit, description))
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.register_metric(key, MetricType::Gauge, unit, description);
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        Gauge::from_arc(self.register_metric(key, MetricType::Gauge, unit, description))
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.register_metric(key, MetricType::Histogram, unit, description);
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        Histogram::from_arc(self.register_metric(key, MetricType::Histogram, unit, description))
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.push_metadata(name, MetricType::Counter, unit, Some(description));
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.push_metadata(name, MetricType::Gauge, unit, Some(description));
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.push_metadata(name, MetricType::Histogram, unit, Some(description));
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
         self.push_metric(key, MetricValue::Counter(value));
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        self.push_metric(key, MetricValue::AbsoluteCounter(value));
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
         self.push_metric(key, MetricValue::Gauge(value));
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        self.push_metric(key, MetricValue::GaugeDelta(value));
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        self.push_metric(key, MetricValue::GaugeDelta(-value));
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         self.push_metric(key, MetricValue::Histogram(value));
     }
 }
@@ -301,11 +423,11 @@ fn run_transport(
     state: State,
     buffer_size: Option<usize>,
 ) {
-    let buffer_limit = buffer_size.unwrap_or(std::usize::MAX);
+    let buffer_limit = buffer_size.unwrap_or(usize::MAX);
     let mut events = Events::with_capacity(1024);
     let mut clients = HashMap::new();
     let mut clients_to_remove = Vec::new();
-    let mut metadata = HashMap::new();
+    let mut metadata = Metadata::new();
     let mut next_token = START_TOKEN;
     let mut buffered_pmsgs = VecDeque::with_capacity(buffer_limit);
 
@@ -350,13 +472,32 @@ fn run_transport(
                         };
 
                         match msg {
-                            Event::Metadata(key, metric_type, unit, desc) => {
+                            Event::Metadata(name, metric_type, unit, desc) => {
+                                // Registrations may not carry a unit or description, so only
+                                // overwrite what we already know about when there's a new value.
                                 let entry = metadata
-                                    .entry(key)
-                                    .or_insert_with(|| (metric_type, None, None));
-                                let (_, uentry, dentry) = entry;
-                                *uentry = unit;
-                                *dentry = desc;
+                                    .entry((metric_type, name.clone()))
+                                    .or_insert((None, None));
+                                let (uentry, dentry) = entry;
+                                if unit.is_some() {
+                                    *uentry = unit;
+                                }
+                                if desc.is_some() {
+                                    *dentry = desc;
+                                }
+
+                                // Clients only get sent the full set of metadata when they
+                                // connect, so forward any changes to those already connected.
+                                let (unit, desc) = entry.clone();
+                                match convert_metadata_to_protobuf_encoded(
+                                    &name,
+                                    metric_type,
+                                    unit,
+                                    desc,
+                                ) {
+                                    Ok(pmsg) => buffered_pmsgs.push_back(pmsg),
+                                    Err(e) => error!(error = ?e, "error encoding metadata"),
+                                }
                             }
                             Event::Metric(key, value) => {
                                 match convert_metric_to_protobuf_encoded(key, value) {
@@ -467,18 +608,12 @@ fn run_transport(
     }
 }
 
-fn generate_metadata_messages(
-    metadata: &HashMap<Key, (MetricType, Option<Unit>, Option<&'static str>)>,
-) -> VecDeque<Bytes> {
+fn generate_metadata_messages(metadata: &Metadata) -> VecDeque<Bytes> {
     let mut bufs = VecDeque::new();
-    for (key, (metric_type, unit, desc)) in metadata.iter() {
-        let msg = convert_metadata_to_protobuf_encoded(
-            key,
-            metric_type.clone(),
-            unit.clone(),
-            desc.clone(),
-        )
-        .expect("failed to encode metadata buffer");
+    for ((metric_type, name), (unit, desc)) in metadata.iter() {
+        let msg =
+            convert_metadata_to_protobuf_encoded(name, *metric_type, unit.clone(), desc.clone())
+                .expect("failed to encode metadata buffer");
         bufs.push_back(msg);
     }
     bufs
@@ -536,17 +671,16 @@ fn drive_connection(
 }
 
 fn convert_metadata_to_protobuf_encoded(
-    key: &Key,
+    name: &str,
     metric_type: MetricType,
     unit: Option<Unit>,
-    desc: Option<&'static str>,
+    desc: Option<SharedString>,
 ) -> Result<Bytes, EncodeError> {
-    let name = key.name().to_string();
     let metadata = proto::Metadata {
-        name,
+        name: name.to_owned(),
         metric_type: metric_type.into(),
         unit: unit.map(|u| proto::metadata::Unit::UnitValue(u.as_str().to_owned())),
-        description: desc.map(|d| proto::metadata::Description::DescriptionValue(d.to_owned())),
+        description: desc.map(|d| proto::metadata::Description::DescriptionValue(d.into_owned())),
     };
     let event = proto::Event {
         event: Some(proto::event::Event::Metadata(metadata)),
@@ -565,7 +699,13 @@ fn convert_metric_to_protobuf_encoded(key: Key, value: MetricValue) -> Result<By
         .collect::<BTreeMap<_, _>>();
     let mvalue = match value {
         MetricValue::Counter(cv) => proto::metric::Value::Counter(proto::Counter { value: cv }),
+        MetricValue::AbsoluteCounter(cv) => {
+            proto::metric::Value::AbsoluteCounter(proto::AbsoluteCounter { value: cv })
+        }
         MetricValue::Gauge(gv) => proto::metric::Value::Gauge(proto::Gauge { value: gv }),
+        MetricValue::GaugeDelta(gv) => {
+            proto::metric::Value::GaugeDelta(proto::GaugeDelta { value: gv })
+        }
         MetricValue::Histogram(hv) => {
             proto::metric::Value::Histogram(proto::Histogram { value: hv })
         }
@@ -8,34 +8,51 @@ use quote::{format_ident, quote, ToTokens};
 use regex::Regex;
 use syn::parse::discouraged::Speculative;
 use syn::parse::{Error, Parse, ParseStream, Result};
-use syn::{parse_macro_input, Expr, LitStr, Token};
+use syn::punctuated::Punctuated;
+use syn::visit_mut::{self, VisitMut};
+use syn::{
+    parse_macro_input, parse_quote, Expr, ExprGroup, ExprLit, ExprMacro, ExprParen, ExprPath,
+    Ident, Item, ItemFn, Lit, LitStr, ReturnType, Token, Type,
+};
 
 #[cfg(test)]
 mod tests;
 
+#[derive(Clone)]
 enum Labels {
-    Existing(Expr),
+    Existing(Box<Expr>),
     Inline(Vec<(LitStr, Expr)>),
 }
 
 struct WithoutExpression {
-    key: LitStr,
+    key: Expr,
     labels: Option<Labels>,
 }
 
 struct WithExpression {
-    key: LitStr,
+    key: Expr,
     op_value: Expr,
     labels: Option<Labels>,
 }
 
+struct Description {
+    key: Expr,
+    unit: Option<Expr>,
+    description: Expr,
+}
+
 struct Registration {
-    key: LitStr,
+    key: Expr,
     unit: Option<Expr>,
     description: Option<LitStr>,
     labels: Option<Labels>,
 }
 
+struct Instrumentation {
+    key: Option<Expr>,
+    result_labels: bool,
+}
+
 impl Parse for WithoutExpression {
     fn parse(mut input: ParseStream) -> Result<Self> {
         let key = read_key(&mut input)?;
@@ -62,6 +79,34 @@ impl Parse for WithExpression {
     }
 }
 
+impl Parse for Description {
+    fn parse(mut input: ParseStream) -> Result<Self> {
+        let key = read_key(&mut input)?;
+
+        // We accept either a description, or a unit followed by a description.  Both are
+        // arbitrary expressions, so the only way to tell them apart is how many there are.
+        input.parse::<Token![,]>()?;
+        let span = input.span();
+        let mut args = Punctuated::<Expr, Token![,]>::parse_terminated(input)?.into_iter();
+        let (unit, description) = match (args.next(), args.next(), args.next()) {
+            (Some(description), None, None) => (None, description),
+            (Some(unit), Some(description), None) => (Some(unit), description),
+            _ => {
+                return Err(Error::new(
+                    span,
+                    "expected a description, or a unit followed by a description",
+                ))
+            }
+        };
+
+        Ok(Description {
+            key,
+            unit,
+            description,
+        })
+    }
+}
+
 impl Parse for Registration {
     fn parse(mut input: ParseStream) -> Result<Self> {
         let key = read_key(&mut input)?;
@@ -144,6 +189,35 @@ impl Parse for Registration {
     }
 }
 
+impl Parse for Instrumentation {
+    fn parse(mut input: ParseStream) -> Result<Self> {
+        let mut key = None;
+        let mut result_labels = false;
+
+        // Arguments are all optional, and can be given in any order.
+        while !input.is_empty() {
+            let arg = input.parse::<Ident>()?;
+            if arg == "name" {
+                input.parse::<Token![=]>()?;
+                key = Some(read_key(&mut input)?);
+            } else if arg == "result" {
+                result_labels = true;
+            } else {
+                return Err(Error::new(
+                    arg.span(),
+                    "expected `name = \"...\"` or `result`",
+                ));
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(Instrumentation { key, result_labels })
+    }
+}
+
 #[proc_macro_hack]
 pub fn register_counter(input: TokenStream) -> TokenStream {
     let Registration {
@@ -153,7 +227,10 @@ pub fn register_counter(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as Registration);
 
-    get_expanded_registration("counter", key, unit, description, labels).into()
+    let catalog_entry = get_expanded_catalog_entry("counter", &key, &labels, &unit, &description);
+    let registration = get_expanded_registration("counter", key, unit, description, labels);
+
+    quote! { { #catalog_entry #registration } }.into()
 }
 
 #[proc_macro_hack]
@@ -165,7 +242,10 @@ pub fn register_gauge(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as Registration);
 
-    get_expanded_registration("gauge", key, unit, description, labels).into()
+    let catalog_entry = get_expanded_catalog_entry("gauge", &key, &labels, &unit, &description);
+    let registration = get_expanded_registration("gauge", key, unit, description, labels);
+
+    quote! { { #catalog_entry #registration } }.into()
 }
 
 #[proc_macro_hack]
@@ -177,7 +257,43 @@ pub fn register_histogram(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as Registration);
 
-    get_expanded_registration("histogram", key, unit, description, labels).into()
+    let catalog_entry = get_expanded_catalog_entry("histogram", &key, &labels, &unit, &description);
+    let registration = get_expanded_registration("histogram", key, unit, description, labels);
+
+    quote! { { #catalog_entry #registration } }.into()
+}
+
+#[proc_macro_hack]
+pub fn describe_counter(input: TokenStream) -> TokenStream {
+    let Description {
+        key,
+        unit,
+        description,
+    } = parse_macro_input!(input as Description);
+
+    get_expanded_description("counter", key, unit, description).into()
+}
+
+#[proc_macro_hack]
+pub fn describe_gauge(input: TokenStream) -> TokenStream {
+    let Description {
+        key,
+        unit,
+        description,
+    } = parse_macro_input!(input as Description);
+
+    get_expanded_description("gauge", key, unit, description).into()
+}
+
+#[proc_macro_hack]
+pub fn describe_histogram(input: TokenStream) -> TokenStream {
+    let Description {
+        key,
+        unit,
+        description,
+    } = parse_macro_input!(input as Description);
+
+    get_expanded_description("histogram", key, unit, description).into()
 }
 
 #[proc_macro_hack]
@@ -186,7 +302,10 @@ pub fn increment(input: TokenStream) -> TokenStream {
 
     let op_value = quote! { 1 };
 
-    get_expanded_callsite("counter", "increment", key, labels, op_value).into()
+    let catalog_entry = get_expanded_catalog_entry("counter", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("counter", "increment", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
 }
 
 #[proc_macro_hack]
@@ -197,7 +316,24 @@ pub fn counter(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as WithExpression);
 
-    get_expanded_callsite("counter", "increment", key, labels, op_value).into()
+    let catalog_entry = get_expanded_catalog_entry("counter", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("counter", "increment", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
+}
+
+#[proc_macro_hack]
+pub fn absolute_counter(input: TokenStream) -> TokenStream {
+    let WithExpression {
+        key,
+        op_value,
+        labels,
+    } = parse_macro_input!(input as WithExpression);
+
+    let catalog_entry = get_expanded_catalog_entry("counter", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("counter", "absolute", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
 }
 
 #[proc_macro_hack]
@@ -208,7 +344,38 @@ pub fn gauge(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as WithExpression);
 
-    get_expanded_callsite("gauge", "update", key, labels, op_value).into()
+    let catalog_entry = get_expanded_catalog_entry("gauge", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("gauge", "update", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
+}
+
+#[proc_macro_hack]
+pub fn increment_gauge(input: TokenStream) -> TokenStream {
+    let WithExpression {
+        key,
+        op_value,
+        labels,
+    } = parse_macro_input!(input as WithExpression);
+
+    let catalog_entry = get_expanded_catalog_entry("gauge", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("gauge", "increment", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
+}
+
+#[proc_macro_hack]
+pub fn decrement_gauge(input: TokenStream) -> TokenStream {
+    let WithExpression {
+        key,
+        op_value,
+        labels,
+    } = parse_macro_input!(input as WithExpression);
+
+    let catalog_entry = get_expanded_catalog_entry("gauge", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("gauge", "decrement", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
 }
 
 #[proc_macro_hack]
@@ -219,18 +386,38 @@ pub fn histogram(input: TokenStream) -> TokenStream {
         labels,
     } = parse_macro_input!(input as WithExpression);
 
-    get_expanded_callsite("histogram", "record", key, labels, op_value).into()
+    let catalog_entry = get_expanded_catalog_entry("histogram", &key, &labels, &None, &None);
+    let callsite = get_expanded_callsite("histogram", "record", key, labels, op_value);
+
+    quote! { { #catalog_entry #callsite } }.into()
+}
+
+#[proc_macro_attribute]
+pub fn timed(args: TokenStream, item: TokenStream) -> TokenStream {
+    let instrumentation = parse_macro_input!(args as Instrumentation);
+    let item = parse_macro_input!(item as ItemFn);
+
+    get_expanded_instrumented("histogram", "record", instrumentation, item).into()
+}
+
+#[proc_macro_attribute]
+pub fn counted(args: TokenStream, item: TokenStream) -> TokenStream {
+    let instrumentation = parse_macro_input!(args as Instrumentation);
+    let item = parse_macro_input!(item as ItemFn);
+
+    get_expanded_instrumented("counter", "increment", instrumentation, item).into()
 }
 
 fn get_expanded_registration(
     metric_type: &str,
-    key: LitStr,
+    key: Expr,
     unit: Option<Expr>,
     description: Option<LitStr>,
     labels: Option<Labels>,
 ) -> proc_macro2::TokenStream {
     let register_ident = format_ident!("register_{}", metric_type);
-    let key = key_to_quoted(key, labels);
+    let handle_ident = format_ident!("{}", handle_type(metric_type));
+    let key = key_to_quoted(const_name_to_owned(key), labels);
 
     let unit = match unit {
         Some(e) => quote! { Some(#e) },
@@ -245,11 +432,40 @@ fn get_expanded_registration(
     quote! {
         {
             // Only do this work if there's a recorder installed.
-            if let Some(recorder) = metrics::try_recorder() {
+            metrics::with_recorder(|recorder| {
                 // Registrations are fairly rare, don't attempt to cache here
                 // and just use an owned ref.
-                recorder.#register_ident(metrics::Key::Owned(#key), #unit, #description);
-            }
+                recorder.#register_ident(metrics::Key::Owned(#key), #unit, #description)
+            })
+            .unwrap_or_else(metrics::#handle_ident::noop)
+        }
+    }
+}
+
+fn get_expanded_description(
+    metric_type: &str,
+    key: Expr,
+    unit: Option<Expr>,
+    description: Expr,
+) -> proc_macro2::TokenStream {
+    let describe_ident = format_ident!("describe_{}", metric_type);
+    let key = const_name_to_owned(key);
+
+    let unit = match unit {
+        Some(e) => quote! { Some(#e) },
+        None => quote! { None },
+    };
+
+    quote! {
+        {
+            // Only do this work if there's a recorder installed.
+            metrics::with_recorder(|recorder| {
+                recorder.#describe_ident(
+                    metrics::SharedString::from(#key),
+                    #unit,
+                    metrics::SharedString::from(#description)
+                );
+            });
         }
     }
 }
@@ -257,24 +473,24 @@ fn get_expanded_registration(
 fn get_expanded_callsite<V>(
     metric_type: &str,
     op_type: &str,
-    key: LitStr,
+    key: Expr,
     labels: Option<Labels>,
     op_values: V,
 ) -> proc_macro2::TokenStream
 where
     V: ToTokens,
 {
-    // We use a helper method for histogram values to coerce into u64, but otherwise,
+    // We use a helper method for histogram values to coerce into f64, but otherwise,
     // just pass through whatever the caller gave us.
     let op_values = if metric_type == "histogram" {
-        quote! { metrics::__into_u64(#op_values) }
+        quote! { metrics::__into_f64(#op_values) }
     } else {
         quote! { #op_values }
     };
 
     let op_ident = format_ident!("{}_{}", op_type, metric_type);
 
-    let use_fast_path = can_use_fast_path(&labels);
+    let use_fast_path = can_use_fast_path(&key, &labels);
     if use_fast_path {
         // We're on the fast path here, so we'll build our key, statically cache it,
         // and use a borrowed reference to it for this and future operations.
@@ -307,9 +523,70 @@ where
                 #statics
 
                 // Only do this work if there's a recorder installed.
-                if let Some(recorder) = metrics::try_recorder() {
+                metrics::with_recorder(|recorder| {
                     recorder.#op_ident(metrics::Key::Borrowed(&METRIC_KEY), #op_values);
-                }
+                });
+            }
+        }
+    } else if can_use_cached_path(&key, &labels) {
+        // We're on the cached path here, where the name and label keys are static but the label
+        // values aren't, so we statically cache a key per set of label values instead, and only
+        // borrow the label values to look it up.
+        let (label_keys, label_values): (Vec<_>, Vec<_>) = match labels {
+            Some(Labels::Inline(pairs)) => pairs.into_iter().unzip(),
+            _ => unreachable!("use_cached_path == true, but found no inline labels"),
+        };
+
+        quote! {
+            {
+                static METRIC_KEYS: metrics::KeyCache =
+                    metrics::KeyCache::new(#key, &[#(#label_keys),*]);
+
+                // Only do this work if there's a recorder installed.
+                metrics::with_recorder(|recorder| {
+                    recorder.#op_ident(
+                        METRIC_KEYS.get(&[#(metrics::__label_value(&#label_values)),*]),
+                        #op_values
+                    );
+                });
+            }
+        }
+    } else if can_use_const_path(&key, &labels) {
+        // We're on the const path here, where the name looks like a constant, but we can't tell
+        // whether it's a `&str` until type checking, nor read it in a static on every supported
+        // version of Rust.  The type picks the branch: a `&str` gets a key which is built on first
+        // use and then cached by the call site for as long as the name stays the same, while
+        // anything else takes the slow path.
+        let (labels_static, labels_ref) = match &labels {
+            Some(Labels::Inline(pairs)) => {
+                let labels = pairs
+                    .iter()
+                    .map(|(key, val)| quote! { metrics::Label::from_static_parts(#key, #val) })
+                    .collect::<Vec<_>>();
+                let labels_len = labels.len();
+                (
+                    quote! { static METRIC_LABELS: [metrics::Label; #labels_len] = [#(#labels),*]; },
+                    quote! { &METRIC_LABELS },
+                )
+            }
+            _ => (quote! {}, quote! { &[] }),
+        };
+        let owned_key = key_to_quoted(parse_quote! { name }, labels);
+
+        quote! {
+            {
+                use metrics::{__OtherName as _, __StrName as _};
+                #labels_static
+                static METRIC_KEY: metrics::StaticKey = metrics::StaticKey::new();
+
+                // Only do this work if there's a recorder installed.
+                metrics::with_recorder(|recorder| {
+                    let key = match (&metrics::__ConstName(&#key)).__name() {
+                        metrics::__Name::Str(name) => METRIC_KEY.get(name, #labels_ref),
+                        metrics::__Name::Owned(name) => metrics::Key::Owned(#owned_key),
+                    };
+                    recorder.#op_ident(key, #op_values);
+                });
             }
         }
     } else {
@@ -318,15 +595,276 @@ where
         quote! {
             {
                 // Only do this work if there's a recorder installed.
-                if let Some(recorder) = metrics::try_recorder() {
+                metrics::with_recorder(|recorder| {
                     recorder.#op_ident(metrics::Key::Owned(#key), #op_values);
+                });
+            }
+        }
+    }
+}
+
+fn get_expanded_catalog_entry(
+    metric_type: &str,
+    key: &Expr,
+    labels: &Option<Labels>,
+    unit: &Option<Expr>,
+    description: &Option<LitStr>,
+) -> proc_macro2::TokenStream {
+    // Call sites are registered in a static, so names only known at runtime can't be listed.
+    if !is_static_name(key) {
+        return quote! {};
+    }
+
+    let kind_ident = format_ident!("{}", handle_type(metric_type));
+
+    // Only inline labels have keys we can know ahead of time.
+    let label_keys = match labels {
+        Some(Labels::Inline(pairs)) => pairs.iter().map(|(key, _)| key).collect(),
+        _ => Vec::new(),
+    };
+
+    // Units can be any expression, but only a literal variant, such as `Unit::Bytes`, is known to
+    // be usable in a static.
+    let unit = match unit {
+        Some(e) if is_unit_variant(e) => quote! { Some(#e) },
+        _ => quote! { None },
+    };
+
+    let description = match description {
+        Some(s) => quote! { Some(#s) },
+        None => quote! { None },
+    };
+
+    quote! {
+        metrics::__catalog_callsite!(metrics::Callsite::new(
+            #key,
+            metrics::MetricKind::#kind_ident,
+            &[#(#label_keys),*],
+            #unit,
+            #description,
+            module_path!(),
+            file!(),
+            line!()
+        ));
+    }
+}
+
+fn is_unit_variant(unit: &Expr) -> bool {
+    match unit {
+        Expr::Path(path) if path.qself.is_none() => {
+            let segments = &path.path.segments;
+            segments.len() >= 2 && segments[segments.len() - 2].ident == "Unit"
+        }
+        _ => false,
+    }
+}
+
+fn get_expanded_instrumented(
+    metric_type: &str,
+    op_type: &str,
+    instrumentation: Instrumentation,
+    item: ItemFn,
+) -> proc_macro2::TokenStream {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = item;
+
+    // Metrics are named after the path of the function unless a name is given.  We can't know the
+    // type that a method belongs to, so this is the module path followed by the function name, with
+    // a suffix per kind of metric so that a function can be both timed and counted.
+    let key = match instrumentation.key {
+        Some(key) => key,
+        None => {
+            let suffix = if metric_type == "histogram" {
+                "duration"
+            } else {
+                "calls"
+            };
+            let name = format!("{}_{}", sig.ident, suffix);
+            parse_quote! { concat!(module_path!(), "::", #name) }
+        }
+    };
+
+    // Timings are measured from entry until the function returns, while counts are simply one per
+    // call that returns.  Timings are in nanoseconds, which we let the recorder know about the
+    // first time each call site records, so that exporters can convert them.
+    let is_timed = metric_type == "histogram";
+    let (start, op_value) = if is_timed {
+        (
+            quote! { let __metrics_start = std::time::Instant::now(); },
+            quote! { __metrics_start.elapsed() },
+        )
+    } else {
+        (quote! {}, quote! { 1 })
+    };
+    let record = |labels: Option<Labels>| {
+        let callsite =
+            get_expanded_callsite(metric_type, op_type, key.clone(), labels.clone(), &op_value);
+        if !is_timed {
+            return callsite;
+        }
+
+        let registration = get_expanded_registration(
+            metric_type,
+            key.clone(),
+            Some(parse_quote! { metrics::Unit::Nanoseconds }),
+            None,
+            labels,
+        );
+        quote! {
+            {
+                static METRIC_UNIT: std::sync::Once = std::sync::Once::new();
+                METRIC_UNIT.call_once(|| {
+                    #registration;
+                });
+                #callsite
+            }
+        }
+    };
+    let result_label = |result: &str| {
+        Some(Labels::Inline(vec![(
+            parse_quote! { "result" },
+            parse_quote! { #result },
+        )]))
+    };
+
+    let catalog_labels = if instrumentation.result_labels {
+        result_label("ok")
+    } else {
+        None
+    };
+    let catalog_entry =
+        get_expanded_catalog_entry(metric_type, &key, &catalog_labels, &None, &None);
+
+    // Annotating the result with the return type helps with inference for the `?` operator, but
+    // `impl Trait` can't be used in that position.
+    let result_type = match &sig.output {
+        ReturnType::Type(_, ty) if !matches!(**ty, Type::ImplTrait(_)) => quote! { : #ty },
+        _ => quote! {},
+    };
+
+    if sig.asyncness.is_some() {
+        // The original body is awaited in place, so that we get a hold of its result no matter
+        // how it returns, including early returns and the `?` operator.
+        let callsite = if instrumentation.result_labels {
+            let ok = record(result_label("ok"));
+            let error = record(result_label("error"));
+            quote! {
+                match &__metrics_result {
+                    Ok(_) => #ok,
+                    Err(_) => #error,
                 }
             }
+        } else {
+            record(None)
+        };
+
+        return quote! {
+            #(#attrs)*
+            #vis #sig {
+                #catalog_entry
+                #start
+                let __metrics_result #result_type = async move #block.await;
+                #callsite
+                __metrics_result
+            }
+        };
+    }
+
+    // The original body is kept in place, so that it can borrow from the arguments as usual, and
+    // we record from a guard once it returns, however it does so.  When the outcome is labeled,
+    // every returned value is checked on its way out.  The `?` operator is the only other way to
+    // return, which only ever returns an error, so that's the outcome until we know otherwise.
+    if instrumentation.result_labels {
+        let ok = record(result_label("ok"));
+        let error = record(result_label("error"));
+        // Functions can be both timed and counted, so each keeps track of the outcome separately.
+        let ok_ident = format_ident!("__metrics_{}_ok", metric_type);
+        let mut block = block;
+        ReturnOutcome {
+            result_type: &result_type,
+            ok_ident: &ok_ident,
+        }
+        .visit_block_mut(&mut block);
+
+        quote! {
+            #(#attrs)*
+            #vis #sig {
+                #catalog_entry
+                #start
+                let #ok_ident = std::cell::Cell::new(false);
+                let __metrics_guard = metrics::__OnReturn(|| {
+                    if #ok_ident.get() #ok else #error
+                });
+                let __metrics_result #result_type = #block;
+                #ok_ident.set(std::result::Result::is_ok(&__metrics_result));
+                __metrics_result
+            }
+        }
+    } else {
+        let callsite = record(None);
+
+        quote! {
+            #(#attrs)*
+            #vis #sig {
+                #catalog_entry
+                #start
+                let __metrics_guard = metrics::__OnReturn(|| #callsite);
+                #block
+            }
         }
     }
 }
 
-fn can_use_fast_path(labels: &Option<Labels>) -> bool {
+/// Records the outcome of every value returned with `return`, for instrumented functions labeled by
+/// their outcome.  Closures, async blocks and nested items have returns of their own, so they're
+/// left alone.
+struct ReturnOutcome<'a> {
+    result_type: &'a proc_macro2::TokenStream,
+    ok_ident: &'a Ident,
+}
+
+impl VisitMut for ReturnOutcome<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Closure(_) | Expr::Async(_) => {}
+            Expr::Return(ret) => {
+                if let Some(value) = ret.expr.as_mut() {
+                    self.visit_expr_mut(value);
+                    let (result_type, ok_ident) = (self.result_type, self.ok_ident);
+                    **value = parse_quote! {
+                        {
+                            let __metrics_result #result_type = #value;
+                            #ok_ident.set(std::result::Result::is_ok(&__metrics_result));
+                            __metrics_result
+                        }
+                    };
+                }
+            }
+            _ => visit_mut::visit_expr_mut(self, expr),
+        }
+    }
+
+    fn visit_item_mut(&mut self, _: &mut Item) {}
+}
+
+fn handle_type(metric_type: &str) -> String {
+    // Handle types are named after the metric type they represent, i.e. `counter` -> `Counter`.
+    let mut chars = metric_type.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn can_use_fast_path(key: &Expr, labels: &Option<Labels>) -> bool {
+    if !is_static_name(key) {
+        return false;
+    }
+
     match labels {
         None => true,
         Some(labels) => match labels {
@@ -336,24 +874,97 @@ fn can_use_fast_path(labels: &Option<Labels>) -> bool {
     }
 }
 
-fn read_key(input: &mut ParseStream) -> Result<LitStr> {
-    let key = input.parse::<LitStr>()?;
-    let inner = key.value();
+fn can_use_cached_path(key: &Expr, labels: &Option<Labels>) -> bool {
+    is_static_name(key) && matches!(labels, Some(Labels::Inline(pairs)) if !pairs.is_empty())
+}
 
-    lazy_static! {
-        static ref RE: Regex = Regex::new("^[a-zA-Z][a-zA-Z0-9_:\\.]*$").unwrap();
+fn can_use_const_path(key: &Expr, labels: &Option<Labels>) -> bool {
+    if !is_const_name(key) {
+        return false;
     }
-    if !RE.is_match(&inner) {
-        return Err(Error::new(
-            key.span(),
-            "metric name must match ^[a-zA-Z][a-zA-Z0-9_:.]*$",
-        ));
+
+    match labels {
+        None => true,
+        Some(Labels::Existing(_)) => false,
+        Some(Labels::Inline(pairs)) => pairs.iter().all(|(_, v)| matches!(v, Expr::Lit(_))),
+    }
+}
+
+fn is_static_name(key: &Expr) -> bool {
+    // We can't evaluate the name, so we go by what it looks like: string literals, and macros which
+    // expand to string literals, can be used to build a key in a static.
+    match key {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(_), ..
+        }) => true,
+        Expr::Macro(ExprMacro { mac, .. }) => {
+            mac.path.is_ident("concat") || mac.path.is_ident("stringify")
+        }
+        Expr::Group(ExprGroup { expr, .. }) | Expr::Paren(ExprParen { expr, .. }) => {
+            is_static_name(expr)
+        }
+        _ => false,
+    }
+}
+
+fn is_const_name(key: &Expr) -> bool {
+    // Paths to what are, by convention, constants or statics.  Locals and arguments can be named
+    // the same way, though, so the name of type `&str` may be borrowed from a local, and the key
+    // built from it is only reused while the name stays the same.
+    match key {
+        Expr::Path(ExprPath {
+            qself: None, path, ..
+        }) => {
+            let ident = match path.segments.last() {
+                Some(segment) => segment.ident.to_string(),
+                None => return false,
+            };
+            path.segments.iter().all(|s| s.arguments.is_empty())
+                && ident.chars().any(|c| c.is_ascii_uppercase())
+                && ident
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        }
+        Expr::Group(ExprGroup { expr, .. }) | Expr::Paren(ExprParen { expr, .. }) => {
+            is_const_name(expr)
+        }
+        _ => false,
+    }
+}
+
+fn read_key(input: &mut ParseStream) -> Result<Expr> {
+    let key = input.parse::<Expr>()?;
+
+    // Names given as literals can be checked right away, but anything else is only known at
+    // runtime, or at least after macro expansion.
+    if let Expr::Lit(ExprLit {
+        lit: Lit::Str(lit), ..
+    }) = &key
+    {
+        lazy_static! {
+            static ref RE: Regex = Regex::new("^[a-zA-Z][a-zA-Z0-9_:\\.]*$").unwrap();
+        }
+        if !RE.is_match(&lit.value()) {
+            return Err(Error::new(
+                lit.span(),
+                "metric name must match ^[a-zA-Z][a-zA-Z0-9_:.]*$",
+            ));
+        }
     }
 
     Ok(key)
 }
 
-fn key_to_quoted(name: LitStr, labels: Option<Labels>) -> proc_macro2::TokenStream {
+fn const_name_to_owned(name: Expr) -> Expr {
+    // A constant name may be a static of a type which can't be moved out of, so we clone it.
+    if is_const_name(&name) {
+        parse_quote! { metrics::__owned_name(&#name) }
+    } else {
+        name
+    }
+}
+
+fn key_to_quoted(name: Expr, labels: Option<Labels>) -> proc_macro2::TokenStream {
     match labels {
         None => quote! { metrics::KeyData::from_name(#name) },
         Some(labels) => match labels {
@@ -429,5 +1040,5 @@ fn parse_labels(input: &mut ParseStream) -> Result<Option<Labels>> {
         input.parse::<Token![,]>()?;
     }
 
-    Ok(Some(Labels::Existing(lvalue)))
+    Ok(Some(Labels::Existing(Box::new(lvalue))))
 }
@@ -10,13 +10,13 @@ fn test_get_expanded_registration() {
         get_expanded_registration("mytype", parse_quote! { "mykeyname" }, None, None, None);
 
     let expected = concat!(
-        "{ if let Some (recorder) = metrics :: try_recorder () { ",
+        "{ metrics :: with_recorder (| recorder | { ",
         "recorder . register_mytype (",
         "metrics :: Key :: Owned (metrics :: KeyData :: from_name (\"mykeyname\")) , ",
         "None , ",
         "None",
-        ") ; ",
-        "} }",
+        ") ",
+        "}) . unwrap_or_else (metrics :: Mytype :: noop) }",
     );
 
     assert_eq!(stream.to_string(), expected);
@@ -35,13 +35,13 @@ fn test_get_expanded_registration_with_unit() {
     );
 
     let expected = concat!(
-        "{ if let Some (recorder) = metrics :: try_recorder () { ",
+        "{ metrics :: with_recorder (| recorder | { ",
         "recorder . register_mytype (",
         "metrics :: Key :: Owned (metrics :: KeyData :: from_name (\"mykeyname\")) , ",
         "Some (metrics :: Unit :: Nanoseconds) , ",
         "None",
-        ") ; ",
-        "} }",
+        ") ",
+        "}) . unwrap_or_else (metrics :: Mytype :: noop) }",
     );
 
     assert_eq!(stream.to_string(), expected);
@@ -59,13 +59,13 @@ fn test_get_expanded_registration_with_description() {
     );
 
     let expected = concat!(
-        "{ if let Some (recorder) = metrics :: try_recorder () { ",
+        "{ metrics :: with_recorder (| recorder | { ",
         "recorder . register_mytype (",
         "metrics :: Key :: Owned (metrics :: KeyData :: from_name (\"mykeyname\")) , ",
         "None , ",
         "Some (\"flerkin\")",
-        ") ; ",
-        "} }",
+        ") ",
+        "}) . unwrap_or_else (metrics :: Mytype :: noop) }",
     );
 
     assert_eq!(stream.to_string(), expected);
@@ -84,13 +84,65 @@ fn test_get_expanded_registration_with_unit_and_description() {
     );
 
     let expected = concat!(
-        "{ if let Some (recorder) = metrics :: try_recorder () { ",
+        "{ metrics :: with_recorder (| recorder | { ",
         "recorder . register_mytype (",
         "metrics :: Key :: Owned (metrics :: KeyData :: from_name (\"mykeyname\")) , ",
         "Some (metrics :: Unit :: Nanoseconds) , ",
         "Some (\"flerkin\")",
+        ") ",
+        "}) . unwrap_or_else (metrics :: Mytype :: noop) }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_handle_type() {
+    assert_eq!(handle_type("counter"), "Counter");
+    assert_eq!(handle_type("gauge"), "Gauge");
+    assert_eq!(handle_type("histogram"), "Histogram");
+}
+
+#[test]
+fn test_get_expanded_description() {
+    let stream = get_expanded_description(
+        "mytype",
+        parse_quote! { "mykeyname" },
+        None,
+        parse_quote! { "flerkin" },
+    );
+
+    let expected = concat!(
+        "{ metrics :: with_recorder (| recorder | { ",
+        "recorder . describe_mytype (",
+        "metrics :: SharedString :: from (\"mykeyname\") , ",
+        "None , ",
+        "metrics :: SharedString :: from (\"flerkin\")",
+        ") ; ",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_get_expanded_description_with_unit() {
+    let units: ExprPath = parse_quote! { metrics::Unit::Nanoseconds };
+    let stream = get_expanded_description(
+        "mytype",
+        parse_quote! { "mykeyname" },
+        Some(Expr::Path(units)),
+        parse_quote! { format!("{} flerkins", 42) },
+    );
+
+    let expected = concat!(
+        "{ metrics :: with_recorder (| recorder | { ",
+        "recorder . describe_mytype (",
+        "metrics :: SharedString :: from (\"mykeyname\") , ",
+        "Some (metrics :: Unit :: Nanoseconds) , ",
+        "metrics :: SharedString :: from (format ! (\"{} flerkins\" , 42))",
         ") ; ",
-        "} }",
+        "}) ; }",
     );
 
     assert_eq!(stream.to_string(), expected);
@@ -109,9 +161,9 @@ fn test_get_expanded_callsite_fast_path_no_labels() {
     let expected = concat!(
         "{ ",
         "static METRIC_KEY : metrics :: KeyData = metrics :: KeyData :: from_static_name (\"mykeyname\") ; ",
-        "if let Some (recorder) = metrics :: try_recorder () { ",
+        "metrics :: with_recorder (| recorder | { ",
         "recorder . myop_mytype (metrics :: Key :: Borrowed (& METRIC_KEY) , 1) ; ",
-        "} }",
+        "}) ; }",
     );
 
     assert_eq!(stream.to_string(), expected);
@@ -132,9 +184,9 @@ fn test_get_expanded_callsite_fast_path_static_labels() {
         "{ ",
         "static METRIC_LABELS : [metrics :: Label ; 1usize] = [metrics :: Label :: from_static_parts (\"key1\" , \"value1\")] ; ",
         "static METRIC_KEY : metrics :: KeyData = metrics :: KeyData :: from_static_parts (\"mykeyname\" , & METRIC_LABELS) ; ",
-        "if let Some (recorder) = metrics :: try_recorder () { ",
+        "metrics :: with_recorder (| recorder | { ",
         "recorder . myop_mytype (metrics :: Key :: Borrowed (& METRIC_KEY) , 1) ; ",
-        "} ",
+        "}) ; ",
         "}",
     );
 
@@ -142,7 +194,7 @@ fn test_get_expanded_callsite_fast_path_static_labels() {
 }
 
 #[test]
-fn test_get_expanded_callsite_fast_path_dynamic_labels() {
+fn test_get_expanded_callsite_cached_path_dynamic_labels() {
     let labels = Labels::Inline(vec![(parse_quote! { "key1" }, parse_quote! { &value1 })]);
     let stream = get_expanded_callsite(
         "mytype",
@@ -154,11 +206,38 @@ fn test_get_expanded_callsite_fast_path_dynamic_labels() {
 
     let expected = concat!(
         "{ ",
-        "if let Some (recorder) = metrics :: try_recorder () { ",
+        "static METRIC_KEYS : metrics :: KeyCache = ",
+        "metrics :: KeyCache :: new (\"mykeyname\" , & [\"key1\"]) ; ",
+        "metrics :: with_recorder (| recorder | { ",
+        "recorder . myop_mytype (",
+        "METRIC_KEYS . get (& [metrics :: __label_value (& & value1)]) , ",
+        "1",
+        ") ; ",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+/// If the name is only known at runtime - generate a direct invocation, even with inline labels.
+#[test]
+fn test_get_expanded_callsite_regular_path_dynamic_name_and_labels() {
+    let labels = Labels::Inline(vec![(parse_quote! { "key1" }, parse_quote! { &value1 })]);
+    let stream = get_expanded_callsite(
+        "mytype",
+        "myop",
+        parse_quote! { my_key },
+        Some(labels),
+        quote! { 1 },
+    );
+
+    let expected = concat!(
+        "{ ",
+        "metrics :: with_recorder (| recorder | { ",
         "recorder . myop_mytype (metrics :: Key :: Owned (",
-        "metrics :: KeyData :: from_parts (\"mykeyname\" , vec ! [metrics :: Label :: new (\"key1\" , & value1)])",
+        "metrics :: KeyData :: from_parts (my_key , vec ! [metrics :: Label :: new (\"key1\" , & value1)])",
         ") , 1) ; ",
-        "} ",
+        "}) ; ",
         "}",
     );
 
@@ -178,17 +257,169 @@ fn test_get_expanded_callsite_regular_path() {
 
     let expected = concat!(
         "{ ",
-        "if let Some (recorder) = metrics :: try_recorder () { ",
+        "metrics :: with_recorder (| recorder | { ",
         "recorder . myop_mytype (",
         "metrics :: Key :: Owned (metrics :: KeyData :: from_parts (\"mykeyname\" , mylabels)) , ",
         "1",
         ") ; ",
-        "} }",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+/// If the name is a constant - pick the key at runtime, based on the type of the name.
+#[test]
+fn test_get_expanded_callsite_const_path() {
+    let stream = get_expanded_callsite(
+        "mytype",
+        "myop",
+        parse_quote! { MY_KEY },
+        None,
+        quote! { 1 },
+    );
+
+    let expected = concat!(
+        "{ ",
+        "use metrics :: { __OtherName as _ , __StrName as _ } ; ",
+        "static METRIC_KEY : metrics :: StaticKey = metrics :: StaticKey :: new () ; ",
+        "metrics :: with_recorder (| recorder | { ",
+        "let key = match (& metrics :: __ConstName (& MY_KEY)) . __name () { ",
+        "metrics :: __Name :: Str (name) => METRIC_KEY . get (name , & []) , ",
+        "metrics :: __Name :: Owned (name) => metrics :: Key :: Owned (metrics :: KeyData :: from_name (name)) , ",
+        "} ; ",
+        "recorder . myop_mytype (key , 1) ; ",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_get_expanded_callsite_const_path_static_labels() {
+    let labels = Labels::Inline(vec![(parse_quote! { "key1" }, parse_quote! { "value1" })]);
+    let stream = get_expanded_callsite(
+        "mytype",
+        "myop",
+        parse_quote! { keys::MY_KEY },
+        Some(labels),
+        quote! { 1 },
+    );
+
+    let expected = concat!(
+        "{ ",
+        "use metrics :: { __OtherName as _ , __StrName as _ } ; ",
+        "static METRIC_LABELS : [metrics :: Label ; 1usize] = [metrics :: Label :: from_static_parts (\"key1\" , \"value1\")] ; ",
+        "static METRIC_KEY : metrics :: StaticKey = metrics :: StaticKey :: new () ; ",
+        "metrics :: with_recorder (| recorder | { ",
+        "let key = match (& metrics :: __ConstName (& keys :: MY_KEY)) . __name () { ",
+        "metrics :: __Name :: Str (name) => METRIC_KEY . get (name , & METRIC_LABELS) , ",
+        "metrics :: __Name :: Owned (name) => metrics :: Key :: Owned (metrics :: KeyData :: from_parts (name , vec ! [metrics :: Label :: new (\"key1\" , \"value1\")])) , ",
+        "} ; ",
+        "recorder . myop_mytype (key , 1) ; ",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+/// If the name is a constant, but the label values aren't - generate a direct invocation.
+#[test]
+fn test_get_expanded_callsite_regular_path_const_name_dynamic_labels() {
+    let labels = Labels::Inline(vec![(parse_quote! { "key1" }, parse_quote! { value1 })]);
+    let stream = get_expanded_callsite(
+        "mytype",
+        "myop",
+        parse_quote! { MY_KEY },
+        Some(labels),
+        quote! { 1 },
+    );
+
+    let expected = concat!(
+        "{ ",
+        "metrics :: with_recorder (| recorder | { ",
+        "recorder . myop_mytype (",
+        "metrics :: Key :: Owned (metrics :: KeyData :: from_parts (MY_KEY , vec ! [metrics :: Label :: new (\"key1\" , value1)])) , ",
+        "1",
+        ") ; ",
+        "}) ; }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+/// If the name is only known at runtime - generate a direct invocation.
+#[test]
+fn test_get_expanded_callsite_regular_path_dynamic_name() {
+    let stream = get_expanded_callsite(
+        "mytype",
+        "myop",
+        parse_quote! { format!("{}.mykeyname", prefix) },
+        None,
+        quote! { 1 },
+    );
+
+    let expected = concat!(
+        "{ ",
+        "metrics :: with_recorder (| recorder | { ",
+        "recorder . myop_mytype (",
+        "metrics :: Key :: Owned (metrics :: KeyData :: from_name (format ! (\"{}.mykeyname\" , prefix))) , ",
+        "1",
+        ") ; ",
+        "}) ; }",
     );
 
     assert_eq!(stream.to_string(), expected);
 }
 
+#[test]
+fn test_is_static_name() {
+    let static_names: Vec<Expr> = vec![
+        parse_quote! { "mykeyname" },
+        parse_quote! { concat!(module_path!(), "::mykeyname") },
+        parse_quote! { stringify!(mykeyname) },
+    ];
+    for name in &static_names {
+        assert!(is_static_name(name), "{}", name.to_token_stream());
+    }
+
+    let dynamic_names: Vec<Expr> = vec![
+        parse_quote! { MY_KEY },
+        parse_quote! { keys::MY_KEY2 },
+        parse_quote! { my_key },
+        parse_quote! { format!("{}.mykeyname", prefix) },
+        parse_quote! { name.to_string() },
+    ];
+    for name in &dynamic_names {
+        assert!(!is_static_name(name), "{}", name.to_token_stream());
+    }
+}
+
+#[test]
+fn test_is_const_name() {
+    let const_names: Vec<Expr> = vec![
+        parse_quote! { MY_KEY },
+        parse_quote! { keys::MY_KEY2 },
+        parse_quote! { (MY_KEY) },
+    ];
+    for name in &const_names {
+        assert!(is_const_name(name), "{}", name.to_token_stream());
+    }
+
+    let other_names: Vec<Expr> = vec![
+        parse_quote! { "mykeyname" },
+        parse_quote! { my_key },
+        parse_quote! { keys::my_key },
+        parse_quote! { Key::<T>::NAME },
+        parse_quote! { _ },
+        parse_quote! { format!("{}.mykeyname", prefix) },
+        parse_quote! { name.to_string() },
+    ];
+    for name in &other_names {
+        assert!(!is_const_name(name), "{}", name.to_token_stream());
+    }
+}
+
 #[test]
 fn test_key_to_quoted_no_labels() {
     let stream = key_to_quoted(parse_quote! {"mykeyname"}, None);
@@ -200,7 +431,9 @@ fn test_key_to_quoted_no_labels() {
 fn test_key_to_quoted_existing_labels() {
     let stream = key_to_quoted(
         parse_quote! {"mykeyname"},
-        Some(Labels::Existing(Expr::Path(parse_quote! { mylabels }))),
+        Some(Labels::Existing(Box::new(Expr::Path(
+            parse_quote! { mylabels },
+        )))),
     );
     let expected = "metrics :: KeyData :: from_parts (\"mykeyname\" , mylabels)";
     assert_eq!(stream.to_string(), expected);
@@ -235,3 +468,168 @@ fn test_key_to_quoted_inline_labels_empty() {
     );
     assert_eq!(stream.to_string(), expected);
 }
+
+#[test]
+fn test_get_expanded_instrumented_timed() {
+    let instrumentation: Instrumentation = parse_quote! {};
+    let item: ItemFn = parse_quote! {
+        fn myfn(value: u64) -> u64 { value + 1 }
+    };
+    let stream = get_expanded_instrumented("histogram", "record", instrumentation, item);
+
+    let expected = concat!(
+        "fn myfn (value : u64) -> u64 { ",
+        "metrics :: __catalog_callsite ! (metrics :: Callsite :: new (",
+        "concat ! (module_path ! () , \"::\" , \"myfn_duration\") , metrics :: MetricKind :: Histogram , ",
+        "& [] , None , None , module_path ! () , file ! () , line ! ())) ; ",
+        "let __metrics_start = std :: time :: Instant :: now () ; ",
+        "let __metrics_guard = metrics :: __OnReturn (|| { ",
+        "static METRIC_UNIT : std :: sync :: Once = std :: sync :: Once :: new () ; ",
+        "METRIC_UNIT . call_once (|| { { metrics :: with_recorder (| recorder | { ",
+        "recorder . register_histogram (metrics :: Key :: Owned (metrics :: KeyData :: from_name (",
+        "concat ! (module_path ! () , \"::\" , \"myfn_duration\"))) , ",
+        "Some (metrics :: Unit :: Nanoseconds) , None) }) ",
+        ". unwrap_or_else (metrics :: Histogram :: noop) } ; }) ; ",
+        "{ ",
+        "static METRIC_KEY : metrics :: KeyData = metrics :: KeyData :: from_static_name (",
+        "concat ! (module_path ! () , \"::\" , \"myfn_duration\")) ; ",
+        "metrics :: with_recorder (| recorder | { ",
+        "recorder . record_histogram (metrics :: Key :: Borrowed (& METRIC_KEY) , ",
+        "metrics :: __into_f64 (__metrics_start . elapsed ())) ; ",
+        "}) ; } }) ; ",
+        "{ value + 1 } }",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_get_expanded_instrumented_counted_async_result() {
+    let instrumentation: Instrumentation = parse_quote! { name = "mykeyname", result };
+    let item: ItemFn = parse_quote! {
+        async fn myfn() -> Result<(), ()> { Ok(()) }
+    };
+    let stream = get_expanded_instrumented("counter", "increment", instrumentation, item);
+
+    let callsite = |result: &str| {
+        format!(
+            concat!(
+                "{{ ",
+                "static METRIC_LABELS : [metrics :: Label ; 1usize] = ",
+                "[metrics :: Label :: from_static_parts (\"result\" , \"{}\")] ; ",
+                "static METRIC_KEY : metrics :: KeyData = ",
+                "metrics :: KeyData :: from_static_parts (\"mykeyname\" , & METRIC_LABELS) ; ",
+                "metrics :: with_recorder (| recorder | {{ ",
+                "recorder . increment_counter (metrics :: Key :: Borrowed (& METRIC_KEY) , 1) ; ",
+                "}}) ; }}",
+            ),
+            result
+        )
+    };
+    let expected = format!(
+        concat!(
+            "async fn myfn () -> Result < () , () > {{ ",
+            "metrics :: __catalog_callsite ! (metrics :: Callsite :: new (",
+            "\"mykeyname\" , metrics :: MetricKind :: Counter , & [\"result\"] , None , None , ",
+            "module_path ! () , file ! () , line ! ())) ; ",
+            "let __metrics_result : Result < () , () > = async move {{ Ok (()) }} . await ; ",
+            "match & __metrics_result {{ Ok (_) => {} , Err (_) => {} , }} ",
+            "__metrics_result }}",
+        ),
+        callsite("ok"),
+        callsite("error")
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_instrumentation_arguments() {
+    let instrumentation: Instrumentation = parse_quote! { result, name = "mykeyname" };
+    let key = instrumentation
+        .key
+        .map(|k| k.into_token_stream().to_string());
+    assert_eq!(key.as_deref(), Some("\"mykeyname\""));
+    assert!(instrumentation.result_labels);
+
+    assert!(syn::parse_str::<Instrumentation>("labels").is_err());
+    assert!(syn::parse_str::<Instrumentation>("name = \"1invalid\"").is_err());
+}
+
+#[test]
+fn test_get_expanded_catalog_entry() {
+    let key: Expr = parse_quote! { "mykeyname" };
+    let labels = Labels::Inline(vec![(parse_quote! { "key1" }, parse_quote! { &value1 })]);
+    let units: ExprPath = parse_quote! { metrics::Unit::Nanoseconds };
+    let stream = get_expanded_catalog_entry(
+        "histogram",
+        &key,
+        &Some(labels),
+        &Some(Expr::Path(units)),
+        &Some(parse_quote! { "flerkin" }),
+    );
+
+    let expected = concat!(
+        "metrics :: __catalog_callsite ! (metrics :: Callsite :: new (",
+        "\"mykeyname\" , ",
+        "metrics :: MetricKind :: Histogram , ",
+        "& [\"key1\"] , ",
+        "Some (metrics :: Unit :: Nanoseconds) , ",
+        "Some (\"flerkin\") , ",
+        "module_path ! () , file ! () , line ! ())) ;",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_get_expanded_catalog_entry_existing_labels() {
+    let key: Expr = parse_quote! { "mykeyname" };
+    let labels = Labels::Existing(Box::new(parse_quote! { mylabels }));
+    let stream = get_expanded_catalog_entry("counter", &key, &Some(labels), &None, &None);
+
+    let expected = concat!(
+        "metrics :: __catalog_callsite ! (metrics :: Callsite :: new (",
+        "\"mykeyname\" , ",
+        "metrics :: MetricKind :: Counter , ",
+        "& [] , ",
+        "None , ",
+        "None , ",
+        "module_path ! () , file ! () , line ! ())) ;",
+    );
+
+    assert_eq!(stream.to_string(), expected);
+}
+
+#[test]
+fn test_get_expanded_catalog_entry_dynamic_unit() {
+    let key: Expr = parse_quote! { "mykeyname" };
+    let units: [Expr; 2] = [
+        parse_quote! { unit_var },
+        parse_quote! { units::default_unit() },
+    ];
+    for unit in &units {
+        let stream =
+            get_expanded_catalog_entry("histogram", &key, &None, &Some(unit.clone()), &None);
+
+        let expected = concat!(
+            "metrics :: __catalog_callsite ! (metrics :: Callsite :: new (",
+            "\"mykeyname\" , ",
+            "metrics :: MetricKind :: Histogram , ",
+            "& [] , ",
+            "None , ",
+            "None , ",
+            "module_path ! () , file ! () , line ! ())) ;",
+        );
+
+        assert_eq!(stream.to_string(), expected);
+    }
+}
+
+#[test]
+fn test_get_expanded_catalog_entry_dynamic_name() {
+    let key: Expr = parse_quote! { my_key };
+    let stream = get_expanded_catalog_entry("counter", &key, &None, &None, &None);
+
+    assert!(stream.is_empty());
+}
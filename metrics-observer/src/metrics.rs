@@ -7,7 +7,7 @@ use std::thread;
 use std::time::Duration;
 
 use bytes::{BufMut, BytesMut};
-use hdrhistogram::Histogram;
+use sketches_ddsketch::{Config as SketchConfig, DDSketch};
 use prost::Message;
 
 use metrics::{KeyData, Label, Unit};
@@ -33,7 +33,7 @@ pub enum ClientState {
 pub enum MetricData {
     Counter(u64),
     Gauge(f64),
-    Histogram(Histogram<u64>),
+    Histogram(DDSketch),
 }
 
 pub struct Client {
@@ -210,15 +210,24 @@ impl Runner {
                                         .expect("failed to get metadata write lock");
                                     let entry = mmap.entry(key).or_insert((None, None));
                                     let (uentry, dentry) = entry;
-                                    *uentry = metadata
+
+                                    // Metadata may arrive piecemeal, from registrations as well
+                                    // as descriptions, so don't clobber what we already have.
+                                    let unit = metadata
                                         .unit
                                         .map(|u| match u {
                                             UnitMetadata::UnitValue(us) => us,
                                         })
                                         .and_then(|s| Unit::from_str(s.as_str()));
-                                    *dentry = metadata.description.map(|d| match d {
+                                    if unit.is_some() {
+                                        *uentry = unit;
+                                    }
+                                    let desc = metadata.description.map(|d| match d {
                                         DescriptionMetadata::DescriptionValue(ds) => ds,
                                     });
+                                    if desc.is_some() {
+                                        *dentry = desc;
+                                    }
                                 }
                                 Event::Metric(metric) => {
                                     let mut labels_raw =
@@ -244,6 +253,19 @@ impl Runner {
                                                 *inner += value.value;
                                             }
                                         }
+                                        proto::metric::Value::AbsoluteCounter(value) => {
+                                            let key = CompositeKey::new(
+                                                MetricKind::Counter,
+                                                key_data.into(),
+                                            );
+                                            let mut metrics = self.metrics.write().unwrap();
+                                            let counter = metrics
+                                                .entry(key)
+                                                .or_insert_with(|| MetricData::Counter(0));
+                                            if let MetricData::Counter(inner) = counter {
+                                                *inner = (*inner).max(value.value);
+                                            }
+                                        }
                                         proto::metric::Value::Gauge(value) => {
                                             let key = CompositeKey::new(
                                                 MetricKind::Gauge,
@@ -257,6 +279,19 @@ impl Runner {
                                                 *inner = value.value;
                                             }
                                         }
+                                        proto::metric::Value::GaugeDelta(value) => {
+                                            let key = CompositeKey::new(
+                                                MetricKind::Gauge,
+                                                key_data.into(),
+                                            );
+                                            let mut metrics = self.metrics.write().unwrap();
+                                            let gauge = metrics
+                                                .entry(key)
+                                                .or_insert_with(|| MetricData::Gauge(0.0));
+                                            if let MetricData::Gauge(inner) = gauge {
+                                                *inner += value.value;
+                                            }
+                                        }
                                         proto::metric::Value::Histogram(value) => {
                                             let key = CompositeKey::new(
                                                 MetricKind::Histogram,
@@ -265,15 +300,13 @@ impl Runner {
                                             let mut metrics = self.metrics.write().unwrap();
                                             let histogram =
                                                 metrics.entry(key).or_insert_with(|| {
-                                                    let histogram = Histogram::new(3)
-                                                        .expect("failed to create histogram");
+                                                    let histogram =
+                                                        DDSketch::new(SketchConfig::defaults());
                                                     MetricData::Histogram(histogram)
                                                 });
 
                                             if let MetricData::Histogram(inner) = histogram {
-                                                inner
-                                                    .record(value.value)
-                                                    .expect("failed to record value to histogram");
+                                                inner.add(value.value);
                                             }
                                         }
                                     }
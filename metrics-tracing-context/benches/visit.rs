@@ -1,3 +1,5 @@
+#![allow(deprecated, dead_code, clippy::useless_format)]
+
 use criterion::{criterion_group, criterion_main, BatchSize, Benchmark, Criterion};
 use metrics_tracing_context::Labels;
 use tracing::Metadata;
@@ -55,7 +55,7 @@
 
 #![deny(missing_docs)]
 
-use metrics::{Key, KeyData, Label, Recorder, Unit};
+use metrics::{Counter, Gauge, Histogram, Key, KeyData, Label, Recorder, SharedString, Unit};
 use metrics_util::layers::Layer;
 use tracing::Span;
 
@@ -103,6 +103,10 @@ where
 
 /// [`TracingContext`] is a [`metrics::Recorder`] that injects labels from the
 /// [`tracing::Span`]s.
+///
+/// Labels are only injected when updating a metric by key.  Handles returned from registration are
+/// passed through from the inner recorder as-is, and so updates made through them do not carry any
+/// span labels.
 pub struct TracingContext<R, F> {
     inner: R,
     label_filter: F,
@@ -136,29 +140,76 @@ where
     R: Recorder,
     F: LabelFilter,
 {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
         self.inner.register_counter(key, unit, description)
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
         self.inner.register_gauge(key, unit, description)
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
         self.inner.register_histogram(key, unit, description)
     }
 
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
+    }
+
     fn increment_counter(&self, key: Key, value: u64) {
         let key = self.enhance_key(key);
         self.inner.increment_counter(key, value);
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let key = self.enhance_key(key);
+        self.inner.absolute_counter(key, value);
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
         let key = self.enhance_key(key);
         self.inner.update_gauge(key, value);
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        let key = self.enhance_key(key);
+        self.inner.increment_gauge(key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let key = self.enhance_key(key);
+        self.inner.decrement_gauge(key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         let key = self.enhance_key(key);
         self.inner.record_histogram(key, value);
     }
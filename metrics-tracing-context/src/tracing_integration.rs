@@ -62,8 +62,10 @@ impl AsRef<Vec<Label>> for Labels {
     }
 }
 
+type WithLabelsFn = fn(&Dispatch, &Id, f: &mut dyn FnMut(&Labels));
+
 pub struct WithContext {
-    with_labels: fn(&Dispatch, &Id, f: &mut dyn FnMut(&Labels)),
+    with_labels: WithLabelsFn,
 }
 
 impl WithContext {
@@ -108,8 +110,7 @@ where
                 .expect("registry should have a span for the current ID")
         };
 
-        let parents = span.parents();
-        for span in std::iter::once(span).chain(parents) {
+        for span in span.scope() {
             let extensions = span.extensions();
             if let Some(value) = extensions.get::<Labels>() {
                 f(value);
@@ -118,6 +119,15 @@ where
     }
 }
 
+impl<S> Default for MetricsLayer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn default() -> Self {
+        MetricsLayer::new()
+    }
+}
+
 impl<S> Layer<S> for MetricsLayer<S>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
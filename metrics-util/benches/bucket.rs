@@ -1,3 +1,5 @@
+#![allow(deprecated)]
+
 use criterion::{criterion_group, criterion_main, Benchmark, Criterion, Throughput};
 use lazy_static::lazy_static;
 use metrics_util::AtomicBucket;
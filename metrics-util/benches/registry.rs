@@ -1,6 +1,51 @@
+#![allow(deprecated, clippy::redundant_closure)]
+
 use criterion::{criterion_group, criterion_main, BatchSize, Benchmark, Criterion};
+use dashmap::DashMap;
 use metrics::{Key, KeyData, Label};
 use metrics_util::Registry;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+static MANY_LABELS: [Label; 4] = [
+    Label::from_static_parts("type", "http"),
+    Label::from_static_parts("method", "GET"),
+    Label::from_static_parts("route", "/api/v1/users/:id"),
+    Label::from_static_parts("status", "200"),
+];
+
+/// A key which hashes its name and labels on every lookup, as keys did before their hash was
+/// precomputed.
+#[derive(PartialEq, Eq)]
+struct RehashedKey(Key);
+
+impl Hash for RehashedKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.name().hash(state);
+        for label in self.0.labels() {
+            label.key().hash(state);
+            label.value().hash(state);
+        }
+    }
+}
+
+/// [`Registry::op`] as it was before keys were hashed with `KeyHasher`: a map using the standard
+/// hasher, and a generation bumped on every operation.
+#[derive(Default)]
+struct RehashedRegistry {
+    map: DashMap<RehashedKey, (Arc<AtomicUsize>, ())>,
+}
+
+impl RehashedRegistry {
+    fn op(&self, key: RehashedKey) {
+        let valref = self
+            .map
+            .entry(key)
+            .or_insert_with(|| (Arc::new(AtomicUsize::new(0)), ()));
+        valref.value().0.fetch_add(1, Ordering::Release);
+    }
+}
 
 fn registry_benchmark(c: &mut Criterion) {
     c.bench(
@@ -24,6 +69,15 @@ fn registry_benchmark(c: &mut Criterion) {
                 registry.op(key, |_| (), || ())
             })
         })
+        .with_function("cached op (many labels)", |b| {
+            let registry: Registry<Key, ()> = Registry::new();
+            static KEY_DATA: KeyData = KeyData::from_static_parts("simple_key", &MANY_LABELS);
+
+            b.iter(|| {
+                let key = Key::Borrowed(&KEY_DATA);
+                registry.op(key, |_| (), || ())
+            })
+        })
         .with_function("uncached op (basic)", |b| {
             b.iter_batched_ref(
                 || Registry::<Key, ()>::new(),
@@ -45,6 +99,16 @@ fn registry_benchmark(c: &mut Criterion) {
                 BatchSize::SmallInput,
             )
         })
+        .with_function("uncached op (many labels)", |b| {
+            b.iter_batched_ref(
+                || Registry::<Key, ()>::new(),
+                |registry| {
+                    let key = Key::Owned(("simple_key", MANY_LABELS.to_vec()).into());
+                    registry.op(key, |_| (), || ())
+                },
+                BatchSize::SmallInput,
+            )
+        })
         .with_function("registry overhead", |b| {
             b.iter_batched(
                 || (),
@@ -99,6 +163,27 @@ fn registry_benchmark(c: &mut Criterion) {
             static KEY_LABELS: [Label; 1] = [Label::from_static_parts("type", "http")];
             static KEY_DATA: KeyData = KeyData::from_static_parts("simple_key", &KEY_LABELS);
             b.iter(|| Key::Borrowed(&KEY_DATA))
+        })
+        // Before keys carried a precomputed hash, every operation rehashed the name and labels
+        // with the standard hasher.
+        .with_function("rehashed op (labels)", |b| {
+            let registry = RehashedRegistry::default();
+            static KEY_LABELS: [Label; 1] = [Label::from_static_parts("type", "http")];
+            static KEY_DATA: KeyData = KeyData::from_static_parts("simple_key", &KEY_LABELS);
+
+            b.iter(|| {
+                let key = RehashedKey(Key::Borrowed(&KEY_DATA));
+                registry.op(key)
+            })
+        })
+        .with_function("rehashed op (many labels)", |b| {
+            let registry = RehashedRegistry::default();
+            static KEY_DATA: KeyData = KeyData::from_static_parts("simple_key", &MANY_LABELS);
+
+            b.iter(|| {
+                let key = RehashedKey(Key::Borrowed(&KEY_DATA));
+                registry.op(key)
+            })
         }),
     );
 }
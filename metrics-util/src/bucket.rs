@@ -47,10 +47,13 @@ impl<T> Block<T> {
 
     /// Links this block to the previous block in the bucket.
     pub fn set_prev(&self, prev: Shared<Block<T>>, guard: &Guard) {
-        match self
-            .prev
-            .compare_and_set(Shared::null(), prev, Ordering::AcqRel, guard)
-        {
+        match self.prev.compare_exchange(
+            Shared::null(),
+            prev,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+            guard,
+        ) {
             Ok(_) => {}
             Err(_) => unreachable!(),
         }
@@ -127,10 +130,11 @@ impl<T> AtomicBucket<T> {
             let mut tail = self.tail.load(Ordering::Acquire, guard);
             if tail.is_null() {
                 // No blocks at all yet.  We need to create one.
-                match self.tail.compare_and_set(
+                match self.tail.compare_exchange(
                     Shared::null(),
                     Owned::new(Block::new()),
                     Ordering::AcqRel,
+                    Ordering::Acquire,
                     guard,
                 ) {
                     // We won the race to install the new block.
@@ -148,10 +152,11 @@ impl<T> AtomicBucket<T> {
                 Ok(_) => return,
                 // The block was full, so we've been given the value back and we need to install a new block.
                 Err(value) => {
-                    match self.tail.compare_and_set(
+                    match self.tail.compare_exchange(
                         tail,
                         Owned::new(Block::new()),
                         Ordering::AcqRel,
+                        Ordering::Acquire,
                         guard,
                     ) {
                         // We managed to install the block, so we need to link this new block to
@@ -263,7 +268,13 @@ impl<T> AtomicBucket<T> {
         if !tail.is_null()
             && self
                 .tail
-                .compare_and_set(tail, Shared::null(), Ordering::SeqCst, guard)
+                .compare_exchange(
+                    tail,
+                    Shared::null(),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                    guard,
+                )
                 .is_ok()
         {
             // While we have a valid block -- either `tail` or the next block as we keep reading -- we
@@ -2,33 +2,16 @@ use core::hash::{Hash, Hasher};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use crate::{handle::Handle, registry::Registry};
+use crate::{handle::Handle, registry::Registry, MetricKind};
 
 use indexmap::IndexMap;
-use metrics::{Key, Recorder, Unit};
-
-/// Metric kinds.
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, Ord, PartialOrd)]
-pub enum MetricKind {
-    /// Counter.
-    Counter,
-
-    /// Gauge.
-    Gauge,
-
-    /// Histogram.
-    Histogram,
-}
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, Recorder, SharedString, Unit,
+};
 
 #[derive(Eq, PartialEq, Hash, Clone)]
 struct DifferentiatedKey(MetricKind, Key);
 
-impl DifferentiatedKey {
-    pub fn into_parts(self) -> (MetricKind, Key) {
-        (self.0, self.1)
-    }
-}
-
 /// A point-in-time value for a metric exposing raw values.
 #[derive(Debug, PartialEq)]
 pub enum DebugValue {
@@ -37,7 +20,7 @@ pub enum DebugValue {
     /// Gauge.
     Gauge(f64),
     /// Histogram.
-    Histogram(Vec<u64>),
+    Histogram(Vec<f64>),
 }
 
 // We don't care that much about total equality nuances here.
@@ -47,68 +30,88 @@ impl Hash for DebugValue {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
             Self::Counter(val) => val.hash(state),
-            Self::Gauge(val) => {
-                // Whatever works, we don't really care in here...
-                if val.is_normal() {
-                    val.to_ne_bytes().hash(state)
-                } else {
-                    0f64.to_ne_bytes().hash(state)
+            Self::Gauge(val) => hash_f64(*val, state),
+            Self::Histogram(val) => {
+                for v in val {
+                    hash_f64(*v, state);
                 }
             }
-            Self::Histogram(val) => val.hash(state),
         }
     }
 }
 
+fn hash_f64<H: Hasher>(val: f64, state: &mut H) {
+    // Whatever works, we don't really care in here...
+    if val.is_normal() {
+        val.to_ne_bytes().hash(state)
+    } else {
+        0f64.to_ne_bytes().hash(state)
+    }
+}
+
+type Snapshot = Vec<(
+    MetricKind,
+    Key,
+    Option<Unit>,
+    Option<SharedString>,
+    DebugValue,
+)>;
+
+/// Metadata given by `describe_*` applies to every metric with the same name, regardless of labels.
+type MetadataKey = (MetricKind, SharedString);
+
 /// Captures point-in-time snapshots of `DebuggingRecorder`.
 pub struct Snapshotter {
     registry: Arc<Registry<DifferentiatedKey, Handle>>,
     metrics: Arc<Mutex<IndexMap<DifferentiatedKey, ()>>>,
     units: Arc<Mutex<HashMap<DifferentiatedKey, Unit>>>,
     descriptions: Arc<Mutex<HashMap<DifferentiatedKey, &'static str>>>,
+    named_units: Arc<Mutex<HashMap<MetadataKey, Unit>>>,
+    named_descriptions: Arc<Mutex<HashMap<MetadataKey, SharedString>>>,
 }
 
 impl Snapshotter {
     /// Takes a snapshot of the recorder.
-    pub fn snapshot(
-        &self,
-    ) -> Vec<(
-        MetricKind,
-        Key,
-        Option<Unit>,
-        Option<&'static str>,
-        DebugValue,
-    )> {
-        let mut snapshot = Vec::new();
-        let handles = self.registry.get_handles();
-        let metrics = {
-            let metrics = self.metrics.lock().expect("metrics lock poisoned");
-            metrics.clone()
-        };
-        for (dkey, _) in metrics.into_iter() {
-            if let Some(handle) = handles.get(&dkey) {
-                let unit = self
-                    .units
-                    .lock()
-                    .expect("units lock poisoned")
-                    .get(&dkey)
-                    .cloned();
-                let description = self
-                    .descriptions
-                    .lock()
-                    .expect("descriptions lock poisoned")
-                    .get(&dkey)
-                    .cloned();
-                let (kind, key) = dkey.into_parts();
-                let value = match kind {
-                    MetricKind::Counter => DebugValue::Counter(handle.read_counter()),
-                    MetricKind::Gauge => DebugValue::Gauge(handle.read_gauge()),
-                    MetricKind::Histogram => DebugValue::Histogram(handle.read_histogram()),
-                };
-                snapshot.push((kind, key, unit, description, value));
-            }
-        }
-        snapshot
+    ///
+    /// Metrics are listed in the order they were first seen by the recorder.  The unit and
+    /// description given when registering a metric take precedence over those given when
+    /// describing its name.
+    pub fn snapshot(&self) -> Snapshot {
+        let metrics = self.metrics.lock().expect("metrics lock poisoned");
+        let units = self.units.lock().expect("units lock poisoned");
+        let descriptions = self
+            .descriptions
+            .lock()
+            .expect("descriptions lock poisoned");
+        let named_units = self.named_units.lock().expect("units lock poisoned");
+        let named_descriptions = self
+            .named_descriptions
+            .lock()
+            .expect("descriptions lock poisoned");
+
+        let mut snapshot = Vec::with_capacity(metrics.len());
+        self.registry.visit(|dkey, handle| {
+            let index = match metrics.get_index_of(dkey) {
+                Some(index) => index,
+                None => return,
+            };
+            let DifferentiatedKey(kind, key) = dkey;
+            let mkey = (*kind, key.name().clone());
+            let unit = units.get(dkey).or_else(|| named_units.get(&mkey)).cloned();
+            let description = descriptions
+                .get(dkey)
+                .map(|description| (*description).into())
+                .or_else(|| named_descriptions.get(&mkey).cloned());
+            let value = match kind {
+                MetricKind::Counter => DebugValue::Counter(handle.read_counter()),
+                MetricKind::Gauge => DebugValue::Gauge(handle.read_gauge()),
+                MetricKind::Histogram => DebugValue::Histogram(handle.read_histogram()),
+            };
+            snapshot.push((index, (*kind, key.clone(), unit, description, value)));
+        });
+
+        snapshot.sort_by_key(|(index, _)| *index);
+        snapshot.into_iter().map(|(_, metric)| metric).collect()
     }
 }
 
@@ -121,6 +124,9 @@ pub struct DebuggingRecorder {
     metrics: Arc<Mutex<IndexMap<DifferentiatedKey, ()>>>,
     units: Arc<Mutex<HashMap<DifferentiatedKey, Unit>>>,
     descriptions: Arc<Mutex<HashMap<DifferentiatedKey, &'static str>>>,
+    named_units: Arc<Mutex<HashMap<MetadataKey, Unit>>>,
+    named_descriptions: Arc<Mutex<HashMap<MetadataKey, SharedString>>>,
+    counter: fn() -> Handle,
 }
 
 impl DebuggingRecorder {
@@ -131,6 +137,22 @@ impl DebuggingRecorder {
             metrics: Arc::new(Mutex::new(IndexMap::new())),
             units: Arc::new(Mutex::new(HashMap::new())),
             descriptions: Arc::new(Mutex::new(HashMap::new())),
+            named_units: Arc::new(Mutex::new(HashMap::new())),
+            named_descriptions: Arc::new(Mutex::new(HashMap::new())),
+            counter: Handle::counter,
+        }
+    }
+
+    /// Creates a new `DebuggingRecorder` which stores counters as [`ShardedCounter`]s.
+    ///
+    /// Every counter then takes up a cache line per CPU, so this is best kept for benchmarking
+    /// contention on a handful of counters.
+    ///
+    /// [`ShardedCounter`]: crate::ShardedCounter
+    pub fn with_sharded_counters() -> DebuggingRecorder {
+        DebuggingRecorder {
+            counter: Handle::sharded_counter,
+            ..DebuggingRecorder::new()
         }
     }
 
@@ -141,6 +163,8 @@ impl DebuggingRecorder {
             metrics: self.metrics.clone(),
             units: self.units.clone(),
             descriptions: self.descriptions.clone(),
+            named_units: self.named_units.clone(),
+            named_descriptions: self.named_descriptions.clone(),
         }
     }
 
@@ -167,32 +191,92 @@ impl DebuggingRecorder {
         }
     }
 
+    fn describe(
+        &self,
+        kind: MetricKind,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        let mkey = (kind, name);
+        if let Some(unit) = unit {
+            let mut units = self.named_units.lock().expect("units lock poisoned");
+            let _ = units.insert(mkey.clone(), unit);
+        }
+        let mut descriptions = self
+            .named_descriptions
+            .lock()
+            .expect("description lock poisoned");
+        let _ = descriptions.insert(mkey, description);
+    }
+
     /// Installs this recorder as the global recorder.
     pub fn install(self) -> Result<(), metrics::SetRecorderError> {
         metrics::set_boxed_recorder(Box::new(self))
     }
 }
 
+impl Default for DebuggingRecorder {
+    fn default() -> Self {
+        DebuggingRecorder::new()
+    }
+}
+
 impl Recorder for DebuggingRecorder {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
         let rkey = DifferentiatedKey(MetricKind::Counter, key);
         self.register_metric(rkey.clone());
         self.insert_unit_description(rkey.clone(), unit, description);
-        self.registry.op(rkey, |_| {}, || Handle::counter())
+        let handle = self.registry.op(rkey, |h| h.clone(), self.counter);
+        Counter::from_arc(Arc::new(handle))
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
         let rkey = DifferentiatedKey(MetricKind::Gauge, key);
         self.register_metric(rkey.clone());
         self.insert_unit_description(rkey.clone(), unit, description);
-        self.registry.op(rkey, |_| {}, || Handle::gauge())
+        let handle = self.registry.op(rkey, |h| h.clone(), Handle::gauge);
+        Gauge::from_arc(Arc::new(handle))
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
         let rkey = DifferentiatedKey(MetricKind::Histogram, key);
         self.register_metric(rkey.clone());
         self.insert_unit_description(rkey.clone(), unit, description);
-        self.registry.op(rkey, |_| {}, || Handle::histogram())
+        let handle = self.registry.op(rkey, |h| h.clone(), Handle::histogram);
+        Histogram::from_arc(Arc::new(handle))
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.describe(MetricKind::Counter, name, unit, description);
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.describe(MetricKind::Gauge, name, unit, description);
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.describe(MetricKind::Histogram, name, unit, description);
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
@@ -200,28 +284,90 @@ impl Recorder for DebuggingRecorder {
         self.register_metric(rkey.clone());
         self.registry.op(
             rkey,
-            |handle| handle.increment_counter(value),
-            || Handle::counter(),
+            |handle| CounterFn::increment(handle, value),
+            self.counter,
+        )
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let rkey = DifferentiatedKey(MetricKind::Counter, key);
+        self.register_metric(rkey.clone());
+        self.registry.op(
+            rkey,
+            |handle| CounterFn::absolute(handle, value),
+            self.counter,
         )
     }
 
     fn update_gauge(&self, key: Key, value: f64) {
+        let rkey = DifferentiatedKey(MetricKind::Gauge, key);
+        self.register_metric(rkey.clone());
+        self.registry
+            .op(rkey, |handle| GaugeFn::update(handle, value), Handle::gauge)
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
         let rkey = DifferentiatedKey(MetricKind::Gauge, key);
         self.register_metric(rkey.clone());
         self.registry.op(
             rkey,
-            |handle| handle.update_gauge(value),
-            || Handle::gauge(),
+            |handle| GaugeFn::increment(handle, value),
+            Handle::gauge,
         )
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let rkey = DifferentiatedKey(MetricKind::Gauge, key);
+        self.register_metric(rkey.clone());
+        self.registry.op(
+            rkey,
+            |handle| GaugeFn::decrement(handle, value),
+            Handle::gauge,
+        )
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         let rkey = DifferentiatedKey(MetricKind::Histogram, key);
         self.register_metric(rkey.clone());
         self.registry.op(
             rkey,
-            |handle| handle.record_histogram(value),
-            || Handle::histogram(),
+            |handle| HistogramFn::record(handle, value),
+            Handle::histogram,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DebuggingRecorder;
+    use metrics::{Key, KeyData, Label, Recorder, Unit};
+
+    #[test]
+    fn test_metadata() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        let key = |path| {
+            Key::Owned(KeyData::from_parts(
+                "requests",
+                vec![Label::new("path", path)],
+            ))
+        };
+        recorder.describe_counter("requests".into(), Some(Unit::Count), "all requests".into());
+        recorder.register_counter(key("/"), None, None);
+        recorder.register_counter(key("/admin"), Some(Unit::Bytes), Some("admin requests"));
+
+        let metadata = snapshotter
+            .snapshot()
+            .into_iter()
+            .map(|(_kind, _key, unit, desc, _value)| (unit, desc.map(|d| d.to_string())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            metadata,
+            vec![
+                (Some(Unit::Count), Some("all requests".to_string())),
+                (Some(Unit::Bytes), Some("admin requests".to_string())),
+            ]
+        );
+    }
+}
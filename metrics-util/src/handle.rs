@@ -1,6 +1,7 @@
-use crate::AtomicBucket;
+use crate::{AtomicBucket, ShardedCounter};
 
 use atomic_shim::AtomicU64;
+use metrics::{CounterFn, GaugeFn, HistogramFn};
 use std::sync::{atomic::Ordering, Arc};
 
 /// Basic metric handle.
@@ -12,11 +13,14 @@ pub enum Handle {
     /// A counter.
     Counter(Arc<AtomicU64>),
 
+    /// A counter split into shards, for counters updated concurrently by many threads.
+    ShardedCounter(Arc<ShardedCounter>),
+
     /// A gauge.
     Gauge(Arc<AtomicU64>),
 
     /// A histogram.
-    Histogram(Arc<AtomicBucket<u64>>),
+    Histogram(Arc<AtomicBucket<f64>>),
 }
 
 impl Handle {
@@ -27,6 +31,15 @@ impl Handle {
         Handle::Counter(Arc::new(AtomicU64::new(0)))
     }
 
+    /// Creates a sharded counter handle.
+    ///
+    /// The counter is initialized to 0.  Sharded counters behave the same as regular counters, but
+    /// trade memory, a cache line per CPU, and slower reads for increments which scale across
+    /// threads.  See [`ShardedCounter`] for more details.
+    pub fn sharded_counter() -> Handle {
+        Handle::ShardedCounter(Arc::new(ShardedCounter::new()))
+    }
+
     /// Creates a gauge handle.
     ///
     /// The gauge is initialized to 0.
@@ -49,10 +62,40 @@ impl Handle {
             Handle::Counter(counter) => {
                 counter.fetch_add(value, Ordering::SeqCst);
             }
+            Handle::ShardedCounter(counter) => counter.increment(value),
             _ => panic!("tried to increment as counter"),
         }
     }
 
+    /// Sets this handle as a counter to an absolute value.
+    ///
+    /// The counter is only updated if `value` is greater than the current value, so that the
+    /// counter never goes backwards.
+    ///
+    /// Panics if this handle is not a counter.
+    pub fn absolute_counter(&self, value: u64) {
+        match self {
+            Handle::Counter(counter) => {
+                // Not every platform supported by `atomic-shim` provides `fetch_max`, so we
+                // emulate it with a compare-and-swap loop.
+                let mut current = counter.load(Ordering::Relaxed);
+                while value > current {
+                    match counter.compare_exchange_weak(
+                        current,
+                        value,
+                        Ordering::SeqCst,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => break,
+                        Err(actual) => current = actual,
+                    }
+                }
+            }
+            Handle::ShardedCounter(counter) => counter.absolute(value),
+            _ => panic!("tried to set absolute value as counter"),
+        }
+    }
+
     /// Updates this handle as a gauge.
     ///
     /// Panics if this handle is not a gauge.
@@ -64,10 +107,34 @@ impl Handle {
         }
     }
 
+    /// Increments this handle as a gauge.
+    ///
+    /// The increment is applied atomically, so concurrent increments and decrements are never lost.
+    ///
+    /// Panics if this handle is not a gauge.
+    pub fn increment_gauge(&self, value: f64) {
+        match self {
+            Handle::Gauge(gauge) => adjust_gauge(gauge, |current| current + value),
+            _ => panic!("tried to increment as gauge"),
+        }
+    }
+
+    /// Decrements this handle as a gauge.
+    ///
+    /// The decrement is applied atomically, so concurrent increments and decrements are never lost.
+    ///
+    /// Panics if this handle is not a gauge.
+    pub fn decrement_gauge(&self, value: f64) {
+        match self {
+            Handle::Gauge(gauge) => adjust_gauge(gauge, |current| current - value),
+            _ => panic!("tried to decrement as gauge"),
+        }
+    }
+
     /// Records to this handle as a histogram.
     ///
     /// Panics if this handle is not a histogram.
-    pub fn record_histogram(&self, value: u64) {
+    pub fn record_histogram(&self, value: f64) {
         match self {
             Handle::Histogram(bucket) => bucket.push(value),
             _ => panic!("tried to record as histogram"),
@@ -80,6 +147,7 @@ impl Handle {
     pub fn read_counter(&self) -> u64 {
         match self {
             Handle::Counter(counter) => counter.load(Ordering::Relaxed),
+            Handle::ShardedCounter(counter) => counter.value(),
             _ => panic!("tried to read as counter"),
         }
     }
@@ -100,7 +168,7 @@ impl Handle {
     /// Reads this handle as a histogram.
     ///
     /// Panics if this handle is not a histogram.
-    pub fn read_histogram(&self) -> Vec<u64> {
+    pub fn read_histogram(&self) -> Vec<f64> {
         match self {
             Handle::Histogram(bucket) => bucket.data(),
             _ => panic!("tried to read as histogram"),
@@ -115,7 +183,7 @@ impl Handle {
     /// Panics if this handle is not a histogram.
     pub fn read_histogram_with_clear<F>(&self, f: F)
     where
-        F: FnMut(&[u64]),
+        F: FnMut(&[f64]),
     {
         match self {
             Handle::Histogram(bucket) => bucket.clear_with(f),
@@ -123,3 +191,114 @@ impl Handle {
         }
     }
 }
+
+impl CounterFn for Handle {
+    fn increment(&self, value: u64) {
+        self.increment_counter(value)
+    }
+
+    fn absolute(&self, value: u64) {
+        self.absolute_counter(value)
+    }
+}
+
+impl GaugeFn for Handle {
+    fn update(&self, value: f64) {
+        self.update_gauge(value)
+    }
+
+    fn increment(&self, value: f64) {
+        self.increment_gauge(value)
+    }
+
+    fn decrement(&self, value: f64) {
+        self.decrement_gauge(value)
+    }
+}
+
+fn adjust_gauge<F>(gauge: &AtomicU64, f: F)
+where
+    F: Fn(f64) -> f64,
+{
+    // Gauges are stored as the raw bits of an `f64`, so we can't use `fetch_add` directly and
+    // instead have to loop until our compare-and-swap wins.
+    let mut current = gauge.load(Ordering::Relaxed);
+    loop {
+        let new = f(f64::from_bits(current)).to_bits();
+        match gauge.compare_exchange_weak(current, new, Ordering::SeqCst, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+impl HistogramFn for Handle {
+    fn record(&self, value: f64) {
+        self.record_histogram(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Handle;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_counter_absolute() {
+        let handle = Handle::counter();
+        handle.increment_counter(5);
+        handle.absolute_counter(42);
+        assert_eq!(handle.read_counter(), 42);
+
+        // Counters never go backwards.
+        handle.absolute_counter(12);
+        assert_eq!(handle.read_counter(), 42);
+
+        handle.increment_counter(1);
+        assert_eq!(handle.read_counter(), 43);
+    }
+
+    #[test]
+    fn test_sharded_counter() {
+        let handle = Handle::sharded_counter();
+        handle.increment_counter(5);
+        handle.absolute_counter(42);
+        handle.increment_counter(1);
+        assert_eq!(handle.read_counter(), 43);
+    }
+
+    #[test]
+    fn test_gauge_increment_decrement() {
+        let handle = Handle::gauge();
+        handle.update_gauge(10.0);
+        handle.increment_gauge(5.5);
+        handle.decrement_gauge(2.0);
+        assert_eq!(handle.read_gauge(), 13.5);
+    }
+
+    #[test]
+    fn test_gauge_concurrent_increment_decrement() {
+        let handle = Arc::new(Handle::gauge());
+
+        let threads = (0..8)
+            .map(|i| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if i % 2 == 0 {
+                            handle.increment_gauge(2.0);
+                        } else {
+                            handle.decrement_gauge(1.0);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(handle.read_gauge(), 4000.0);
+    }
+}
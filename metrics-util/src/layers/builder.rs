@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+use crate::layers::{FanoutBuilder, GlobalLabelsLayer, PrefixLayer, Stack};
+use metrics::{Label, Recorder};
+
+/// Describes a recorder pipeline to build at runtime with [`StackBuilder`].
+///
+/// With the `layer-stack-config` feature enabled, this type can be deserialized from any format
+/// supported by `serde`, such as TOML, JSON or YAML.  Every field is optional, except that at
+/// least one exporter must be given:
+///
+/// ```toml
+/// prefix = "app"
+/// filter = ["tokio", "bb8"]
+/// exporters = ["prometheus", "tcp"]
+///
+/// [global_labels]
+/// region = "eu-west-1"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "layer-stack-config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "layer-stack-config", serde(default, deny_unknown_fields))]
+pub struct StackConfig {
+    /// Prefix applied to every metric name, as with [`PrefixLayer`].
+    pub prefix: Option<String>,
+
+    /// Patterns of metric names to discard, as with
+    /// [`FilterLayer`](crate::layers::FilterLayer).
+    ///
+    /// Requires the `layer-filter` feature.
+    pub filter: Vec<String>,
+
+    /// Labels added to every metric, as with [`GlobalLabelsLayer`].
+    pub global_labels: BTreeMap<String, String>,
+
+    /// Names of the exporters to send metrics to, as registered with [`StackBuilder::exporter`].
+    pub exporters: Vec<String>,
+}
+
+/// Errors that could occur while building a stack from a [`StackConfig`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StackError {
+    /// No exporters were given.
+    NoExporters,
+
+    /// The given exporter was not registered with the builder.
+    UnknownExporter(String),
+
+    /// Filter patterns were given, but the `layer-filter` feature is not enabled.
+    FilterUnavailable,
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::NoExporters => write!(f, "no exporters were configured"),
+            StackError::UnknownExporter(name) => write!(f, "unknown exporter '{}'", name),
+            StackError::FilterUnavailable => write!(
+                f,
+                "filter patterns were configured but the layer-filter feature is disabled"
+            ),
+        }
+    }
+}
+
+impl Error for StackError {}
+
+type ExporterFactory = Box<dyn Fn() -> Box<dyn Recorder + Send + Sync>>;
+
+/// Builds a [`Stack`] at runtime from a [`StackConfig`].
+///
+/// Exporters are registered by name ahead of time, along with a function to create them, and
+/// the configuration picks which of them to send metrics to.  Metrics are fanned out if more
+/// than one exporter is picked.
+///
+/// Layers are applied such that metrics are first filtered, based on their original name, then
+/// prefixed, and then given the global labels, before being sent to the exporters.
+///
+/// ```rust
+/// # use metrics_util::{layers::{StackBuilder, StackConfig}, DebuggingRecorder};
+/// let builder = StackBuilder::new()
+///     .exporter("debug", DebuggingRecorder::new)
+///     .exporter("other", DebuggingRecorder::new);
+///
+/// let mut config = StackConfig::default();
+/// config.prefix = Some("app".to_string());
+/// config.exporters = vec!["debug".to_string()];
+///
+/// builder
+///     .build(&config)
+///     .expect("invalid stack configuration")
+///     .install()
+///     .expect("failed to install stack");
+/// ```
+#[derive(Default)]
+pub struct StackBuilder {
+    exporters: HashMap<String, ExporterFactory>,
+}
+
+impl StackBuilder {
+    /// Creates an empty `StackBuilder`.
+    pub fn new() -> Self {
+        StackBuilder::default()
+    }
+
+    /// Registers an exporter under the given name.
+    ///
+    /// `factory` is called every time a stack using the exporter is built.
+    pub fn exporter<N, F, R>(mut self, name: N, factory: F) -> StackBuilder
+    where
+        N: Into<String>,
+        F: Fn() -> R + 'static,
+        R: Recorder + Send + Sync + 'static,
+    {
+        let factory = move || Box::new(factory()) as Box<dyn Recorder + Send + Sync>;
+        self.exporters.insert(name.into(), Box::new(factory));
+        self
+    }
+
+    /// Builds a stack as described by `config`.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if `config` gives no exporters, names an exporter that wasn't
+    /// registered, or uses a layer whose feature is disabled.
+    pub fn build(
+        &self,
+        config: &StackConfig,
+    ) -> Result<Stack<Box<dyn Recorder + Send + Sync>>, StackError> {
+        let mut exporters = config
+            .exporters
+            .iter()
+            .map(|name| {
+                self.exporters
+                    .get(name)
+                    .map(|factory| factory())
+                    .ok_or_else(|| StackError::UnknownExporter(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let inner = match exporters.len() {
+            0 => return Err(StackError::NoExporters),
+            1 => exporters.remove(0),
+            _ => {
+                let fanout = exporters
+                    .into_iter()
+                    .fold(FanoutBuilder::default(), |builder, exporter| {
+                        builder.add_recorder(exporter)
+                    });
+                Box::new(fanout.build())
+            }
+        };
+
+        let mut stack = Stack::new(inner);
+        if !config.global_labels.is_empty() {
+            let labels = config
+                .global_labels
+                .iter()
+                .map(|(k, v)| Label::new(k.clone(), v.clone()))
+                .collect::<Vec<_>>();
+            stack = stack.push_boxed(&GlobalLabelsLayer::new(labels));
+        }
+        if let Some(prefix) = &config.prefix {
+            stack = stack.push_boxed(&PrefixLayer::new(prefix.clone()));
+        }
+        if !config.filter.is_empty() {
+            stack = push_filter(stack, &config.filter)?;
+        }
+
+        Ok(stack)
+    }
+}
+
+#[cfg(feature = "layer-filter")]
+fn push_filter(
+    stack: Stack<Box<dyn Recorder + Send + Sync>>,
+    patterns: &[String],
+) -> Result<Stack<Box<dyn Recorder + Send + Sync>>, StackError> {
+    let filter = crate::layers::FilterLayer::from_patterns(patterns.iter());
+    Ok(stack.push_boxed(&filter))
+}
+
+#[cfg(not(feature = "layer-filter"))]
+fn push_filter(
+    _stack: Stack<Box<dyn Recorder + Send + Sync>>,
+    _patterns: &[String],
+) -> Result<Stack<Box<dyn Recorder + Send + Sync>>, StackError> {
+    Err(StackError::FilterUnavailable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StackBuilder, StackConfig, StackError};
+    use crate::debugging::{DebuggingRecorder, Snapshotter};
+    use metrics::{Key, Recorder};
+    use std::sync::{Arc, Mutex};
+
+    type Snapshotters = Arc<Mutex<Vec<(&'static str, Snapshotter)>>>;
+
+    /// Builds a `StackBuilder` whose exporters hand out a snapshotter for every recorder created.
+    fn builder(names: &[&'static str]) -> (StackBuilder, Snapshotters) {
+        let snapshotters = Arc::new(Mutex::new(Vec::new()));
+        let builder = names.iter().fold(StackBuilder::new(), |builder, name| {
+            let name: &'static str = name;
+            let snapshotters = snapshotters.clone();
+            builder.exporter(name, move || {
+                let recorder = DebuggingRecorder::new();
+                snapshotters
+                    .lock()
+                    .unwrap()
+                    .push((name, recorder.snapshotter()));
+                recorder
+            })
+        });
+        (builder, snapshotters)
+    }
+
+    #[test]
+    fn test_basic_functionality() {
+        let (builder, snapshotters) = builder(&["prometheus", "tcp", "unused"]);
+        let config = StackConfig {
+            prefix: Some("app".to_string()),
+            global_labels: vec![("region".to_string(), "eu-west-1".to_string())]
+                .into_iter()
+                .collect(),
+            exporters: vec!["prometheus".to_string(), "tcp".to_string()],
+            ..Default::default()
+        };
+
+        let stack = builder.build(&config).unwrap();
+        stack.increment_counter(Key::Owned("requests".into()), 1);
+
+        let snapshotters = snapshotters.lock().unwrap();
+        let names = snapshotters.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+        assert_eq!(names, vec!["prometheus", "tcp"]);
+
+        for (_, snapshotter) in snapshotters.iter() {
+            let snapshot = snapshotter.snapshot();
+            assert_eq!(snapshot.len(), 1);
+            let (_kind, key, _unit, _desc, _value) = &snapshot[0];
+            assert_eq!(key.name(), "app.requests");
+            let labels = key
+                .labels()
+                .map(|l| (l.key(), l.value()))
+                .collect::<Vec<_>>();
+            assert_eq!(labels, vec![("region", "eu-west-1")]);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let (builder, _) = builder(&["prometheus"]);
+
+        let config = StackConfig::default();
+        assert_eq!(builder.build(&config).err(), Some(StackError::NoExporters));
+
+        let config = StackConfig {
+            exporters: vec!["statsd".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            builder.build(&config).err(),
+            Some(StackError::UnknownExporter("statsd".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_reinstall() {
+        let (builder, snapshotters) = builder(&["prometheus", "tcp"]);
+        let mut config = StackConfig {
+            prefix: Some("app".to_string()),
+            exporters: vec!["prometheus".to_string(), "tcp".to_string()],
+            ..Default::default()
+        };
+
+        builder.build(&config).unwrap().reinstall().unwrap();
+        metrics::increment!("requests");
+
+        config.prefix = Some("worker".to_string());
+        builder.build(&config).unwrap().reinstall().unwrap();
+        metrics::increment!("requests");
+
+        let snapshotters = snapshotters.lock().unwrap();
+        let names = snapshotters
+            .iter()
+            .map(|(_, snapshotter)| {
+                let snapshot = snapshotter.snapshot();
+                assert_eq!(snapshot.len(), 1);
+                snapshot[0].1.name().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "app.requests",
+                "app.requests",
+                "worker.requests",
+                "worker.requests"
+            ]
+        );
+    }
+
+    #[cfg(feature = "layer-filter")]
+    #[test]
+    fn test_filter() {
+        let (builder, snapshotters) = builder(&["prometheus"]);
+        let config = StackConfig {
+            prefix: Some("app".to_string()),
+            filter: vec!["tokio".to_string()],
+            exporters: vec!["prometheus".to_string()],
+            ..Default::default()
+        };
+
+        let stack = builder.build(&config).unwrap();
+        stack.increment_counter(Key::Owned("tokio.loops".into()), 1);
+        stack.increment_counter(Key::Owned("hyper.conns".into()), 1);
+
+        let snapshotters = snapshotters.lock().unwrap();
+        let snapshot = snapshotters[0].1.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].1.name(), "app.hyper.conns");
+    }
+
+    #[cfg(not(feature = "layer-filter"))]
+    #[test]
+    fn test_filter_unavailable() {
+        let (builder, _) = builder(&["prometheus"]);
+        let config = StackConfig {
+            filter: vec!["tokio".to_string()],
+            exporters: vec!["prometheus".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            builder.build(&config).err(),
+            Some(StackError::FilterUnavailable)
+        );
+    }
+
+    #[cfg(feature = "layer-stack-config")]
+    #[test]
+    fn test_config() {
+        let config: StackConfig = toml::from_str(
+            r#"
+            prefix = "app"
+            exporters = ["prometheus"]
+
+            [global_labels]
+            region = "eu-west-1"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.prefix.as_deref(), Some("app"));
+        assert!(config.filter.is_empty());
+        assert_eq!(config.global_labels["region"], "eu-west-1");
+        assert_eq!(config.exporters, vec!["prometheus".to_string()]);
+
+        assert!(toml::from_str::<StackConfig>("exporter = []").is_err());
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::layers::Layer;
+use arc_swap::{ArcSwap, ArcSwapOption};
+use metrics::{Counter, Gauge, Histogram, Key, KeyData, Label, Recorder, SharedString, Unit};
+
+const EMPTY: u64 = 0;
+const TOMBSTONE: u64 = 1;
+const MIN_SLOTS: usize = 16;
+
+/// What to do with a new series once a metric has reached its cardinality limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowBehavior {
+    /// The series is discarded.
+    Drop,
+
+    /// The series is folded into a single series for the metric, labeled `overflow="true"`.
+    Overflow,
+}
+
+impl Default for OverflowBehavior {
+    fn default() -> Self {
+        OverflowBehavior::Drop
+    }
+}
+
+/// The outcome of checking a key against the cardinality limit.
+enum Admission {
+    Accepted(Key),
+    Overflowed(Key),
+    Dropped,
+}
+
+/// A slot of a [`SeriesSet`].
+///
+/// The hash is only set once the key is, and cleared before it is, so a lookup which finds a
+/// matching hash either finds the key it was set along with, or a key which doesn't match.
+struct Slot {
+    hash: AtomicU64,
+    key: ArcSwapOption<Key>,
+}
+
+/// A set of keys which can be checked without taking a lock.
+///
+/// This is an open-addressing table with linear probing, keyed by the hash of each key.  Keys are
+/// compared in full, as their hashes aren't keyed, so distinct keys whose hashes collide are still
+/// told apart.  Lookups only load the current table, so they never block.  Only one caller may
+/// modify the set at a time, which [`CardinalityLimit`] ensures by only doing so while holding its
+/// lock.  A modification either writes a single slot of the current table or swaps in a rebuilt
+/// one; a lookup which races with either may miss the new key, which only sends the caller down
+/// the locked path.
+struct SeriesSet {
+    slots: ArcSwap<Vec<Slot>>,
+}
+
+impl SeriesSet {
+    fn new() -> SeriesSet {
+        SeriesSet {
+            slots: ArcSwap::from_pointee(empty_slots(MIN_SLOTS)),
+        }
+    }
+
+    /// Finds the slot holding the given key, if any.
+    fn find<'a>(slots: &'a [Slot], hash: u64, key: &Key) -> Option<&'a Slot> {
+        let mask = slots.len() - 1;
+        let mut idx = hash as usize & mask;
+        loop {
+            let slot = &slots[idx];
+            match slot.hash.load(Ordering::Acquire) {
+                EMPTY => return None,
+                h if h == hash && slot.key.load().as_deref() == Some(key) => return Some(slot),
+                _ => idx = (idx + 1) & mask,
+            }
+        }
+    }
+
+    fn contains(&self, hash: u64, key: &Key) -> bool {
+        let slots = self.slots.load();
+        SeriesSet::find(&slots, normalize(hash), key).is_some()
+    }
+
+    /// Inserts a key which is known not to be in the set.
+    ///
+    /// `used` counts the slots holding either a key or a tombstone, and `len` the keys alone.
+    fn insert(&self, hash: u64, key: Key, used: &mut usize, len: usize) {
+        let hash = normalize(hash);
+        let mut slots = self.slots.load_full();
+
+        // Keep at least half of the slots empty, so that probes stay short and always terminate.
+        if (*used + 1) * 2 > slots.len() {
+            let capacity = ((len + 1) * 4).next_power_of_two().max(MIN_SLOTS);
+            let rebuilt = empty_slots(capacity);
+            let mask = capacity - 1;
+            for slot in slots.iter() {
+                let h = slot.hash.load(Ordering::Acquire);
+                if h != EMPTY && h != TOMBSTONE {
+                    let mut idx = h as usize & mask;
+                    while rebuilt[idx].hash.load(Ordering::Relaxed) != EMPTY {
+                        idx = (idx + 1) & mask;
+                    }
+                    rebuilt[idx].key.store(slot.key.load_full());
+                    rebuilt[idx].hash.store(h, Ordering::Relaxed);
+                }
+            }
+            slots = Arc::new(rebuilt);
+            self.slots.store(slots.clone());
+            *used = len;
+        }
+
+        let mask = slots.len() - 1;
+        let mut idx = hash as usize & mask;
+        loop {
+            match slots[idx].hash.load(Ordering::Acquire) {
+                EMPTY => {
+                    *used += 1;
+                    break;
+                }
+                TOMBSTONE => break,
+                _ => idx = (idx + 1) & mask,
+            }
+        }
+        slots[idx].key.store(Some(Arc::new(key)));
+        slots[idx].hash.store(hash, Ordering::Release);
+    }
+
+    fn remove(&self, hash: u64, key: &Key) -> bool {
+        let slots = self.slots.load();
+        match SeriesSet::find(&slots, normalize(hash), key) {
+            Some(slot) => {
+                slot.hash.store(TOMBSTONE, Ordering::Release);
+                slot.key.store(None);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn empty_slots(capacity: usize) -> Vec<Slot> {
+    (0..capacity)
+        .map(|_| Slot {
+            hash: AtomicU64::new(EMPTY),
+            key: ArcSwapOption::empty(),
+        })
+        .collect()
+}
+
+/// Moves hashes off the values reserved for empty slots and tombstones.
+fn normalize(hash: u64) -> u64 {
+    if hash <= TOMBSTONE {
+        hash + 2
+    } else {
+        hash
+    }
+}
+
+/// Bookkeeping for the admitted series, only touched when a series is admitted or released.
+#[derive(Default)]
+struct Tracked {
+    per_name: HashMap<SharedString, usize>,
+    used: usize,
+    len: usize,
+}
+
+/// The series admitted by a [`CardinalityLimit`].
+struct Admitted {
+    series: SeriesSet,
+    tracked: Mutex<Tracked>,
+}
+
+impl Admitted {
+    fn release(&self, key: &Key) -> bool {
+        let mut tracked = self.tracked.lock().unwrap();
+        if !self.series.remove(key.get_hash(), key) {
+            return false;
+        }
+
+        tracked.len -= 1;
+        if let Some(count) = tracked.per_name.get_mut(key.name()) {
+            *count -= 1;
+            if *count == 0 {
+                tracked.per_name.remove(key.name());
+            }
+        }
+        true
+    }
+}
+
+/// Limits the number of distinct label sets tracked per metric name.
+///
+/// The first `limit` label sets seen for a metric name are passed through.  Any label set seen
+/// after that is handled according to [`OverflowBehavior`].  Label sets are tracked per metric name
+/// regardless of the kind of the metric.
+///
+/// Admitted label sets count toward the limit until they are released, which should be done
+/// whenever the inner recorder stops tracking a series.  Otherwise, the series keeps its slot
+/// forever.  Series are released with [`release`](CardinalityLimit::release), or with a
+/// [`SeriesReleaser`], which can be handed to an exporter before it's wrapped by the layer, such as
+/// the idle removal callback of the Prometheus exporter.
+///
+/// Every update which exceeds the limit, whether it was discarded or folded into the overflow
+/// series, increments a counter on the inner recorder, labeled with the name of the offending
+/// metric as `metric`.  This counter is named `cardinality_limit_exceeded` by default, and is not
+/// itself subject to the limit.
+///
+/// Updates to series which were already admitted are checked without taking a lock.  Series are
+/// compared by their full key, so keys whose hashes collide, which label values controlled by an
+/// attacker can be crafted to do, still count as separate series.
+pub struct CardinalityLimit<R> {
+    inner: R,
+    limit: usize,
+    behavior: OverflowBehavior,
+    exceeded_name: SharedString,
+    admitted: Arc<Admitted>,
+}
+
+impl<R> CardinalityLimit<R> {
+    /// Releases a series, so that it no longer counts toward the limit of its metric.
+    ///
+    /// Updating the series afterwards admits it again, subject to the limit.  Returns `true` if the
+    /// series had been admitted.
+    pub fn release(&self, key: &Key) -> bool {
+        self.admitted.release(key)
+    }
+}
+
+/// Releases series admitted by the [`CardinalityLimit`]s built from a [`CardinalityLimitLayer`].
+///
+/// Created with [`CardinalityLimitLayer::releaser`], so that series can be released by code which
+/// only has access to the inner recorder, such as an exporter which removes idle series.
+#[derive(Clone)]
+pub struct SeriesReleaser(Arc<Admitted>);
+
+impl SeriesReleaser {
+    /// Releases a series, so that it no longer counts toward the limit of its metric.
+    ///
+    /// See [`CardinalityLimit::release`].
+    pub fn release(&self, key: &Key) -> bool {
+        self.0.release(key)
+    }
+}
+
+impl<R: Recorder> CardinalityLimit<R> {
+    fn admit(&self, key: Key) -> Admission {
+        let hash = key.get_hash();
+        let series = &self.admitted.series;
+        if series.contains(hash, &key) {
+            return Admission::Accepted(key);
+        }
+
+        let admitted = {
+            let mut tracked = self.admitted.tracked.lock().unwrap();
+            let tracked = &mut *tracked;
+            if series.contains(hash, &key) {
+                true
+            } else {
+                let count = tracked.per_name.get(key.name()).copied().unwrap_or(0);
+                if count < self.limit {
+                    series.insert(hash, key.clone(), &mut tracked.used, tracked.len);
+                    tracked.len += 1;
+                    *tracked.per_name.entry(key.name().clone()).or_insert(0) += 1;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+
+        if admitted {
+            return Admission::Accepted(key);
+        }
+
+        let exceeded_key = KeyData::from_parts(
+            self.exceeded_name.clone(),
+            vec![Label::new("metric", key.name().clone())],
+        );
+        self.inner.increment_counter(exceeded_key.into(), 1);
+
+        match self.behavior {
+            OverflowBehavior::Drop => Admission::Dropped,
+            OverflowBehavior::Overflow => {
+                let overflow_key =
+                    KeyData::from_parts(key.name().clone(), vec![Label::new("overflow", "true")]);
+                Admission::Overflowed(overflow_key.into())
+            }
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for CardinalityLimit<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        match self.admit(key) {
+            Admission::Accepted(key) | Admission::Overflowed(key) => {
+                self.inner.register_counter(key, unit, description)
+            }
+            Admission::Dropped => Counter::noop(),
+        }
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        match self.admit(key) {
+            Admission::Accepted(key) | Admission::Overflowed(key) => {
+                self.inner.register_gauge(key, unit, description)
+            }
+            Admission::Dropped => Gauge::noop(),
+        }
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        match self.admit(key) {
+            Admission::Accepted(key) | Admission::Overflowed(key) => {
+                self.inner.register_histogram(key, unit, description)
+            }
+            Admission::Dropped => Histogram::noop(),
+        }
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.increment_counter(key, value);
+        }
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.absolute_counter(key, value);
+        }
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.update_gauge(key, value);
+        }
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.increment_gauge(key, value);
+        }
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.decrement_gauge(key, value);
+        }
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        if let Admission::Accepted(key) | Admission::Overflowed(key) = self.admit(key) {
+            self.inner.record_histogram(key, value);
+        }
+    }
+}
+
+/// A layer for limiting the number of distinct label sets tracked per metric name.
+///
+/// More information on the behavior of the layer can be found in [`CardinalityLimit`].
+///
+/// Every [`CardinalityLimit`] built from the same layer shares the same set of admitted series.
+pub struct CardinalityLimitLayer {
+    limit: usize,
+    behavior: OverflowBehavior,
+    exceeded_name: SharedString,
+    admitted: Arc<Admitted>,
+}
+
+impl CardinalityLimitLayer {
+    /// Creates a new `CardinalityLimitLayer` allowing up to `limit` label sets per metric name.
+    pub fn new(limit: usize) -> CardinalityLimitLayer {
+        CardinalityLimitLayer {
+            limit,
+            behavior: OverflowBehavior::Drop,
+            exceeded_name: SharedString::const_str("cardinality_limit_exceeded"),
+            admitted: Arc::new(Admitted {
+                series: SeriesSet::new(),
+                tracked: Mutex::new(Tracked::default()),
+            }),
+        }
+    }
+
+    /// Sets what to do with a new series once a metric has reached its limit.
+    ///
+    /// Defaults to [`OverflowBehavior::Drop`].
+    pub fn overflow_behavior(&mut self, behavior: OverflowBehavior) -> &mut CardinalityLimitLayer {
+        self.behavior = behavior;
+        self
+    }
+
+    /// Sets the name of the counter incremented whenever an update exceeds the limit.
+    ///
+    /// Defaults to `cardinality_limit_exceeded`.
+    pub fn exceeded_counter_name<N>(&mut self, name: N) -> &mut CardinalityLimitLayer
+    where
+        N: Into<SharedString>,
+    {
+        self.exceeded_name = name.into();
+        self
+    }
+
+    /// Gets a [`SeriesReleaser`] for the series admitted by the recorders built from this layer.
+    pub fn releaser(&self) -> SeriesReleaser {
+        SeriesReleaser(self.admitted.clone())
+    }
+}
+
+impl<R> Layer<R> for CardinalityLimitLayer {
+    type Output = CardinalityLimit<R>;
+
+    fn layer(&self, inner: R) -> Self::Output {
+        CardinalityLimit {
+            inner,
+            limit: self.limit,
+            behavior: self.behavior,
+            exceeded_name: self.exceeded_name.clone(),
+            admitted: self.admitted.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CardinalityLimitLayer, OverflowBehavior, SeriesSet};
+    use crate::debugging::{DebugValue, DebuggingRecorder};
+    use crate::layers::Layer;
+    use metrics::{Key, KeyData, Label, Recorder};
+
+    fn user_key(id: u64) -> Key {
+        KeyData::from_parts("logins", vec![Label::new("user_id", id.to_string())]).into()
+    }
+
+    fn render(key: &Key) -> String {
+        let labels = key
+            .labels()
+            .map(|l| format!("{}={}", l.key(), l.value()))
+            .collect::<Vec<_>>();
+        format!("{}{{{}}}", key.name(), labels.join(","))
+    }
+
+    #[test]
+    fn test_drop() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let layered = CardinalityLimitLayer::new(2).layer(recorder);
+
+        for id in 0..4 {
+            layered.increment_counter(user_key(id), 1);
+        }
+        // Series which were already admitted keep being updated.
+        layered.increment_counter(user_key(0), 1);
+        layered
+            .register_counter(user_key(5), None, None)
+            .increment(1);
+        layered.increment_counter(KeyData::from_name("logouts").into(), 1);
+
+        let mut after = snapshotter
+            .snapshot()
+            .into_iter()
+            .map(|(_kind, key, _unit, _desc, value)| (render(&key), value))
+            .collect::<Vec<_>>();
+        after.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            after,
+            vec![
+                (
+                    "cardinality_limit_exceeded{metric=logins}".to_string(),
+                    DebugValue::Counter(3)
+                ),
+                ("logins{user_id=0}".to_string(), DebugValue::Counter(2)),
+                ("logins{user_id=1}".to_string(), DebugValue::Counter(1)),
+                ("logouts{}".to_string(), DebugValue::Counter(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut layer = CardinalityLimitLayer::new(1);
+        layer
+            .overflow_behavior(OverflowBehavior::Overflow)
+            .exceeded_counter_name("series_overflowed");
+        let layered = layer.layer(recorder);
+
+        for id in 0..4 {
+            layered.increment_counter(user_key(id), 1);
+        }
+
+        let mut after = snapshotter
+            .snapshot()
+            .into_iter()
+            .map(|(_kind, key, _unit, _desc, value)| (render(&key), value))
+            .collect::<Vec<_>>();
+        after.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            after,
+            vec![
+                ("logins{overflow=true}".to_string(), DebugValue::Counter(3)),
+                ("logins{user_id=0}".to_string(), DebugValue::Counter(1)),
+                (
+                    "series_overflowed{metric=logins}".to_string(),
+                    DebugValue::Counter(3)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let layered = CardinalityLimitLayer::new(1).layer(recorder);
+
+        layered.increment_counter(user_key(0), 1);
+        layered.increment_counter(user_key(1), 1);
+
+        // Releasing the admitted series frees its slot for the next one.
+        assert!(layered.release(&user_key(0)));
+        assert!(!layered.release(&user_key(0)));
+        assert!(!layered.release(&user_key(1)));
+        layered.increment_counter(user_key(1), 1);
+        layered.increment_counter(user_key(0), 1);
+
+        let mut after = snapshotter
+            .snapshot()
+            .into_iter()
+            .map(|(_kind, key, _unit, _desc, value)| (render(&key), value))
+            .collect::<Vec<_>>();
+        after.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            after,
+            vec![
+                (
+                    "cardinality_limit_exceeded{metric=logins}".to_string(),
+                    DebugValue::Counter(2)
+                ),
+                ("logins{user_id=0}".to_string(), DebugValue::Counter(1)),
+                ("logins{user_id=1}".to_string(), DebugValue::Counter(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_series_set() {
+        let set = SeriesSet::new();
+        let (mut used, mut len) = (0, 0);
+
+        // Includes the values reserved for empty slots and tombstones, and enough keys to grow.
+        let hashes = || (0..2u64).chain(10..1000);
+        for hash in hashes() {
+            assert!(!set.contains(hash, &user_key(hash)));
+            set.insert(hash, user_key(hash), &mut used, len);
+            len += 1;
+        }
+        for hash in hashes().filter(|h| h % 2 == 0) {
+            assert!(set.remove(hash, &user_key(hash)));
+            len -= 1;
+        }
+        for hash in hashes() {
+            let contained = set.contains(hash, &user_key(hash));
+            assert_eq!(contained, hash % 2 == 1, "hash {}", hash);
+        }
+
+        // Tombstones are reused, and dropped when the table is rebuilt.
+        for hash in 1000..3000u64 {
+            set.insert(hash, user_key(hash), &mut used, len);
+            len += 1;
+        }
+        assert!((1000..3000u64).all(|h| set.contains(h, &user_key(h))));
+        assert!(used <= set.slots.load().len() / 2);
+    }
+
+    #[test]
+    fn test_series_set_collisions() {
+        let set = SeriesSet::new();
+        let (mut used, mut len) = (0, 0);
+
+        // Keys are told apart even when their hashes collide.
+        set.insert(42, user_key(0), &mut used, len);
+        len += 1;
+        assert!(set.contains(42, &user_key(0)));
+        assert!(!set.contains(42, &user_key(1)));
+        assert!(!set.remove(42, &user_key(1)));
+
+        set.insert(42, user_key(1), &mut used, len);
+        assert!(set.remove(42, &user_key(0)));
+        assert!(!set.contains(42, &user_key(0)));
+        assert!(set.contains(42, &user_key(1)));
+    }
+
+    #[test]
+    fn test_releaser() {
+        let layer = CardinalityLimitLayer::new(1);
+        let releaser = layer.releaser();
+        let layered = layer.layer(DebuggingRecorder::new());
+
+        layered.increment_counter(user_key(0), 1);
+        assert!(releaser.release(&user_key(0)));
+        assert!(!layered.release(&user_key(0)));
+    }
+}
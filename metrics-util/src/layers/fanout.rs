@@ -1,43 +1,204 @@
-use metrics::{Key, Recorder, Unit};
+use std::sync::Arc;
+
+use crate::MetricKind;
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, Recorder, SharedString, Unit,
+};
+
+/// Decides whether a metric, given its kind and name, is routed to a recorder.
+type Predicate = Box<dyn Fn(MetricKind, &str) -> bool + Send + Sync>;
+
+struct Route {
+    recorder: Box<dyn Recorder + Send + Sync>,
+    predicate: Option<Predicate>,
+}
+
+impl Route {
+    fn accepts(&self, kind: MetricKind, name: &str) -> bool {
+        match &self.predicate {
+            Some(predicate) => predicate(kind, name),
+            None => true,
+        }
+    }
+}
+
+struct FanoutCounter {
+    counters: Vec<Counter>,
+}
+
+impl CounterFn for FanoutCounter {
+    fn increment(&self, value: u64) {
+        for counter in &self.counters {
+            counter.increment(value);
+        }
+    }
+
+    fn absolute(&self, value: u64) {
+        for counter in &self.counters {
+            counter.absolute(value);
+        }
+    }
+}
+
+struct FanoutGauge {
+    gauges: Vec<Gauge>,
+}
+
+impl GaugeFn for FanoutGauge {
+    fn update(&self, value: f64) {
+        for gauge in &self.gauges {
+            gauge.update(value);
+        }
+    }
+
+    fn increment(&self, value: f64) {
+        for gauge in &self.gauges {
+            gauge.increment(value);
+        }
+    }
+
+    fn decrement(&self, value: f64) {
+        for gauge in &self.gauges {
+            gauge.decrement(value);
+        }
+    }
+}
+
+struct FanoutHistogram {
+    histograms: Vec<Histogram>,
+}
+
+impl HistogramFn for FanoutHistogram {
+    fn record(&self, value: f64) {
+        for histogram in &self.histograms {
+            histogram.record(value);
+        }
+    }
+}
 
 /// Fans out metrics to multiple recorders.
+///
+/// Recorders can be added with a routing predicate, in which case they only receive the metrics,
+/// and the descriptions, for which the predicate returns `true`.  Recorders added without one
+/// receive everything.
+///
+/// Handles returned from registration fan out as well, updating the handles of every inner
+/// recorder the metric was routed to.
 pub struct Fanout {
-    recorders: Vec<Box<dyn Recorder>>,
+    routes: Vec<Route>,
+}
+
+impl Fanout {
+    fn recorders<'a>(
+        &'a self,
+        kind: MetricKind,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a dyn Recorder> + 'a {
+        self.routes
+            .iter()
+            .filter(move |route| route.accepts(kind, name))
+            .map(|route| route.recorder.as_ref() as &dyn Recorder)
+    }
 }
 
 impl Recorder for Fanout {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        for recorder in &self.recorders {
-            recorder.register_counter(key.clone(), unit.clone(), description);
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        let counters = self
+            .recorders(MetricKind::Counter, key.name())
+            .map(|recorder| recorder.register_counter(key.clone(), unit.clone(), description))
+            .collect();
+
+        Counter::from_arc(Arc::new(FanoutCounter { counters }))
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        let gauges = self
+            .recorders(MetricKind::Gauge, key.name())
+            .map(|recorder| recorder.register_gauge(key.clone(), unit.clone(), description))
+            .collect();
+
+        Gauge::from_arc(Arc::new(FanoutGauge { gauges }))
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        let histograms = self
+            .recorders(MetricKind::Histogram, key.name())
+            .map(|recorder| recorder.register_histogram(key.clone(), unit.clone(), description))
+            .collect();
+
+        Histogram::from_arc(Arc::new(FanoutHistogram { histograms }))
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        for recorder in self.recorders(MetricKind::Counter, &name) {
+            recorder.describe_counter(name.clone(), unit.clone(), description.clone());
         }
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        for recorder in &self.recorders {
-            recorder.register_gauge(key.clone(), unit.clone(), description);
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        for recorder in self.recorders(MetricKind::Gauge, &name) {
+            recorder.describe_gauge(name.clone(), unit.clone(), description.clone());
         }
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        for recorder in &self.recorders {
-            recorder.register_histogram(key.clone(), unit.clone(), description);
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        for recorder in self.recorders(MetricKind::Histogram, &name) {
+            recorder.describe_histogram(name.clone(), unit.clone(), description.clone());
         }
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
-        for recorder in &self.recorders {
+        for recorder in self.recorders(MetricKind::Counter, key.name()) {
             recorder.increment_counter(key.clone(), value);
         }
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        for recorder in self.recorders(MetricKind::Counter, key.name()) {
+            recorder.absolute_counter(key.clone(), value);
+        }
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
-        for recorder in &self.recorders {
+        for recorder in self.recorders(MetricKind::Gauge, key.name()) {
             recorder.update_gauge(key.clone(), value);
         }
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
-        for recorder in &self.recorders {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        for recorder in self.recorders(MetricKind::Gauge, key.name()) {
+            recorder.increment_gauge(key.clone(), value);
+        }
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        for recorder in self.recorders(MetricKind::Gauge, key.name()) {
+            recorder.decrement_gauge(key.clone(), value);
+        }
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        for recorder in self.recorders(MetricKind::Histogram, key.name()) {
             recorder.record_histogram(key.clone(), value);
         }
     }
@@ -48,23 +209,52 @@ impl Recorder for Fanout {
 /// More information on the behavior of the layer can be found in [`Fanout`].
 #[derive(Default)]
 pub struct FanoutBuilder {
-    recorders: Vec<Box<dyn Recorder>>,
+    routes: Vec<Route>,
 }
 
 impl FanoutBuilder {
     /// Adds a recorder to the fanout list.
     pub fn add_recorder<R>(mut self, recorder: R) -> FanoutBuilder
     where
-        R: Recorder + 'static,
+        R: Recorder + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            recorder: Box::new(recorder),
+            predicate: None,
+        });
+        self
+    }
+
+    /// Adds a recorder to the fanout list, which only receives the metrics matching `predicate`.
+    ///
+    /// The predicate is given the kind and the name of the metric.  For example, a recorder which
+    /// only needs histograms could be added like so:
+    ///
+    /// ```rust
+    /// # use metrics_util::{layers::FanoutBuilder, DebuggingRecorder, MetricKind};
+    /// let fanout = FanoutBuilder::default()
+    ///     .add_recorder(DebuggingRecorder::new())
+    ///     .add_routed_recorder(DebuggingRecorder::new(), |kind, name| {
+    ///         kind == MetricKind::Histogram && name.starts_with("http.")
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn add_routed_recorder<R, F>(mut self, recorder: R, predicate: F) -> FanoutBuilder
+    where
+        R: Recorder + Send + Sync + 'static,
+        F: Fn(MetricKind, &str) -> bool + Send + Sync + 'static,
     {
-        self.recorders.push(Box::new(recorder));
+        self.routes.push(Route {
+            recorder: Box::new(recorder),
+            predicate: Some(Box::new(predicate)),
+        });
         self
     }
 
     /// Builds the `Fanout` layer.
     pub fn build(self) -> Fanout {
         Fanout {
-            recorders: self.recorders,
+            routes: self.routes,
         }
     }
 }
@@ -72,7 +262,8 @@ impl FanoutBuilder {
 #[cfg(test)]
 mod tests {
     use super::FanoutBuilder;
-    use crate::debugging::DebuggingRecorder;
+    use crate::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+    use crate::MetricKind;
     use metrics::{Key, Recorder, Unit};
 
     #[test]
@@ -104,7 +295,9 @@ mod tests {
             Some(ud[1].1),
         );
         fanout.increment_counter(Key::Owned("tokio.loops".into()), 47);
-        fanout.update_gauge(Key::Owned("hyper.sent_bytes".into()), 12.0);
+        fanout.update_gauge(Key::Owned("hyper.sent_bytes".into()), 10.0);
+        fanout.increment_gauge(Key::Owned("hyper.sent_bytes".into()), 3.0);
+        fanout.decrement_gauge(Key::Owned("hyper.sent_bytes".into()), 1.0);
 
         let after1 = snapshotter1.snapshot();
         let after2 = snapshotter2.snapshot();
@@ -124,8 +317,147 @@ mod tests {
             assert_eq!(v1, v2);
             assert_eq!(Some(ud[i].0.clone()), u1);
             assert_eq!(Some(ud[i].0.clone()), u2);
-            assert_eq!(Some(ud[i].1), d1);
-            assert_eq!(Some(ud[i].1), d2);
+            assert_eq!(Some(ud[i].1), d1.as_deref());
+            assert_eq!(Some(ud[i].1), d2.as_deref());
+        }
+    }
+
+    #[test]
+    fn test_handles() {
+        let recorder1 = DebuggingRecorder::new();
+        let snapshotter1 = recorder1.snapshotter();
+        let recorder2 = DebuggingRecorder::new();
+        let snapshotter2 = recorder2.snapshotter();
+        let fanout = FanoutBuilder::default()
+            .add_recorder(recorder1)
+            .add_recorder(recorder2)
+            .build();
+
+        let counter = fanout.register_counter(Key::Owned("tokio.loops".into()), None, None);
+        let gauge = fanout.register_gauge(Key::Owned("hyper.sent_bytes".into()), None, None);
+        let histogram = fanout.register_histogram(Key::Owned("hyper.latency".into()), None, None);
+        counter.increment(47);
+        gauge.update(10.0);
+        gauge.increment(3.0);
+        gauge.decrement(1.0);
+        histogram.record(3);
+        histogram.record(5);
+
+        for snapshotter in &[snapshotter1, snapshotter2] {
+            let values = snapshotter
+                .snapshot()
+                .into_iter()
+                .map(|(_, _, _, _, value)| value)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                values,
+                vec![
+                    DebugValue::Counter(47),
+                    DebugValue::Gauge(12.0),
+                    DebugValue::Histogram(vec![3.0, 5.0]),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let recorder1 = DebuggingRecorder::new();
+        let snapshotter1 = recorder1.snapshotter();
+        let recorder2 = DebuggingRecorder::new();
+        let snapshotter2 = recorder2.snapshotter();
+        let fanout = FanoutBuilder::default()
+            .add_recorder(recorder1)
+            .add_recorder(recorder2)
+            .build();
+
+        fanout.describe_counter(
+            "tokio.loops".into(),
+            Some(Unit::Count),
+            format!("loops run by {}", "tokio").into(),
+        );
+
+        // Describing a metric doesn't create it.
+        assert_eq!(snapshotter1.snapshot().len(), 0);
+        assert_eq!(snapshotter2.snapshot().len(), 0);
+
+        fanout.increment_counter(Key::Owned("tokio.loops".into()), 1);
+
+        for snapshotter in &[snapshotter1, snapshotter2] {
+            let snapshot = snapshotter.snapshot();
+            assert_eq!(snapshot.len(), 1);
+            let (_, _, unit, desc, _) = &snapshot[0];
+            assert_eq!(unit, &Some(Unit::Count));
+            assert_eq!(desc.as_deref(), Some("loops run by tokio"));
         }
     }
+
+    #[test]
+    fn test_routing() {
+        let recorder1 = DebuggingRecorder::new();
+        let snapshotter1 = recorder1.snapshotter();
+        let recorder2 = DebuggingRecorder::new();
+        let snapshotter2 = recorder2.snapshotter();
+        let fanout = FanoutBuilder::default()
+            .add_recorder(recorder1)
+            .add_routed_recorder(recorder2, |kind, name| {
+                kind == MetricKind::Counter || name.starts_with("db.")
+            })
+            .build();
+
+        fanout.describe_histogram("http.latency".into(), Some(Unit::Seconds), "".into());
+        fanout.increment_counter(Key::Owned("http.requests".into()), 1);
+        fanout.record_histogram(Key::Owned("http.latency".into()), 1.0);
+        fanout.record_histogram(Key::Owned("db.latency".into()), 1.0);
+        let histogram = fanout.register_histogram(Key::Owned("http.latency".into()), None, None);
+        histogram.record(2.0);
+
+        let names = |snapshotter: &Snapshotter| {
+            snapshotter
+                .snapshot()
+                .into_iter()
+                .map(|(kind, key, unit, _, value)| (kind, key.name().to_string(), unit, value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&snapshotter1),
+            vec![
+                (
+                    MetricKind::Counter,
+                    "http.requests".to_string(),
+                    None,
+                    DebugValue::Counter(1)
+                ),
+                (
+                    MetricKind::Histogram,
+                    "http.latency".to_string(),
+                    Some(Unit::Seconds),
+                    DebugValue::Histogram(vec![1.0, 2.0])
+                ),
+                (
+                    MetricKind::Histogram,
+                    "db.latency".to_string(),
+                    None,
+                    DebugValue::Histogram(vec![1.0])
+                ),
+            ]
+        );
+        assert_eq!(
+            names(&snapshotter2),
+            vec![
+                (
+                    MetricKind::Counter,
+                    "http.requests".to_string(),
+                    None,
+                    DebugValue::Counter(1)
+                ),
+                (
+                    MetricKind::Histogram,
+                    "db.latency".to_string(),
+                    None,
+                    DebugValue::Histogram(vec![1.0])
+                ),
+            ]
+        );
+    }
 }
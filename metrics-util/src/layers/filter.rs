@@ -1,72 +1,316 @@
-use crate::layers::Layer;
+use crate::layers::{glob::glob_match, Layer};
+use crate::MetricKind;
 use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
-use metrics::{Key, Recorder, Unit};
+use metrics::{Counter, Gauge, Histogram, Key, Label, Recorder, SharedString, Unit};
 
-/// Filters and discards metrics matching certain name patterns.
+#[cfg(feature = "layer-filter-regex")]
+use regex::Regex;
+
+/// Whether metrics matching a filter are discarded or kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Metrics that match are discarded, and all other metrics are passed through.
+    Deny,
+
+    /// Metrics that match are passed through, and all other metrics are discarded.
+    Allow,
+}
+
+impl Default for FilterMode {
+    fn default() -> Self {
+        FilterMode::Deny
+    }
+}
+
+#[derive(Clone)]
+enum Condition {
+    NameContains(String),
+    NameGlob(String),
+    #[cfg(feature = "layer-filter-regex")]
+    NameRegex(Regex),
+    LabelKey(String),
+    Label(String, String),
+}
+
+impl Condition {
+    /// Evaluates the condition against a metric.
+    ///
+    /// Returns `None` if the condition depends on labels and no labels were given, which is the
+    /// case when a metric is described rather than registered or updated.
+    fn matches(&self, name: &str, labels: Option<&[Label]>) -> Option<bool> {
+        match self {
+            Condition::NameContains(pattern) => Some(name.contains(pattern.as_str())),
+            Condition::NameGlob(pattern) => Some(glob_match(pattern, name)),
+            #[cfg(feature = "layer-filter-regex")]
+            Condition::NameRegex(regex) => Some(regex.is_match(name)),
+            Condition::LabelKey(key) => labels.map(|labels| labels.iter().any(|l| l.key() == key)),
+            Condition::Label(key, value) => {
+                labels.map(|labels| labels.iter().any(|l| l.key() == key && l.value() == value))
+            }
+        }
+    }
+}
+
+/// A rule for matching metrics in [`FilterLayer`].
 ///
-/// Uses an Aho-Corasick automaton to efficiently match a metric key against multiple patterns at
-/// once.  Patterns are matched across the entire key i.e. they are matched as substrings.
+/// A rule matches a metric when the metric satisfies every condition added to the rule, and, if a
+/// kind was set, is of that kind.  A rule with no conditions matches every metric of its kind, or
+/// every metric at all if no kind was set.
+///
+/// Name conditions are always case sensitive.
+#[derive(Clone, Default)]
+pub struct FilterRule {
+    kind: Option<MetricKind>,
+    conditions: Vec<Condition>,
+}
+
+impl FilterRule {
+    /// Creates an empty `FilterRule`.
+    pub fn new() -> Self {
+        FilterRule::default()
+    }
+
+    /// Restricts the rule to metrics of the given kind.
+    pub fn kind(mut self, kind: MetricKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Requires the metric name to contain the given substring.
+    pub fn name_contains<P: AsRef<str>>(mut self, pattern: P) -> Self {
+        self.conditions
+            .push(Condition::NameContains(pattern.as_ref().to_string()));
+        self
+    }
+
+    /// Requires the metric name to match the given glob pattern.
+    ///
+    /// The pattern must match the entire name.  `*` matches any sequence of characters, including
+    /// an empty one, and `?` matches exactly one character.
+    pub fn name_glob<P: AsRef<str>>(mut self, pattern: P) -> Self {
+        self.conditions
+            .push(Condition::NameGlob(pattern.as_ref().to_string()));
+        self
+    }
+
+    /// Requires the metric name to match the given regular expression.
+    ///
+    /// As with [`Regex::is_match`], the expression can match anywhere in the name unless it is
+    /// anchored.
+    #[cfg(feature = "layer-filter-regex")]
+    pub fn name_regex(mut self, regex: Regex) -> Self {
+        self.conditions.push(Condition::NameRegex(regex));
+        self
+    }
+
+    /// Requires the metric to have a label with the given key, regardless of its value.
+    pub fn label_key<K: AsRef<str>>(mut self, key: K) -> Self {
+        self.conditions
+            .push(Condition::LabelKey(key.as_ref().to_string()));
+        self
+    }
+
+    /// Requires the metric to have a label with the given key and value.
+    pub fn label<K: AsRef<str>, V: AsRef<str>>(mut self, key: K, value: V) -> Self {
+        self.conditions.push(Condition::Label(
+            key.as_ref().to_string(),
+            value.as_ref().to_string(),
+        ));
+        self
+    }
+
+    /// Evaluates the rule against a metric.
+    ///
+    /// Returns `None` if the outcome depends on labels and no labels were given.
+    fn matches(&self, kind: MetricKind, name: &str, labels: Option<&[Label]>) -> Option<bool> {
+        match self.kind {
+            Some(k) if k != kind => return Some(false),
+            _ => {}
+        }
+
+        let mut result = Some(true);
+        for condition in &self.conditions {
+            match condition.matches(name, labels) {
+                Some(false) => return Some(false),
+                None => result = None,
+                Some(true) => {}
+            }
+        }
+        result
+    }
+}
+
+/// Filters and discards metrics matching certain name patterns, labels or kinds.
+///
+/// Name patterns use an Aho-Corasick automaton to efficiently match a metric key against multiple
+/// patterns at once.  Patterns are matched across the entire key i.e. they are matched as
+/// substrings.  More specific matching, such as by label or by metric kind, is configured with
+/// [`FilterRule`].  A metric matches the filter if it matches any name pattern or any rule.
+///
+/// In [`FilterMode::Deny`] mode, the default, matching metrics are discarded.  In
+/// [`FilterMode::Allow`] mode, only matching metrics are passed through.
+///
+/// Descriptions carry no labels, so rules that depend on labels cannot be evaluated for them.  A
+/// description is discarded in deny mode only if a name pattern or a rule without label conditions
+/// matches it, and kept in allow mode if any rule could match a metric with that name.
 pub struct Filter<R> {
     inner: R,
     automaton: AhoCorasick,
+    rules: Vec<FilterRule>,
+    mode: FilterMode,
 }
 
 impl<R> Filter<R> {
-    fn should_filter(&self, key: &Key) -> bool {
-        self.automaton.is_match(key.name().as_ref())
+    fn should_filter(&self, kind: MetricKind, key: &Key) -> bool {
+        let name: &str = key.name();
+        let labels = key.labels().as_slice();
+        let matched = self.automaton.is_match(name)
+            || self
+                .rules
+                .iter()
+                .any(|r| r.matches(kind, name, Some(labels)) == Some(true));
+
+        match self.mode {
+            FilterMode::Deny => matched,
+            FilterMode::Allow => !matched,
+        }
+    }
+
+    fn should_filter_name(&self, kind: MetricKind, name: &str) -> bool {
+        let name_matched = self.automaton.is_match(name);
+        match self.mode {
+            FilterMode::Deny => {
+                name_matched
+                    || self
+                        .rules
+                        .iter()
+                        .any(|r| r.matches(kind, name, None) == Some(true))
+            }
+            FilterMode::Allow => {
+                !name_matched
+                    && self
+                        .rules
+                        .iter()
+                        .all(|r| r.matches(kind, name, None) == Some(false))
+            }
+        }
     }
 }
 
 impl<R: Recorder> Recorder for Filter<R> {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        if self.should_filter(&key) {
-            return;
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        if self.should_filter(MetricKind::Counter, &key) {
+            return Counter::noop();
         }
         self.inner.register_counter(key, unit, description)
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        if self.should_filter(&key) {
-            return;
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        if self.should_filter(MetricKind::Gauge, &key) {
+            return Gauge::noop();
         }
         self.inner.register_gauge(key, unit, description)
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        if self.should_filter(&key) {
-            return;
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        if self.should_filter(MetricKind::Histogram, &key) {
+            return Histogram::noop();
         }
         self.inner.register_histogram(key, unit, description)
     }
 
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        if self.should_filter_name(MetricKind::Counter, &name) {
+            return;
+        }
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        if self.should_filter_name(MetricKind::Gauge, &name) {
+            return;
+        }
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        if self.should_filter_name(MetricKind::Histogram, &name) {
+            return;
+        }
+        self.inner.describe_histogram(name, unit, description)
+    }
+
     fn increment_counter(&self, key: Key, value: u64) {
-        if self.should_filter(&key) {
+        if self.should_filter(MetricKind::Counter, &key) {
             return;
         }
         self.inner.increment_counter(key, value);
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        if self.should_filter(MetricKind::Counter, &key) {
+            return;
+        }
+        self.inner.absolute_counter(key, value);
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
-        if self.should_filter(&key) {
+        if self.should_filter(MetricKind::Gauge, &key) {
             return;
         }
         self.inner.update_gauge(key, value);
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
-        if self.should_filter(&key) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        if self.should_filter(MetricKind::Gauge, &key) {
+            return;
+        }
+        self.inner.increment_gauge(key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        if self.should_filter(MetricKind::Gauge, &key) {
+            return;
+        }
+        self.inner.decrement_gauge(key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        if self.should_filter(MetricKind::Histogram, &key) {
             return;
         }
         self.inner.record_histogram(key, value);
     }
 }
 
-/// A layer for filtering and discarding metrics matching certain name patterns.
+/// A layer for filtering and discarding metrics matching certain name patterns, labels or kinds.
 ///
 /// More information on the behavior of the layer can be found in [`Filter`].
 #[derive(Default)]
 pub struct FilterLayer {
     patterns: Vec<String>,
+    rules: Vec<FilterRule>,
+    mode: FilterMode,
     case_insensitive: bool,
     use_dfa: bool,
 }
@@ -80,6 +324,8 @@ impl FilterLayer {
     {
         FilterLayer {
             patterns: patterns.map(|s| s.as_ref().to_string()).collect(),
+            rules: Vec::new(),
+            mode: FilterMode::Deny,
             case_insensitive: false,
             use_dfa: true,
         }
@@ -94,8 +340,42 @@ impl FilterLayer {
         self
     }
 
+    /// Adds a rule to match.
+    pub fn add_rule(&mut self, rule: FilterRule) -> &mut FilterLayer {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Matches any metric with a label with the given key, regardless of its value.
+    pub fn add_label_key<K>(&mut self, key: K) -> &mut FilterLayer
+    where
+        K: AsRef<str>,
+    {
+        self.add_rule(FilterRule::new().label_key(key))
+    }
+
+    /// Matches any metric with a label with the given key and value.
+    pub fn add_label<K, V>(&mut self, key: K, value: V) -> &mut FilterLayer
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.add_rule(FilterRule::new().label(key, value))
+    }
+
+    /// Sets whether matching metrics are discarded or kept.
+    ///
+    /// Defaults to [`FilterMode::Deny`] i.e. matching metrics are discarded.
+    pub fn mode(&mut self, mode: FilterMode) -> &mut FilterLayer {
+        self.mode = mode;
+        self
+    }
+
     /// Sets the case sensitivity used for pattern matching.
     ///
+    /// Only applies to patterns added via [`FilterLayer::add_pattern`] or
+    /// [`FilterLayer::from_patterns`].
+    ///
     /// Defaults to `false` i.e. searches are case sensitive.
     pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut FilterLayer {
         self.case_insensitive = case_insensitive;
@@ -126,16 +406,22 @@ impl<R> Layer<R> for FilterLayer {
             .dfa(self.use_dfa)
             .auto_configure(&self.patterns)
             .build(&self.patterns);
-        Filter { inner, automaton }
+        Filter {
+            inner,
+            automaton,
+            rules: self.rules.clone(),
+            mode: self.mode,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FilterLayer;
+    use super::{FilterLayer, FilterMode, FilterRule};
     use crate::debugging::DebuggingRecorder;
     use crate::layers::Layer;
-    use metrics::{Key, Recorder, Unit};
+    use crate::MetricKind;
+    use metrics::{Key, KeyData, Label, Recorder, Unit};
 
     #[test]
     fn test_basic_functionality() {
@@ -190,7 +476,7 @@ mod tests {
             // We cheat here since we're not comparing one-to-one with the source data,
             // but we know which metrics are going to make it through so we can hard code.
             assert_eq!(Some(Unit::Bytes), unit);
-            assert!(!desc.unwrap().is_empty() && desc.unwrap() == "gauge desc");
+            assert_eq!(desc.as_deref(), Some("gauge desc"));
         }
     }
 
@@ -222,4 +508,142 @@ mod tests {
             );
         }
     }
+
+    fn labeled(name: &'static str, labels: &[(&'static str, &'static str)]) -> Key {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| Label::new(*k, *v))
+            .collect::<Vec<_>>();
+        Key::Owned(KeyData::from_parts(name, labels))
+    }
+
+    #[test]
+    fn test_labels() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::default();
+        filter.add_label_key("tenant_id").add_label("env", "dev");
+        let layered = filter.layer(recorder);
+
+        layered.increment_counter(labeled("requests", &[("tenant_id", "42")]), 1);
+        layered.increment_counter(labeled("requests", &[("env", "dev")]), 1);
+        layered.increment_counter(labeled("requests", &[("env", "prod")]), 1);
+        layered.increment_counter(labeled("requests", &[]), 1);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 2);
+
+        for (_kind, key, _unit, _desc, _value) in &after {
+            assert!(key
+                .labels()
+                .all(|l| l.key() != "tenant_id" && l.value() != "dev"));
+        }
+    }
+
+    #[test]
+    fn test_allow_mode() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::from_patterns(["http"].iter());
+        filter.add_label("env", "prod").mode(FilterMode::Allow);
+        let layered = filter.layer(recorder);
+
+        layered.update_gauge(labeled("http.conns", &[]), 1.0);
+        layered.update_gauge(labeled("db.conns", &[("env", "prod")]), 1.0);
+        layered.update_gauge(labeled("db.conns", &[("env", "dev")]), 1.0);
+        layered.update_gauge(labeled("cache.size", &[]), 1.0);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 2);
+
+        for (_kind, key, _unit, _desc, _value) in &after {
+            assert!(key.name().contains("http") || key.labels().any(|l| l.value() == "prod"));
+        }
+    }
+
+    #[test]
+    fn test_kind_rules() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::default();
+        filter.add_rule(
+            FilterRule::new()
+                .kind(MetricKind::Histogram)
+                .name_glob("http.*"),
+        );
+        let layered = filter.layer(recorder);
+
+        layered.increment_counter(labeled("http.requests", &[]), 1);
+        layered.record_histogram(labeled("http.latency", &[]), 1.0);
+        layered.record_histogram(labeled("db.latency", &[]), 1.0);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 2);
+
+        for (kind, key, _unit, _desc, _value) in &after {
+            assert!(*kind != MetricKind::Histogram || !key.name().starts_with("http."));
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::default();
+        filter.add_label("env", "dev").add_rule(
+            FilterRule::new()
+                .kind(MetricKind::Counter)
+                .name_contains("dropped"),
+        );
+        let layered = filter.layer(recorder);
+
+        // A label rule can't tell anything about a description, so it's kept.
+        layered.describe_counter("requests".into(), None, "kept".into());
+        layered.describe_counter("dropped".into(), None, "not kept".into());
+        layered.register_counter(labeled("requests", &[]), None, None);
+        layered.register_counter(labeled("dropped", &[]), None, None);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 1);
+        let (_kind, key, _unit, desc, _value) = &after[0];
+        assert_eq!(key.name(), "requests");
+        assert_eq!(desc.as_deref(), Some("kept"));
+
+        // In allow mode, descriptions are kept if a rule could match once labels are known.
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::default();
+        filter
+            .mode(FilterMode::Allow)
+            .add_rule(FilterRule::new().name_contains("http").label("env", "prod"));
+        let layered = filter.layer(recorder);
+
+        layered.describe_gauge("http.conns".into(), None, "kept".into());
+        layered.describe_gauge("db.conns".into(), None, "not kept".into());
+        layered.register_gauge(labeled("http.conns", &[("env", "prod")]), None, None);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 1);
+        let (_kind, _key, _unit, desc, _value) = &after[0];
+        assert_eq!(desc.as_deref(), Some("kept"));
+    }
+
+    #[cfg(feature = "layer-filter-regex")]
+    #[test]
+    fn test_regex() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut filter = FilterLayer::default();
+        let regex = regex::Regex::new(r"^tokio\.(loops|polls)$").unwrap();
+        filter.add_rule(FilterRule::new().name_regex(regex));
+        let layered = filter.layer(recorder);
+
+        layered.increment_counter(labeled("tokio.loops", &[]), 1);
+        layered.increment_counter(labeled("tokio.polls", &[]), 1);
+        layered.increment_counter(labeled("tokio.loops_total", &[]), 1);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].1.name(), "tokio.loops_total");
+    }
 }
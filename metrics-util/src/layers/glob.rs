@@ -0,0 +1,57 @@
+use core::str::Chars;
+
+/// Matches `name` against a glob `pattern` supporting `*` and `?`.
+///
+/// The pattern must match the entire name.  `*` matches any sequence of characters, including an
+/// empty one, and `?` matches exactly one character.  Matching does not allocate, so it can be used
+/// on the hot path.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let (mut p, mut n) = (pattern.chars(), name.chars());
+    // The pattern right after the last `*` seen, and the name from where that `*` stopped matching.
+    let mut backtrack: Option<(Chars<'_>, Chars<'_>)> = None;
+    loop {
+        let nc = match n.clone().next() {
+            Some(nc) => nc,
+            None => return p.all(|c| c == '*'),
+        };
+
+        match p.clone().next() {
+            Some('*') => {
+                p.next();
+                backtrack = Some((p.clone(), n.clone()));
+            }
+            Some(pc) if pc == '?' || pc == nc => {
+                p.next();
+                n.next();
+            }
+            _ => match &mut backtrack {
+                Some((bp, bn)) => {
+                    // Let the last `*` absorb one more character and try again.
+                    bn.next();
+                    p = bp.clone();
+                    n = bn.clone();
+                }
+                None => return false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("http.*", "http.requests"));
+        assert!(glob_match("http.*", "http."));
+        assert!(!glob_match("http.*", "db.http.requests"));
+        assert!(glob_match("*.latency", "db.latency"));
+        assert!(glob_match("*.*.bytes", "a.b.c.bytes"));
+        assert!(glob_match("req?ests", "requests"));
+        assert!(!glob_match("req?ests", "reqests"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("", "a"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+    }
+}
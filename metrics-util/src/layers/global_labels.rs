@@ -0,0 +1,269 @@
+use crate::layers::Layer;
+use metrics::{
+    Counter, Gauge, Histogram, IntoLabels, Key, KeyData, Label, Recorder, SharedString, Unit,
+};
+
+/// How to resolve a global label whose key is already used by a label of the metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelConflict {
+    /// The label of the metric is kept, and the global label is not added.
+    KeepExisting,
+
+    /// The label of the metric is removed, and the global label is added.
+    Overwrite,
+}
+
+impl Default for LabelConflict {
+    fn default() -> Self {
+        LabelConflict::KeepExisting
+    }
+}
+
+/// Adds a fixed set of labels to every metric key.
+///
+/// Global labels are appended after the labels of the metric.  When a metric already has a label
+/// with the same key as a global label, the conflict is resolved according to [`LabelConflict`].
+///
+/// Keys which would be left unchanged, such as keys already carrying every global label when
+/// keeping existing labels, are passed through without allocating.
+pub struct GlobalLabels<R> {
+    inner: R,
+    labels: Vec<Label>,
+    conflict: LabelConflict,
+}
+
+impl<R> GlobalLabels<R> {
+    fn label_key(&self, key: Key) -> Key {
+        if self.labels.is_empty() {
+            return key;
+        }
+
+        let has_label = |k: &str| key.labels().any(|l| l.key() == k);
+        match self.conflict {
+            LabelConflict::KeepExisting => {
+                if self.labels.iter().all(|l| has_label(l.key())) {
+                    return key;
+                }
+
+                let extra = self
+                    .labels
+                    .iter()
+                    .filter(|l| !has_label(l.key()))
+                    .cloned()
+                    .collect();
+                key.with_extra_labels(extra).into()
+            }
+            LabelConflict::Overwrite => {
+                let is_global = |k: &str| self.labels.iter().any(|l| l.key() == k);
+                let labels = key
+                    .labels()
+                    .filter(|l| !is_global(l.key()))
+                    .chain(self.labels.iter())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                KeyData::from_parts(key.name().clone(), labels).into()
+            }
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for GlobalLabels<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        let new_key = self.label_key(key);
+        self.inner.register_counter(new_key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        let new_key = self.label_key(key);
+        self.inner.register_gauge(new_key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        let new_key = self.label_key(key);
+        self.inner.register_histogram(new_key, unit, description)
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        let new_key = self.label_key(key);
+        self.inner.increment_counter(new_key, value);
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let new_key = self.label_key(key);
+        self.inner.absolute_counter(new_key, value);
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        let new_key = self.label_key(key);
+        self.inner.update_gauge(new_key, value);
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        let new_key = self.label_key(key);
+        self.inner.increment_gauge(new_key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let new_key = self.label_key(key);
+        self.inner.decrement_gauge(new_key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        let new_key = self.label_key(key);
+        self.inner.record_histogram(new_key, value);
+    }
+}
+
+/// A layer for adding a fixed set of labels to every metric key.
+///
+/// More information on the behavior of the layer can be found in [`GlobalLabels`].
+#[derive(Default)]
+pub struct GlobalLabelsLayer {
+    labels: Vec<Label>,
+    conflict: LabelConflict,
+}
+
+impl GlobalLabelsLayer {
+    /// Creates a new `GlobalLabelsLayer` based on the given labels.
+    ///
+    /// If more than one label is given with the same key, the last one is used, as with
+    /// [`add_label`](GlobalLabelsLayer::add_label).
+    pub fn new<L: IntoLabels>(labels: L) -> GlobalLabelsLayer {
+        let mut layer = GlobalLabelsLayer::default();
+        for label in labels.into_labels() {
+            layer.push_label(label);
+        }
+        layer
+    }
+
+    /// Adds a label to every metric key.
+    ///
+    /// If a global label with the same key was already added, it is replaced.
+    pub fn add_label<K, V>(&mut self, key: K, value: V) -> &mut GlobalLabelsLayer
+    where
+        K: Into<SharedString>,
+        V: Into<SharedString>,
+    {
+        self.push_label(Label::new(key, value));
+        self
+    }
+
+    /// Sets how to resolve a global label whose key is already used by a label of the metric.
+    ///
+    /// Defaults to [`LabelConflict::KeepExisting`].
+    pub fn on_conflict(&mut self, conflict: LabelConflict) -> &mut GlobalLabelsLayer {
+        self.conflict = conflict;
+        self
+    }
+
+    fn push_label(&mut self, label: Label) {
+        self.labels.retain(|l| l.key() != label.key());
+        self.labels.push(label);
+    }
+}
+
+impl<R> Layer<R> for GlobalLabelsLayer {
+    type Output = GlobalLabels<R>;
+
+    fn layer(&self, inner: R) -> Self::Output {
+        GlobalLabels {
+            inner,
+            labels: self.labels.clone(),
+            conflict: self.conflict,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GlobalLabelsLayer, LabelConflict};
+    use crate::layers::test_util::labels_of;
+
+    #[test]
+    fn test_basic_functionality() {
+        let mut layer = GlobalLabelsLayer::new(&[("host", "web-1")]);
+        layer.add_label("region", "eu-west-1");
+
+        let labels = labels_of(&layer, "queue_depth", &[]);
+        assert_eq!(labels, vec!["host=web-1", "region=eu-west-1"]);
+
+        let labels = labels_of(&layer, "queue_depth", &[("queue", "jobs")]);
+        assert_eq!(labels, vec!["queue=jobs", "host=web-1", "region=eu-west-1"]);
+
+        // Adding a label with an existing key replaces it.
+        layer.add_label("host", "web-2");
+        let labels = labels_of(&layer, "queue_depth", &[]);
+        assert_eq!(labels, vec!["region=eu-west-1", "host=web-2"]);
+
+        // So does giving the same key more than once when creating the layer.
+        let layer = GlobalLabelsLayer::new(&[
+            ("host", "web-1"),
+            ("region", "eu-west-1"),
+            ("host", "web-2"),
+        ]);
+        let labels = labels_of(&layer, "queue_depth", &[]);
+        assert_eq!(labels, vec!["region=eu-west-1", "host=web-2"]);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut layer = GlobalLabelsLayer::new(&[("host", "web-1"), ("region", "eu-west-1")]);
+
+        let labels = labels_of(
+            &layer,
+            "queue_depth",
+            &[("host", "batch-1"), ("queue", "jobs")],
+        );
+        assert_eq!(
+            labels,
+            vec!["host=batch-1", "queue=jobs", "region=eu-west-1"]
+        );
+
+        let labels = labels_of(
+            &layer,
+            "queue_depth",
+            &[("region", "us-east-1"), ("host", "batch-1")],
+        );
+        assert_eq!(labels, vec!["region=us-east-1", "host=batch-1"]);
+
+        layer.on_conflict(LabelConflict::Overwrite);
+        let labels = labels_of(
+            &layer,
+            "queue_depth",
+            &[("host", "batch-1"), ("queue", "jobs")],
+        );
+        assert_eq!(labels, vec!["queue=jobs", "host=web-1", "region=eu-west-1"]);
+    }
+}
@@ -8,7 +8,7 @@
 //! Here's an example of a layer that filters out all metrics that start with a specific string:
 //!
 //! ```rust
-//! # use metrics::{Key, Recorder, Unit};
+//! # use metrics::{Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
 //! # use metrics_util::DebuggingRecorder;
 //! # use metrics_util::layers::{Layer, Stack, PrefixLayer};
 //! // A simple layer that denies any metrics that have "stairway" or "heaven" in their name.
@@ -22,27 +22,39 @@
 //! }
 //!
 //! impl<R: Recorder> Recorder for StairwayDeny<R> {
-//!    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+//!    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) -> Counter {
 //!        if self.is_invalid_key(&key) {
-//!            return;
+//!            return Counter::noop();
 //!        }
 //!        self.0.register_counter(key, unit, description)
 //!    }
 //!
-//!    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+//!    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) -> Gauge {
 //!        if self.is_invalid_key(&key) {
-//!            return;
+//!            return Gauge::noop();
 //!        }
 //!        self.0.register_gauge(key, unit, description)
 //!    }
 //!
-//!    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+//!    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) -> Histogram {
 //!        if self.is_invalid_key(&key) {
-//!            return;
+//!            return Histogram::noop();
 //!        }
 //!        self.0.register_histogram(key, unit, description)
 //!    }
 //!
+//!    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+//!        self.0.describe_counter(name, unit, description)
+//!    }
+//!
+//!    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+//!        self.0.describe_gauge(name, unit, description)
+//!    }
+//!
+//!    fn describe_histogram(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+//!        self.0.describe_histogram(name, unit, description)
+//!    }
+//!
 //!    fn increment_counter(&self, key: Key, value: u64) {
 //!        if self.is_invalid_key(&key) {
 //!            return;
@@ -50,6 +62,13 @@
 //!        self.0.increment_counter(key, value);
 //!    }
 //!
+//!    fn absolute_counter(&self, key: Key, value: u64) {
+//!        if self.is_invalid_key(&key) {
+//!            return;
+//!        }
+//!        self.0.absolute_counter(key, value);
+//!    }
+//!
 //!    fn update_gauge(&self, key: Key, value: f64) {
 //!        if self.is_invalid_key(&key) {
 //!            return;
@@ -57,7 +76,21 @@
 //!        self.0.update_gauge(key, value);
 //!    }
 //!
-//!    fn record_histogram(&self, key: Key, value: u64) {
+//!    fn increment_gauge(&self, key: Key, value: f64) {
+//!        if self.is_invalid_key(&key) {
+//!            return;
+//!        }
+//!        self.0.increment_gauge(key, value);
+//!    }
+//!
+//!    fn decrement_gauge(&self, key: Key, value: f64) {
+//!        if self.is_invalid_key(&key) {
+//!            return;
+//!        }
+//!        self.0.decrement_gauge(key, value);
+//!    }
+//!
+//!    fn record_histogram(&self, key: Key, value: f64) {
 //!        if self.is_invalid_key(&key) {
 //!            return;
 //!        }
@@ -100,24 +133,70 @@
 //!     .push(StairwayDenyLayer::default())
 //!     .install()
 //!     .expect("failed to install stack");
+//!
+//! # metrics::clear_recorder();
+//!
+//! // If the stack needs to be rebuilt at runtime, such as when reloading configuration, it can be
+//! // installed as a replaceable recorder instead, and swapped out for a new stack later on.
+//! Stack::new(DebuggingRecorder::new())
+//!     .push(PrefixLayer::new("app_name"))
+//!     .reinstall()
+//!     .expect("failed to install stack");
+//! Stack::new(DebuggingRecorder::new())
+//!     .push(PrefixLayer::new("new_app_name"))
+//!     .reinstall()
+//!     .expect("failed to replace stack");
 //! # }
 //! ```
-use metrics::{Key, Recorder, Unit};
+use metrics::{Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
 
 #[cfg(feature = "std")]
 use metrics::SetRecorderError;
 
+mod glob;
+
+#[cfg(all(test, feature = "std"))]
+mod test_util;
+
 #[cfg(feature = "layer-filter")]
 mod filter;
 #[cfg(feature = "layer-filter")]
-pub use filter::{Filter, FilterLayer};
+pub use filter::{Filter, FilterLayer, FilterMode, FilterRule};
 
 mod prefix;
 pub use prefix::{Prefix, PrefixLayer};
 
+#[cfg(feature = "layer-relabel")]
+mod relabel;
+#[cfg(feature = "layer-relabel")]
+pub use relabel::{Relabel, RelabelLayer, RelabelRule};
+
 mod fanout;
 pub use fanout::{Fanout, FanoutBuilder};
 
+mod global_labels;
+pub use global_labels::{GlobalLabels, GlobalLabelsLayer, LabelConflict};
+
+#[cfg(feature = "std")]
+mod cardinality;
+#[cfg(feature = "std")]
+pub use cardinality::{CardinalityLimit, CardinalityLimitLayer, OverflowBehavior, SeriesReleaser};
+
+#[cfg(feature = "std")]
+mod sampling;
+#[cfg(feature = "std")]
+pub use sampling::{Sampling, SamplingLayer, SAMPLE_RATE_LABEL};
+
+#[cfg(feature = "std")]
+mod rename;
+#[cfg(feature = "std")]
+pub use rename::{Rename, RenameConfig, RenameLayer};
+
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "std")]
+pub use builder::{StackBuilder, StackConfig, StackError};
+
 /// Decorates an object by wrapping it within another type.
 pub trait Layer<R> {
     /// The output type after wrapping.
@@ -127,6 +206,35 @@ pub trait Layer<R> {
     fn layer(&self, inner: R) -> Self::Output;
 }
 
+/// A [`Layer`] whose output type has been erased.
+///
+/// Stacking layers with [`Stack::push`] nests their types, which requires knowing every layer at
+/// compile time.  Layers can instead be applied to, and produce, a boxed recorder, so that which
+/// layers are used can be decided at runtime, such as from configuration.  The boxed recorder is
+/// `Send + Sync`, so the resulting stack can also be installed with [`Stack::reinstall`].
+///
+/// This trait is implemented for every [`Layer`] that can wrap a boxed recorder.
+pub trait BoxedLayer {
+    /// Wraps `inner` based on this layer, boxing the result.
+    fn layer_boxed(
+        &self,
+        inner: Box<dyn Recorder + Send + Sync>,
+    ) -> Box<dyn Recorder + Send + Sync>;
+}
+
+impl<L> BoxedLayer for L
+where
+    L: Layer<Box<dyn Recorder + Send + Sync>>,
+    L::Output: Recorder + Send + Sync + 'static,
+{
+    fn layer_boxed(
+        &self,
+        inner: Box<dyn Recorder + Send + Sync>,
+    ) -> Box<dyn Recorder + Send + Sync> {
+        Box::new(self.layer(inner))
+    }
+}
+
 /// Builder for composing layers together in a top-down/inside-out order.
 pub struct Stack<R> {
     inner: R,
@@ -144,6 +252,21 @@ impl<R> Stack<R> {
     }
 }
 
+impl Stack<Box<dyn Recorder + Send + Sync>> {
+    /// Pushes the given boxed layer on to the stack, wrapping the existing stack.
+    ///
+    /// Unlike [`Stack::push`], the type of the stack stays the same, so layers can be pushed
+    /// conditionally or in a loop.
+    pub fn push_boxed(self, layer: &dyn BoxedLayer) -> Stack<Box<dyn Recorder + Send + Sync>> {
+        Stack::new(layer.layer_boxed(self.inner))
+    }
+
+    /// Consumes the stack, returning the boxed recorder at its top.
+    pub fn into_inner(self) -> Box<dyn Recorder + Send + Sync> {
+        self.inner
+    }
+}
+
 #[cfg(feature = "std")]
 impl<R: Recorder + 'static> Stack<R> {
     /// Installs this stack as the global recorder.
@@ -154,28 +277,88 @@ impl<R: Recorder + 'static> Stack<R> {
     }
 }
 
+#[cfg(feature = "std")]
+impl<R: Recorder + Send + Sync + 'static> Stack<R> {
+    /// Installs this stack as the global recorder, replacing any stack previously installed this
+    /// way.
+    ///
+    /// This allows rebuilding the stack, and the exporter underneath it, at runtime, such as when
+    /// reloading configuration.  See [`metrics::replace_recorder`] for details on how the swap
+    /// behaves.
+    ///
+    /// An error will be returned if a recorder was already installed via [`Stack::install`] or any
+    /// of the other non-replaceable installation methods.
+    pub fn reinstall(self) -> Result<(), SetRecorderError> {
+        metrics::replace_recorder(Box::new(self))
+    }
+}
+
 impl<R: Recorder> Recorder for Stack<R> {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.inner.register_counter(key, unit, description);
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        self.inner.register_counter(key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        self.inner.register_gauge(key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        self.inner.register_histogram(key, unit, description)
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.inner.register_gauge(key, unit, description);
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
-        self.inner.register_histogram(key, unit, description);
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
         self.inner.increment_counter(key, value);
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        self.inner.absolute_counter(key, value);
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
         self.inner.update_gauge(key, value);
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        self.inner.increment_gauge(key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        self.inner.decrement_gauge(key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         self.inner.record_histogram(key, value);
     }
 }
@@ -1,5 +1,5 @@
 use crate::layers::Layer;
-use metrics::{Key, Recorder, Unit};
+use metrics::{Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
 
 /// Applies a prefix to every metric key.
 ///
@@ -15,35 +15,89 @@ impl<R> Prefix<R> {
             .map_name(|old| format!("{}.{}", self.prefix, old))
             .into()
     }
+
+    fn prefix_name(&self, name: SharedString) -> SharedString {
+        format!("{}.{}", self.prefix, name).into()
+    }
 }
 
 impl<R: Recorder> Recorder for Prefix<R> {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
         let new_key = self.prefix_key(key);
         self.inner.register_counter(new_key, unit, description)
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
         let new_key = self.prefix_key(key);
         self.inner.register_gauge(new_key, unit, description)
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
         let new_key = self.prefix_key(key);
         self.inner.register_histogram(new_key, unit, description)
     }
 
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        let new_name = self.prefix_name(name);
+        self.inner.describe_counter(new_name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        let new_name = self.prefix_name(name);
+        self.inner.describe_gauge(new_name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        let new_name = self.prefix_name(name);
+        self.inner.describe_histogram(new_name, unit, description)
+    }
+
     fn increment_counter(&self, key: Key, value: u64) {
         let new_key = self.prefix_key(key);
         self.inner.increment_counter(new_key, value);
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let new_key = self.prefix_key(key);
+        self.inner.absolute_counter(new_key, value);
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
         let new_key = self.prefix_key(key);
         self.inner.update_gauge(new_key, value);
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        let new_key = self.prefix_key(key);
+        self.inner.increment_gauge(new_key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let new_key = self.prefix_key(key);
+        self.inner.decrement_gauge(new_key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         let new_key = self.prefix_key(key);
         self.inner.record_histogram(new_key, value);
     }
@@ -117,7 +171,7 @@ mod tests {
         for (i, (_kind, key, unit, desc, _value)) in after.iter().enumerate() {
             assert!(key.name().starts_with("testing"));
             assert_eq!(&Some(ud[i].0.clone()), unit);
-            assert_eq!(&Some(ud[i].1), desc);
+            assert_eq!(Some(ud[i].1), desc.as_deref());
         }
     }
 }
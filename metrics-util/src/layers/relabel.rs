@@ -0,0 +1,358 @@
+use crate::layers::Layer;
+use metrics::{Counter, Gauge, Histogram, Key, KeyData, Label, Recorder, SharedString, Unit};
+use regex::Regex;
+
+/// A single relabeling step applied by [`Relabel`].
+///
+/// Regular expressions are not implicitly anchored, so use `^` and `$` to match an entire value.
+/// Replacements can refer to capture groups of the expression, using the syntax of
+/// [`regex::Captures::expand`], such as `$1` or `${name}`.
+#[derive(Clone, Debug)]
+pub enum RelabelRule {
+    /// Renames the label key `from` to `to`, keeping its value.
+    ///
+    /// Any label already using the key `to` is replaced.
+    RenameKey {
+        /// Label key to rename.
+        from: String,
+        /// New label key.
+        to: String,
+    },
+
+    /// Drops the label with the given key.
+    DropKey(String),
+
+    /// Rewrites the value of the label `key` when it matches `regex`.
+    ///
+    /// The new value is `replacement`, with any capture groups expanded.  Values which do not match
+    /// are left as-is.
+    ReplaceValue {
+        /// Label key whose value is rewritten.
+        key: String,
+        /// Expression to match against the value.
+        regex: Regex,
+        /// Replacement for matching values.
+        replacement: String,
+    },
+
+    /// Adds the label `key` when the metric name matches `regex`.
+    ///
+    /// The value of the label is `replacement`, with any capture groups expanded.  Any label already
+    /// using the key `key` is replaced.  Metrics whose name does not match are left as-is.
+    LabelFromName {
+        /// Label key to add.
+        key: String,
+        /// Expression to match against the metric name.
+        regex: Regex,
+        /// Value of the label, for matching names.
+        replacement: String,
+    },
+}
+
+impl RelabelRule {
+    /// Checks whether applying this rule would change `labels`, without having to copy them.
+    fn changes(&self, name: &str, labels: &[Label]) -> bool {
+        match self {
+            RelabelRule::RenameKey { from, to } => {
+                from != to && labels.iter().any(|l| l.key() == from)
+            }
+            RelabelRule::DropKey(key) => labels.iter().any(|l| l.key() == key),
+            RelabelRule::ReplaceValue { key, regex, .. } => labels
+                .iter()
+                .any(|l| l.key() == key && regex.is_match(l.value())),
+            RelabelRule::LabelFromName { regex, .. } => regex.is_match(name),
+        }
+    }
+
+    /// Applies this rule to `labels`.
+    fn apply(&self, name: &str, labels: &mut Vec<Label>) {
+        match self {
+            RelabelRule::RenameKey { from, to } => {
+                if from == to || !labels.iter().any(|l| l.key() == from) {
+                    return;
+                }
+
+                labels.retain(|l| l.key() != to);
+                for label in labels.iter_mut() {
+                    if label.key() == from {
+                        let (_, value) = label.clone().into_parts();
+                        *label = Label::new(to.clone(), value);
+                    }
+                }
+            }
+            RelabelRule::DropKey(key) => labels.retain(|l| l.key() != key),
+            RelabelRule::ReplaceValue {
+                key,
+                regex,
+                replacement,
+            } => {
+                for label in labels.iter_mut() {
+                    if label.key() != key {
+                        continue;
+                    }
+
+                    if let Some(value) = expand(regex, replacement, label.value()) {
+                        *label = Label::new(key.clone(), value);
+                    }
+                }
+            }
+            RelabelRule::LabelFromName {
+                key,
+                regex,
+                replacement,
+            } => {
+                if let Some(value) = expand(regex, replacement, name) {
+                    labels.retain(|l| l.key() != key);
+                    labels.push(Label::new(key.clone(), value));
+                }
+            }
+        }
+    }
+}
+
+/// Expands `replacement` against the captures of `regex` in `haystack`, if it matches.
+fn expand(regex: &Regex, replacement: &str, haystack: &str) -> Option<String> {
+    regex.captures(haystack).map(|captures| {
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        expanded
+    })
+}
+
+/// Rewrites the labels of every metric key.
+///
+/// Rules are applied in the order they were added, with each rule seeing the labels produced by
+/// the rules before it.  Keys which are left unchanged by every rule are passed through as-is.
+///
+/// Only labels are rewritten, so metric names, and therefore descriptions, pass through unchanged.
+pub struct Relabel<R> {
+    inner: R,
+    rules: Vec<RelabelRule>,
+}
+
+impl<R> Relabel<R> {
+    fn relabel_key(&self, key: Key) -> Key {
+        // Rules which leave the labels as they are can be skipped, so the labels only need to be
+        // copied once a rule actually changes them.
+        let first = match self
+            .rules
+            .iter()
+            .position(|rule| rule.changes(key.name(), key.labels().as_slice()))
+        {
+            Some(first) => first,
+            None => return key,
+        };
+
+        let mut labels = key.labels().cloned().collect::<Vec<_>>();
+        for rule in &self.rules[first..] {
+            rule.apply(key.name(), &mut labels);
+        }
+
+        KeyData::from_parts(key.name().clone(), labels).into()
+    }
+}
+
+impl<R: Recorder> Recorder for Relabel<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        let new_key = self.relabel_key(key);
+        self.inner.register_counter(new_key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        let new_key = self.relabel_key(key);
+        self.inner.register_gauge(new_key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        let new_key = self.relabel_key(key);
+        self.inner.register_histogram(new_key, unit, description)
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        let new_key = self.relabel_key(key);
+        self.inner.increment_counter(new_key, value);
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let new_key = self.relabel_key(key);
+        self.inner.absolute_counter(new_key, value);
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        let new_key = self.relabel_key(key);
+        self.inner.update_gauge(new_key, value);
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        let new_key = self.relabel_key(key);
+        self.inner.increment_gauge(new_key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let new_key = self.relabel_key(key);
+        self.inner.decrement_gauge(new_key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        let new_key = self.relabel_key(key);
+        self.inner.record_histogram(new_key, value);
+    }
+}
+
+/// A layer for rewriting the labels of every metric key.
+///
+/// More information on the behavior of the layer can be found in [`Relabel`].
+#[derive(Default)]
+pub struct RelabelLayer {
+    rules: Vec<RelabelRule>,
+}
+
+impl RelabelLayer {
+    /// Creates a `RelabelLayer` from an existing, ordered set of rules.
+    pub fn from_rules<I>(rules: I) -> Self
+    where
+        I: IntoIterator<Item = RelabelRule>,
+    {
+        RelabelLayer {
+            rules: rules.into_iter().collect(),
+        }
+    }
+
+    /// Adds a rule to apply after all previously added rules.
+    pub fn add_rule(&mut self, rule: RelabelRule) -> &mut RelabelLayer {
+        self.rules.push(rule);
+        self
+    }
+}
+
+impl<R> Layer<R> for RelabelLayer {
+    type Output = Relabel<R>;
+
+    fn layer(&self, inner: R) -> Self::Output {
+        Relabel {
+            inner,
+            rules: self.rules.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RelabelLayer, RelabelRule};
+    use crate::debugging::DebuggingRecorder;
+    use crate::layers::test_util::labels_of;
+    use crate::layers::Layer;
+    use metrics::{Key, KeyData, Label};
+    use regex::Regex;
+
+    #[test]
+    fn test_rename_and_drop() {
+        let layer = RelabelLayer::from_rules(vec![
+            RelabelRule::RenameKey {
+                from: "svc".to_string(),
+                to: "service".to_string(),
+            },
+            RelabelRule::DropKey("request_id".to_string()),
+        ]);
+
+        let labels = labels_of(
+            &layer,
+            "requests",
+            &[("svc", "api"), ("request_id", "1234"), ("method", "GET")],
+        );
+        assert_eq!(labels, vec!["service=api", "method=GET"]);
+
+        // Renaming replaces any label already using the new key.
+        let labels = labels_of(&layer, "requests", &[("service", "old"), ("svc", "api")]);
+        assert_eq!(labels, vec!["service=api"]);
+    }
+
+    #[test]
+    fn test_replace_value() {
+        let mut layer = RelabelLayer::default();
+        layer.add_rule(RelabelRule::ReplaceValue {
+            key: "status".to_string(),
+            regex: Regex::new(r"^([1-5])\d\d$").unwrap(),
+            replacement: "${1}xx".to_string(),
+        });
+
+        let labels = labels_of(&layer, "responses", &[("status", "404")]);
+        assert_eq!(labels, vec!["status=4xx"]);
+
+        let labels = labels_of(&layer, "responses", &[("status", "unknown")]);
+        assert_eq!(labels, vec!["status=unknown"]);
+    }
+
+    #[test]
+    fn test_label_from_name() {
+        let mut layer = RelabelLayer::default();
+        layer
+            .add_rule(RelabelRule::LabelFromName {
+                key: "subsystem".to_string(),
+                regex: Regex::new(r"^([a-z]+)\.").unwrap(),
+                replacement: "$1".to_string(),
+            })
+            .add_rule(RelabelRule::RenameKey {
+                from: "subsystem".to_string(),
+                to: "component".to_string(),
+            });
+
+        let labels = labels_of(&layer, "db.queries", &[("table", "users")]);
+        assert_eq!(labels, vec!["table=users", "component=db"]);
+
+        let labels = labels_of(&layer, "uptime", &[]);
+        assert!(labels.is_empty());
+    }
+
+    #[test]
+    fn test_unchanged_key_passes_through() {
+        static LABELS: [Label; 1] = [Label::from_static_parts("method", "GET")];
+        static KEY_DATA: KeyData = KeyData::from_static_parts("requests", &LABELS);
+
+        let layer = RelabelLayer::from_rules(vec![
+            RelabelRule::DropKey("request_id".to_string()),
+            RelabelRule::ReplaceValue {
+                key: "method".to_string(),
+                regex: Regex::new("^POST$").unwrap(),
+                replacement: "WRITE".to_string(),
+            },
+        ]);
+        let relabel = layer.layer(DebuggingRecorder::new());
+
+        // Keys no rule applies to are handed back rather than copied.
+        let key = relabel.relabel_key(Key::Borrowed(&KEY_DATA));
+        assert!(matches!(key, Key::Borrowed(data) if std::ptr::eq(data, &KEY_DATA)));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use crate::layers::Layer;
+use metrics::{Counter, Gauge, Histogram, Key, KeyData, Recorder, SharedString, Unit};
+
+/// Name mappings for [`RenameLayer`].
+///
+/// With the `layer-rename-config` feature enabled, this type can be deserialized from any format
+/// supported by `serde`, such as TOML, JSON or YAML, which allows names to be changed without
+/// recompiling.  Both tables are optional:
+///
+/// ```toml
+/// [exact]
+/// "http_requests" = "http.requests"
+///
+/// [prefixes]
+/// "hyper." = "http.server."
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "layer-rename-config", derive(serde::Deserialize))]
+#[cfg_attr(feature = "layer-rename-config", serde(default, deny_unknown_fields))]
+pub struct RenameConfig {
+    /// Names which are replaced entirely, keyed by the original name.
+    pub exact: HashMap<String, String>,
+
+    /// Prefixes which are replaced, keyed by the original prefix.
+    pub prefixes: HashMap<String, String>,
+}
+
+/// Renames metrics based on exact names and name prefixes.
+///
+/// An exact mapping for a name takes precedence over any prefix mapping.  Otherwise, the longest
+/// matching prefix is replaced, leaving the rest of the name intact.  Names matching neither are
+/// passed through as-is, without allocating.
+///
+/// Names are mapped when registering, describing and updating metrics alike, so units and
+/// descriptions follow the new name.
+pub struct Rename<R> {
+    inner: R,
+    exact: HashMap<String, String>,
+    // Sorted by descending prefix length, so the first match is the longest one.
+    prefixes: Vec<(String, String)>,
+}
+
+impl<R> Rename<R> {
+    fn rename(&self, name: &str) -> Option<String> {
+        if let Some(new_name) = self.exact.get(name) {
+            return Some(new_name.clone());
+        }
+
+        self.prefixes.iter().find_map(|(from, to)| {
+            if name.starts_with(from.as_str()) {
+                Some(format!("{}{}", to, &name[from.len()..]))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn rename_key(&self, key: Key) -> Key {
+        match self.rename(key.name()) {
+            Some(new_name) => {
+                let (_, labels) = key.into_owned().into_parts();
+                KeyData::from_parts(new_name, labels).into()
+            }
+            None => key,
+        }
+    }
+
+    fn rename_name(&self, name: SharedString) -> SharedString {
+        match self.rename(&name) {
+            Some(new_name) => new_name.into(),
+            None => name,
+        }
+    }
+}
+
+impl<R: Recorder> Recorder for Rename<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        let new_key = self.rename_key(key);
+        self.inner.register_counter(new_key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        let new_key = self.rename_key(key);
+        self.inner.register_gauge(new_key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        let new_key = self.rename_key(key);
+        self.inner.register_histogram(new_key, unit, description)
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        let new_name = self.rename_name(name);
+        self.inner.describe_counter(new_name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        let new_name = self.rename_name(name);
+        self.inner.describe_gauge(new_name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        let new_name = self.rename_name(name);
+        self.inner.describe_histogram(new_name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        let new_key = self.rename_key(key);
+        self.inner.increment_counter(new_key, value);
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        let new_key = self.rename_key(key);
+        self.inner.absolute_counter(new_key, value);
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        let new_key = self.rename_key(key);
+        self.inner.update_gauge(new_key, value);
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        let new_key = self.rename_key(key);
+        self.inner.increment_gauge(new_key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        let new_key = self.rename_key(key);
+        self.inner.decrement_gauge(new_key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        let new_key = self.rename_key(key);
+        self.inner.record_histogram(new_key, value);
+    }
+}
+
+/// A layer for renaming metrics based on exact names and name prefixes.
+///
+/// More information on the behavior of the layer can be found in [`Rename`].
+#[derive(Default)]
+pub struct RenameLayer {
+    config: RenameConfig,
+}
+
+impl RenameLayer {
+    /// Creates a `RenameLayer` from an existing set of mappings.
+    pub fn from_config(config: RenameConfig) -> Self {
+        RenameLayer { config }
+    }
+
+    /// Renames metrics named exactly `from` to `to`.
+    pub fn add_exact<F, T>(&mut self, from: F, to: T) -> &mut RenameLayer
+    where
+        F: Into<String>,
+        T: Into<String>,
+    {
+        self.config.exact.insert(from.into(), to.into());
+        self
+    }
+
+    /// Replaces the prefix `from` with `to` for metrics whose name starts with `from`.
+    pub fn add_prefix<F, T>(&mut self, from: F, to: T) -> &mut RenameLayer
+    where
+        F: Into<String>,
+        T: Into<String>,
+    {
+        self.config.prefixes.insert(from.into(), to.into());
+        self
+    }
+}
+
+impl<R> Layer<R> for RenameLayer {
+    type Output = Rename<R>;
+
+    fn layer(&self, inner: R) -> Self::Output {
+        let mut prefixes = self
+            .config
+            .prefixes
+            .iter()
+            .map(|(from, to)| (from.clone(), to.clone()))
+            .collect::<Vec<_>>();
+        prefixes.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then_with(|| a.0.cmp(&b.0)));
+
+        Rename {
+            inner,
+            exact: self.config.exact.clone(),
+            prefixes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RenameLayer;
+    use crate::debugging::DebuggingRecorder;
+    use crate::layers::Layer;
+    use metrics::{KeyData, Recorder, Unit};
+
+    #[test]
+    fn test_basic_functionality() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut layer = RenameLayer::default();
+        layer
+            .add_exact("http_requests", "http.requests")
+            .add_exact("hyper.conns", "http.connections")
+            .add_prefix("hyper.", "http.server.")
+            .add_prefix("hyper.client.", "http.client.");
+        let layered = layer.layer(recorder);
+
+        layered.describe_counter("http_requests".into(), Some(Unit::Count), "requests".into());
+        for name in &[
+            "http_requests",
+            "hyper.conns",
+            "hyper.bytes",
+            "hyper.client.bytes",
+            "db.queries",
+        ] {
+            layered.increment_counter(KeyData::from_name(*name).into(), 1);
+        }
+
+        let mut after = snapshotter
+            .snapshot()
+            .into_iter()
+            .map(|(_kind, key, unit, desc, _value)| (key.name().to_string(), unit, desc))
+            .collect::<Vec<_>>();
+        after.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let names = after.iter().map(|(n, _, _)| n.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "db.queries",
+                "http.client.bytes",
+                "http.connections",
+                "http.requests",
+                "http.server.bytes",
+            ]
+        );
+        assert_eq!(after[3].1, Some(Unit::Count));
+        assert_eq!(after[3].2.as_deref(), Some("requests"));
+    }
+
+    #[cfg(feature = "layer-rename-config")]
+    #[test]
+    fn test_config() {
+        use super::RenameConfig;
+
+        let toml = r#"
+            [exact]
+            "http_requests" = "http.requests"
+
+            [prefixes]
+            "hyper." = "http.server."
+        "#;
+        let from_toml: RenameConfig = toml::from_str(toml).unwrap();
+
+        let json = r#"{"exact": {"http_requests": "http.requests"}, "prefixes": {"hyper.": "http.server."}}"#;
+        let from_json: RenameConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(from_toml, from_json);
+
+        let partial: RenameConfig = serde_json::from_str(r#"{"prefixes": {"a.": "b."}}"#).unwrap();
+        assert!(partial.exact.is_empty());
+        assert!(serde_json::from_str::<RenameConfig>(r#"{"prefix": {}}"#).is_err());
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let layered = RenameLayer::from_config(from_toml).layer(recorder);
+        layered.update_gauge(KeyData::from_name("hyper.conns").into(), 1.0);
+
+        let after = snapshotter.snapshot();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].1.name(), "http.server.conns");
+    }
+}
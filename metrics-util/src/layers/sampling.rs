@@ -0,0 +1,295 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+use crate::layers::{glob::glob_match, Layer};
+use dashmap::DashMap;
+use metrics::{Counter, Gauge, Histogram, HistogramFn, Key, Label, Recorder, SharedString, Unit};
+
+/// Label added to sampled histograms, holding the fraction of samples which are recorded.
+///
+/// The value is formatted as a floating-point number, such as `0.1` when recording 1 in 10
+/// samples.  Exporters can use it to scale counts back up, and should remove it before exposing
+/// the metric.
+pub const SAMPLE_RATE_LABEL: &str = "sample_rate";
+
+thread_local! {
+    static RNG_STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    // `RandomState` is randomly seeded per thread, and xorshift can't recover from a zero state.
+    RandomState::new().build_hasher().finish() | 1
+}
+
+/// Returns `true` with a probability of one in `n`.
+fn should_sample(n: u64) -> bool {
+    RNG_STATE.with(|state| {
+        // xorshift64*, which is plenty for deciding whether to keep a sample.
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d) % n == 0
+    })
+}
+
+struct SampledHistogram {
+    histogram: Histogram,
+    n: u64,
+}
+
+impl HistogramFn for SampledHistogram {
+    fn record(&self, value: f64) {
+        if should_sample(self.n) {
+            self.histogram.record(value);
+        }
+    }
+}
+
+/// Records only a random subset of histogram samples.
+///
+/// Histograms whose name matches a pattern have, on average, one in every `N` samples recorded,
+/// where `N` is set per pattern.  Patterns are globs which must match the entire name, as with
+/// [`FilterRule::name_glob`](crate::layers::FilterRule), and are checked in the order they were
+/// added, with the first match winning.  Histograms matching no pattern, as well as counters and
+/// gauges, are passed through as-is.
+///
+/// Sampled histograms carry a [`SAMPLE_RATE_LABEL`] label so that exporters can scale counts back
+/// up.  Handles returned from registration are sampled as well.
+///
+/// The patterns are only matched the first time a histogram name is seen, after which the decision
+/// is cached by name, with one entry per distinct name.  Registering a handle up front skips even
+/// the cache lookup on every sample.
+pub struct Sampling<R> {
+    inner: R,
+    rates: Vec<(String, u64, Label)>,
+    decisions: DashMap<SharedString, Option<usize>>,
+}
+
+impl<R> Sampling<R> {
+    fn sample_rate(&self, key: &Key) -> Option<(u64, &Label)> {
+        let decision = match self.decisions.get(key.name()) {
+            Some(decision) => *decision,
+            None => {
+                let decision = self
+                    .rates
+                    .iter()
+                    .position(|(pattern, _, _)| glob_match(pattern, key.name()))
+                    .filter(|idx| self.rates[*idx].1 > 1);
+                self.decisions.insert(key.name().clone(), decision);
+                decision
+            }
+        };
+
+        decision.map(|idx| {
+            let (_, n, label) = &self.rates[idx];
+            (*n, label)
+        })
+    }
+}
+
+impl<R: Recorder> Recorder for Sampling<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        self.inner.register_counter(key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        self.inner.register_gauge(key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        match self.sample_rate(&key) {
+            Some((n, label)) => {
+                let key = key.with_extra_labels(vec![label.clone()]).into();
+                let histogram = self.inner.register_histogram(key, unit, description);
+                Histogram::from_arc(Arc::new(SampledHistogram { histogram, n }))
+            }
+            None => self.inner.register_histogram(key, unit, description),
+        }
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        self.inner.describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        self.inner.describe_histogram(name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        self.inner.increment_counter(key, value);
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        self.inner.absolute_counter(key, value);
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        self.inner.update_gauge(key, value);
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        self.inner.increment_gauge(key, value);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        self.inner.decrement_gauge(key, value);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        match self.sample_rate(&key) {
+            Some((n, label)) => {
+                if should_sample(n) {
+                    let key = key.with_extra_labels(vec![label.clone()]).into();
+                    self.inner.record_histogram(key, value);
+                }
+            }
+            None => self.inner.record_histogram(key, value),
+        }
+    }
+}
+
+/// A layer for recording only a random subset of histogram samples.
+///
+/// More information on the behavior of the layer can be found in [`Sampling`].
+#[derive(Default)]
+pub struct SamplingLayer {
+    rates: Vec<(String, u64)>,
+}
+
+impl SamplingLayer {
+    /// Records one in every `n` samples of histograms whose name matches `pattern`.
+    ///
+    /// A rate of one in `1`, or `0`, records every sample, and is useful for exempting histograms
+    /// from a broader pattern added afterwards.
+    pub fn sample_one_in<P>(&mut self, pattern: P, n: u64) -> &mut SamplingLayer
+    where
+        P: AsRef<str>,
+    {
+        self.rates.push((pattern.as_ref().to_string(), n.max(1)));
+        self
+    }
+}
+
+impl<R> Layer<R> for SamplingLayer {
+    type Output = Sampling<R>;
+
+    fn layer(&self, inner: R) -> Self::Output {
+        let rates = self
+            .rates
+            .iter()
+            .map(|(pattern, n)| {
+                let rate = 1.0 / *n as f64;
+                let label = Label::new(SAMPLE_RATE_LABEL, rate.to_string());
+                (pattern.clone(), *n, label)
+            })
+            .collect();
+        Sampling {
+            inner,
+            rates,
+            decisions: DashMap::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{should_sample, SamplingLayer, SAMPLE_RATE_LABEL};
+    use crate::debugging::{DebugValue, DebuggingRecorder};
+    use crate::layers::Layer;
+    use metrics::{Key, KeyData, Label, Recorder};
+
+    #[test]
+    fn test_should_sample() {
+        assert!((0..100).all(|_| should_sample(1)));
+
+        let sampled = (0..100_000).filter(|_| should_sample(10)).count();
+        assert!(sampled > 9_000 && sampled < 11_000, "sampled {}", sampled);
+    }
+
+    #[test]
+    fn test_basic_functionality() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let mut layer = SamplingLayer::default();
+        layer
+            .sample_one_in("http.health", 1)
+            .sample_one_in("http.*", 4);
+        let layered = layer.layer(recorder);
+
+        let key = |name: &'static str| -> Key { KeyData::from_name(name).into() };
+        let handle = layered.register_histogram(key("http.latency"), None, None);
+        for i in 0..10_000 {
+            layered.record_histogram(key("http.latency"), i as f64);
+            handle.record(i as f64);
+            layered.record_histogram(key("http.health"), i as f64);
+            layered.record_histogram(key("db.latency"), i as f64);
+        }
+        layered.increment_counter(key("http.requests"), 1);
+
+        let snapshot = snapshotter.snapshot();
+        assert_eq!(snapshot.len(), 4);
+        for (_kind, key, _unit, _desc, value) in snapshot {
+            let rate = key
+                .labels()
+                .find(|l| l.key() == SAMPLE_RATE_LABEL)
+                .map(|l| l.value().to_string());
+            match (key.name().as_ref(), value) {
+                ("http.latency", DebugValue::Histogram(samples)) => {
+                    assert_eq!(rate.as_deref(), Some("0.25"));
+                    assert!(samples.len() > 4_000 && samples.len() < 6_000);
+                }
+                ("http.health", DebugValue::Histogram(samples))
+                | ("db.latency", DebugValue::Histogram(samples)) => {
+                    assert_eq!(rate, None);
+                    assert_eq!(samples.len(), 10_000);
+                }
+                ("http.requests", DebugValue::Counter(1)) => assert_eq!(rate, None),
+                (name, value) => panic!("unexpected metric {}: {:?}", name, value),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decisions_per_name() {
+        let mut layer = SamplingLayer::default();
+        layer.sample_one_in("http.*", 4);
+        let layered = layer.layer(DebuggingRecorder::new());
+
+        // Every label set of a name shares the one decision.
+        for id in 0..100 {
+            let labels = vec![Label::new("conn", id.to_string())];
+            let key = KeyData::from_parts("http.latency", labels).into();
+            layered.record_histogram(key, 1.0);
+        }
+        layered.record_histogram(KeyData::from_name("db.latency").into(), 1.0);
+        assert_eq!(layered.decisions.len(), 2);
+    }
+}
@@ -0,0 +1,29 @@
+use crate::debugging::DebuggingRecorder;
+use crate::layers::Layer;
+use metrics::{KeyData, Label, Recorder};
+
+/// Sends a counter with the given name and labels through `layer`, and gets the labels it comes
+/// out with, as `key=value` strings.
+pub(crate) fn labels_of<L>(layer: &L, name: &'static str, labels: &[(&str, &str)]) -> Vec<String>
+where
+    L: Layer<DebuggingRecorder>,
+    L::Output: Recorder,
+{
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let layered = layer.layer(recorder);
+
+    let labels = labels
+        .iter()
+        .map(|(k, v)| Label::new(k.to_string(), v.to_string()))
+        .collect::<Vec<_>>();
+    layered.increment_counter(KeyData::from_parts(name, labels).into(), 1);
+
+    let snapshot = snapshotter.snapshot();
+    assert_eq!(snapshot.len(), 1);
+    let (_kind, key, _unit, _desc, _value) = &snapshot[0];
+    assert_eq!(key.name(), name);
+    key.labels()
+        .map(|l| format!("{}={}", l.key(), l.value()))
+        .collect()
+}
@@ -10,7 +10,7 @@ pub use bucket::AtomicBucket;
 #[cfg(feature = "std")]
 mod debugging;
 #[cfg(feature = "std")]
-pub use debugging::{DebugValue, DebuggingRecorder, MetricKind, Snapshotter};
+pub use debugging::{DebugValue, DebuggingRecorder, Snapshotter};
 
 #[cfg(feature = "std")]
 mod handle;
@@ -20,14 +20,21 @@ pub use handle::Handle;
 mod quantile;
 pub use quantile::{parse_quantiles, Quantile};
 
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "std")]
+pub use sharded::ShardedCounter;
+
 #[cfg(feature = "std")]
 mod registry;
 #[cfg(feature = "std")]
-pub use registry::Registry;
+pub use registry::{Generation, Generational, KeyHasher, Registry};
 
 mod key;
 pub use key::CompositeKey;
 
+pub use metrics::MetricKind;
+
 mod histogram;
 pub use histogram::Histogram;
 
@@ -1,6 +1,221 @@
-use core::hash::Hash;
+use crate::{CompositeKey, MetricKind};
+use core::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use core::ops::Deref;
 use dashmap::DashMap;
-use std::collections::HashMap;
+use metrics::{CounterFn, GaugeFn, HistogramFn, Key};
+use std::collections::{hash_map::RandomState, HashMap};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Once,
+};
+
+/// A hasher tuned for keys which carry a precomputed hash.
+///
+/// [`Key`](metrics::Key) hashes itself by writing a single `u64` -- its precomputed hash, which
+/// static keys compute on first use -- so the hasher only needs to mix that value once, rather than
+/// rehashing the name and labels on every lookup.  Integers are mixed in with a folded multiply, so
+/// composite keys, such as a metric kind along with a [`Key`](metrics::Key), still hash well, and
+/// byte slices are consumed in 8-byte chunks, so arbitrary keys work too, if not quite as well as
+/// with the standard hasher.
+///
+/// Every hasher starts from a seed which is picked at random once per process, so which keys land
+/// in the same bucket of a map can't be predicted from the outside.  This doesn't help against keys
+/// whose precomputed hashes are equal: those are computed with an unkeyed hash, so label values
+/// controlled by an attacker can be crafted to collide.  Such keys still compare by name and
+/// labels, so they're never mixed up by a [`Registry`], but they share a bucket, and every lookup
+/// of one of them has to compare against the others.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyHasher(u64);
+
+impl KeyHasher {
+    #[inline]
+    fn add(&mut self, value: u64) {
+        let product = u128::from(self.0 ^ value) * u128::from(0x517c_c1b7_2722_0a95u64);
+        self.0 = (product as u64) ^ ((product >> 64) as u64);
+    }
+}
+
+impl Default for KeyHasher {
+    #[inline]
+    fn default() -> Self {
+        KeyHasher(seed())
+    }
+}
+
+/// Gets the random seed for [`KeyHasher`], which is picked the first time it's needed.
+fn seed() -> u64 {
+    static INIT: Once = Once::new();
+    static mut SEED: u64 = 0;
+
+    // SAFETY: The seed is only written once, before any reads, which `Once` orders after the write.
+    unsafe {
+        INIT.call_once(|| SEED = RandomState::new().build_hasher().finish());
+        SEED
+    }
+}
+
+impl Hasher for KeyHasher {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(buf));
+        }
+        for byte in chunks.remainder() {
+            self.add(u64::from(*byte));
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
+    }
+
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
+    }
+}
+
+/// Generation of a handle in a [`Registry`].
+///
+/// The generation changes when the handle is updated after its generation was last taken, so
+/// comparing generations taken at two points in time tells whether the handle was updated in
+/// between.  Updates racing with taking the generation may be counted as happening before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Generation(usize);
+
+/// A handle stored in a [`Registry`], along with its generation.
+///
+/// Updating a metric through this handle moves it on to the next generation.  `Generational`
+/// dereferences to the inner handle, and implements `CounterFn`, `GaugeFn` and `HistogramFn` if
+/// the inner handle does, so it can be handed out during registration.
+///
+/// Generations come from an epoch shared by every handle in the registry, which only moves on when
+/// a generation is taken.  An update stores the current epoch as the generation of the handle, and
+/// only writes to it the first time the handle is updated after its generation was taken, so
+/// handles updated from many threads at once don't contend on their generation.
+#[derive(Clone)]
+pub struct Generational<H> {
+    inner: H,
+    generation: Arc<AtomicUsize>,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl<H> Generational<H> {
+    fn new(inner: H, epoch: Arc<AtomicUsize>) -> Generational<H> {
+        Generational {
+            inner,
+            generation: Arc::new(AtomicUsize::new(0)),
+            epoch,
+        }
+    }
+
+    /// Gets the inner handle.
+    pub fn get_inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Gets the current generation of the handle.
+    pub fn get_generation(&self) -> Generation {
+        let generation = self.generation.load(Ordering::Acquire);
+
+        // Later updates have to move the handle on to a generation other than this one, so the
+        // epoch moves on if it hasn't already.
+        if self.epoch.load(Ordering::Relaxed) == generation {
+            let _ = self.epoch.compare_exchange(
+                generation,
+                generation.wrapping_add(1),
+                Ordering::AcqRel,
+                Ordering::Relaxed,
+            );
+        }
+
+        Generation(generation)
+    }
+
+    /// Marks the handle as updated.
+    ///
+    /// Updates made through the `CounterFn`, `GaugeFn` and `HistogramFn` implementations do this
+    /// already, so this is only needed when updating the inner handle directly, such as in
+    /// [`Registry::op`].
+    pub fn mark_updated(&self) {
+        let epoch = self.epoch.load(Ordering::Relaxed);
+        if self.generation.load(Ordering::Relaxed) != epoch {
+            self.generation.store(epoch, Ordering::Release);
+        }
+    }
+
+    /// Whether the handle is held anywhere other than in the registry.
+    fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.generation) > 1
+    }
+}
+
+impl<H> Deref for Generational<H> {
+    type Target = H;
+
+    fn deref(&self) -> &H {
+        &self.inner
+    }
+}
+
+impl<H: CounterFn> CounterFn for Generational<H> {
+    fn increment(&self, value: u64) {
+        self.mark_updated();
+        self.inner.increment(value)
+    }
+
+    fn absolute(&self, value: u64) {
+        self.mark_updated();
+        self.inner.absolute(value)
+    }
+}
+
+impl<H: GaugeFn> GaugeFn for Generational<H> {
+    fn update(&self, value: f64) {
+        self.mark_updated();
+        self.inner.update(value)
+    }
+
+    fn increment(&self, value: f64) {
+        self.mark_updated();
+        self.inner.increment(value)
+    }
+
+    fn decrement(&self, value: f64) {
+        self.mark_updated();
+        self.inner.decrement(value)
+    }
+}
+
+impl<H: HistogramFn> HistogramFn for Generational<H> {
+    fn record(&self, value: f64) {
+        self.mark_updated();
+        self.inner.record(value)
+    }
+}
 
 /// A high-performance metric registry.
 ///
@@ -17,9 +232,25 @@ use std::collections::HashMap;
 /// `Registry` handles deduplicating metrics, and will return the `Identifier` for an existing
 /// metric if a caller attempts to reregister it.
 ///
-/// `Registry` is optimized for reads.
+/// `Registry` is optimized for reads.  Keys are hashed with [`KeyHasher`], which makes lookups of
+/// keys built on [`Key`](metrics::Key) cheap, as their hash is computed ahead of time.  See
+/// [`KeyHasher`] for how keys crafted to collide are handled.
+///
+/// Every handle is stored along with its [`Generation`], so that callers can tell which metrics
+/// haven't been updated for a while, and remove them with [`Registry::delete`] without racing
+/// against concurrent updates.
 pub struct Registry<K, H> {
-    map: DashMap<K, H>,
+    map: DashMap<K, Generational<H>, BuildHasherDefault<KeyHasher>>,
+    epoch: Arc<AtomicUsize>,
+}
+
+impl<K, H> Default for Registry<K, H>
+where
+    K: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<K, H> Registry<K, H>
@@ -29,7 +260,8 @@ where
     /// Creates a new `Registry`.
     pub fn new() -> Self {
         Self {
-            map: DashMap::new(),
+            map: DashMap::default(),
+            epoch: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -39,14 +271,78 @@ where
     ///
     /// If the `key` is not already mapped, the `init` function will be
     /// called, and the resulting handle will be stored in the registry.
+    ///
+    /// Operations don't count as updates by themselves, as they may only register or clone the
+    /// handle.  Updates should go through the `CounterFn`, `GaugeFn` and `HistogramFn`
+    /// implementations of [`Generational`], or call [`Generational::mark_updated`].
     pub fn op<I, O, V>(&self, key: K, op: O, init: I) -> V
     where
         I: FnOnce() -> H,
-        O: FnOnce(&H) -> V,
+        O: FnOnce(&Generational<H>) -> V,
     {
-        let valref = self.map.entry(key).or_insert_with(init);
+        let valref = self
+            .map
+            .entry(key)
+            .or_insert_with(|| Generational::new(init(), self.epoch.clone()));
         op(valref.value())
     }
+
+    /// Deletes the handle under the given `key`, if it's still at the given generation.
+    ///
+    /// Returns `true` if the handle was deleted.  If the handle was updated since `generation` was
+    /// taken, it's left in place, which avoids losing an update racing with the deletion.
+    ///
+    /// Handles which are still held outside of the registry, such as those returned during
+    /// registration, are left in place as well, as their updates would otherwise be lost.
+    pub fn delete(&self, key: &K, generation: Generation) -> bool {
+        self.map
+            .remove_if(key, |_, handle| {
+                handle.generation.load(Ordering::Acquire) == generation.0 && !handle.is_shared()
+            })
+            .is_some()
+    }
+
+    /// Visits every handle in the registry, in no particular order.
+    ///
+    /// Unlike [`Registry::get_handles`], entries are visited in place, without cloning any keys or
+    /// handles.  The registry is split into shards, each of which is locked while its entries are
+    /// visited, so metrics being updated at the same time may have to wait for `f`.  `f` should be
+    /// quick, and must not call back into the registry, or it may deadlock.
+    pub fn visit<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &Generational<H>),
+    {
+        for item in self.map.iter() {
+            f(item.key(), item.value());
+        }
+    }
+
+    /// Retains only the handles for which `f` returns `true`, deleting the rest.
+    ///
+    /// Unlike [`Registry::delete`], handles are deleted even if they're still held outside of the
+    /// registry, after which their updates are lost.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &Generational<H>) -> bool,
+    {
+        self.map.retain(|key, handle| f(key, handle))
+    }
+}
+
+impl<H> Registry<CompositeKey, H> {
+    /// Visits every handle of the given kind in the registry, in no particular order.
+    ///
+    /// The same caveats as for [`Registry::visit`] apply.
+    pub fn visit_kind<F>(&self, kind: MetricKind, mut f: F)
+    where
+        F: FnMut(&Key, &Generational<H>),
+    {
+        self.visit(|key, handle| {
+            if key.kind() == kind {
+                f(key.key(), handle);
+            }
+        })
+    }
 }
 
 impl<K, H> Registry<K, H>
@@ -57,10 +353,130 @@ where
     /// Gets a map of all present handles, mapped by key.
     ///
     /// Handles must implement `Clone`.  This map is a point-in-time snapshot of the registry.
-    pub fn get_handles(&self) -> HashMap<K, H> {
+    ///
+    /// Every key and handle is cloned into the map, so [`Registry::visit`] should be preferred
+    /// when the map isn't needed after the fact.
+    pub fn get_handles(&self) -> HashMap<K, Generational<H>> {
         self.map
             .iter()
             .map(|item| (item.key().clone(), item.value().clone()))
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Generational, KeyHasher, Registry};
+    use crate::{CompositeKey, Handle, MetricKind};
+    use metrics::{CounterFn, Key, KeyData, Label};
+    use std::hash::{Hash, Hasher};
+
+    #[test]
+    fn test_key_hasher_passes_hash_through() {
+        static LABELS: [Label; 1] = [Label::from_static_parts("type", "http")];
+        static KEY_DATA: KeyData = KeyData::from_static_parts("requests", &LABELS);
+
+        let mut hasher = KeyHasher::default();
+        Key::Borrowed(&KEY_DATA).hash(&mut hasher);
+        let mut expected = KeyHasher::default();
+        expected.write_u64(KEY_DATA.get_hash());
+        assert_eq!(hasher.finish(), expected.finish());
+    }
+
+    #[test]
+    fn test_registry_composite_keys() {
+        let registry = Registry::<CompositeKey, usize>::new();
+        let key =
+            |kind| CompositeKey::new(kind, Key::Owned(("requests", &[("type", "http")]).into()));
+
+        assert_eq!(registry.op(key(MetricKind::Counter), |h| **h, || 1), 1);
+        assert_eq!(registry.op(key(MetricKind::Gauge), |h| **h, || 2), 2);
+        assert_eq!(registry.op(key(MetricKind::Counter), |h| **h, || 3), 1);
+        assert_eq!(registry.get_handles().len(), 2);
+    }
+
+    #[test]
+    fn test_delete_generations() {
+        let registry = Registry::<&'static str, usize>::new();
+
+        registry.op("a", |_| (), || 1);
+        registry.op("b", |_| (), || 2);
+        let generation = registry.get_handles()["a"].get_generation();
+
+        // Merely looking the handle up doesn't count as an update.
+        registry.op("a", |_| (), || 1);
+        assert_eq!(registry.get_handles()["a"].get_generation(), generation);
+
+        // An update since the generation was taken keeps the handle around.
+        registry.op("a", |h| h.mark_updated(), || 1);
+        assert!(!registry.delete(&"a", generation));
+
+        let generation = registry.get_handles()["a"].get_generation();
+        assert!(registry.delete(&"a", generation));
+        assert!(!registry.delete(&"a", generation));
+
+        registry.retain(|key, handle| *key != "b" || **handle != 2);
+        assert!(registry.get_handles().is_empty());
+    }
+
+    #[test]
+    fn test_visit() {
+        let registry = Registry::<CompositeKey, usize>::new();
+        let key = |kind, name| CompositeKey::new(kind, Key::Owned(KeyData::from_name(name)));
+        registry.op(key(MetricKind::Counter, "a"), |_| (), || 1);
+        registry.op(key(MetricKind::Counter, "b"), |_| (), || 2);
+        registry.op(key(MetricKind::Gauge, "c"), |_| (), || 3);
+
+        let mut all = Vec::new();
+        registry.visit(|key, handle| all.push((key.key().name().to_string(), **handle)));
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                ("a".to_string(), 1),
+                ("b".to_string(), 2),
+                ("c".to_string(), 3)
+            ]
+        );
+
+        let mut counters = Vec::new();
+        registry.visit_kind(MetricKind::Counter, |key, handle| {
+            counters.push((key.name().to_string(), **handle))
+        });
+        counters.sort();
+        assert_eq!(counters, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_generational_handles() {
+        let registry = Registry::<&'static str, Handle>::new();
+
+        let handle: Generational<Handle> = registry.op("requests", |h| h.clone(), Handle::counter);
+        let generation = handle.get_generation();
+        CounterFn::increment(&handle, 1);
+        assert_ne!(
+            registry.get_handles()["requests"].get_generation(),
+            generation
+        );
+        assert_eq!(handle.read_counter(), 1);
+    }
+
+    #[test]
+    fn test_delete_held_handle() {
+        let registry = Registry::<&'static str, Handle>::new();
+
+        // A handle held across a deletion keeps its place in the registry, so its updates aren't
+        // lost.
+        let handle = registry.op("requests", |h| h.clone(), Handle::counter);
+        let generation = registry.get_handles()["requests"].get_generation();
+        assert!(!registry.delete(&"requests", generation));
+        CounterFn::increment(&handle, 2);
+        assert_eq!(registry.get_handles()["requests"].read_counter(), 2);
+
+        // Once it's dropped, the handle can be deleted.
+        drop(handle);
+        let generation = registry.get_handles()["requests"].get_generation();
+        assert!(registry.delete(&"requests", generation));
+        assert!(registry.get_handles().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use atomic_shim::AtomicU64;
+use crossbeam_utils::CachePadded;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_THREAD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Handed out round-robin, so that threads spread evenly over the shards of every counter.
+    static THREAD_INDEX: usize = NEXT_THREAD_INDEX.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A counter split into shards, so that threads incrementing it concurrently don't contend.
+///
+/// A plain atomic counter is updated by every thread in the same cache line, which has to bounce
+/// between cores on every increment, and quickly becomes a bottleneck when many threads update the
+/// same counter.  `ShardedCounter` instead gives each thread one of several shards, each on its
+/// own cache line, and only sums them up when the counter is read.
+///
+/// This makes increments scale with the number of threads, at the cost of reads taking longer and
+/// of the counter taking up a cache line per shard, typically 128 bytes.  With a shard per CPU,
+/// that's 8 KiB per counter on a machine with 64 CPUs, so it's best kept for counters updated on
+/// hot paths.
+pub struct ShardedCounter {
+    shards: Box<[CachePadded<AtomicU64>]>,
+    // Adjustment applied by `absolute`, which has to be a single value so that concurrent calls
+    // can't both apply their own adjustment.
+    base: AtomicU64,
+}
+
+impl ShardedCounter {
+    /// Creates a counter with a shard per available CPU.
+    ///
+    /// The counter is initialized to 0.
+    pub fn new() -> ShardedCounter {
+        ShardedCounter::with_shards(num_cpus::get())
+    }
+
+    /// Creates a counter with at least the given number of shards.
+    ///
+    /// The number of shards is rounded up to the next power of two.  The counter is initialized to
+    /// 0.
+    pub fn with_shards(shards: usize) -> ShardedCounter {
+        let shards = shards.max(1).next_power_of_two();
+        ShardedCounter {
+            shards: (0..shards)
+                .map(|_| CachePadded::new(AtomicU64::new(0)))
+                .collect(),
+            base: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of shards in this counter.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Increments the counter.
+    pub fn increment(&self, value: u64) {
+        self.shard().fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Sets the counter to an absolute value.
+    ///
+    /// The counter is only updated if `value` is greater than the current value, so that the
+    /// counter never goes backwards.  Increments made while the counter is being set may end up
+    /// added on top of `value`.
+    pub fn absolute(&self, value: u64) {
+        let mut base = self.base.load(Ordering::Relaxed);
+        loop {
+            let current = base.wrapping_add(self.sum());
+            if value <= current {
+                break;
+            }
+            let new = base.wrapping_add(value - current);
+            match self
+                .base
+                .compare_exchange_weak(base, new, Ordering::SeqCst, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(actual) => base = actual,
+            }
+        }
+    }
+
+    /// Reads the counter, summing up every shard.
+    ///
+    /// Increments made while the shards are being summed may or may not be included.
+    pub fn value(&self) -> u64 {
+        self.base.load(Ordering::Relaxed).wrapping_add(self.sum())
+    }
+
+    fn sum(&self) -> u64 {
+        self.shards.iter().fold(0, |sum, shard| {
+            sum.wrapping_add(shard.load(Ordering::Relaxed))
+        })
+    }
+
+    fn shard(&self) -> &AtomicU64 {
+        let index = THREAD_INDEX.with(|index| *index);
+        &self.shards[index & (self.shards.len() - 1)]
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        ShardedCounter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedCounter;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_shards() {
+        assert_eq!(ShardedCounter::with_shards(0).shards(), 1);
+        assert_eq!(ShardedCounter::with_shards(6).shards(), 8);
+        assert!(ShardedCounter::new().shards().is_power_of_two());
+    }
+
+    #[test]
+    fn test_absolute() {
+        let counter = ShardedCounter::with_shards(4);
+        counter.increment(5);
+        counter.absolute(42);
+        assert_eq!(counter.value(), 42);
+
+        // Counters never go backwards.
+        counter.absolute(12);
+        assert_eq!(counter.value(), 42);
+
+        counter.increment(1);
+        assert_eq!(counter.value(), 43);
+    }
+
+    #[test]
+    fn test_concurrent_increments() {
+        let counter = Arc::new(ShardedCounter::with_shards(4));
+
+        let threads = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        counter.increment(2);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert_eq!(counter.value(), 16_000);
+    }
+}
@@ -1,27 +1,49 @@
+#![allow(deprecated, clippy::default_constructed_unit_structs)]
+
 #[macro_use]
 extern crate criterion;
 
 use criterion::{Benchmark, Criterion};
 
-use metrics::{counter, Key, Recorder, Unit};
+use metrics::{counter, Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
 use rand::{thread_rng, Rng};
 
 #[derive(Default)]
 struct TestRecorder;
 impl Recorder for TestRecorder {
-    fn register_counter(&self, _key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _description: Option<&'static str>,
+    ) -> Counter {
+        Counter::noop()
+    }
+    fn register_gauge(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _description: Option<&'static str>,
+    ) -> Gauge {
+        Gauge::noop()
     }
-    fn register_gauge(&self, _key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {}
     fn register_histogram(
         &self,
         _key: Key,
         _unit: Option<Unit>,
         _description: Option<&'static str>,
-    ) {
+    ) -> Histogram {
+        Histogram::noop()
     }
+    fn describe_counter(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
+    fn describe_gauge(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
+    fn describe_histogram(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
     fn increment_counter(&self, _key: Key, _value: u64) {}
+    fn absolute_counter(&self, _key: Key, _value: u64) {}
     fn update_gauge(&self, _key: Key, _value: f64) {}
-    fn record_histogram(&self, _key: Key, _value: u64) {}
+    fn increment_gauge(&self, _key: Key, _value: f64) {}
+    fn decrement_gauge(&self, _key: Key, _value: f64) {}
+    fn record_histogram(&self, _key: Key, _value: f64) {}
 }
 
 fn reset_recorder() {
@@ -30,6 +52,9 @@ fn reset_recorder() {
 }
 
 fn macro_benchmark(c: &mut Criterion) {
+    // Generated once, so that every run of the benchmark hits the key cached by the call site.
+    let borrowed_label_val = thread_rng().gen::<u64>().to_string();
+
     c.bench(
         "macros",
         Benchmark::new("uninitialized/no_labels", |b| {
@@ -66,6 +91,13 @@ fn macro_benchmark(c: &mut Criterion) {
                 counter!("counter_bench", 42, "request" => "http", "uid" => label_val.clone());
             });
             metrics::clear_recorder();
+        })
+        .with_function("initialized/with_borrowed_dynamic_labels", move |b| {
+            reset_recorder();
+            b.iter(|| {
+                counter!("counter_bench", 42, "request" => "http", "uid" => borrowed_label_val.as_str());
+            });
+            metrics::clear_recorder();
         }),
     );
 }
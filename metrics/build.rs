@@ -9,5 +9,13 @@ fn main() {
         println!("cargo:rustc-cfg=atomic_cas");
     }
 
+    // Static keys keep their hash once it's computed, which takes 64-bit atomics.
+    let has_atomic = env::var("CARGO_CFG_TARGET_HAS_ATOMIC").unwrap_or_default();
+    if has_atomic.split(',').any(|width| width == "64") {
+        println!("cargo:rustc-cfg=atomic_u64");
+    }
+
+    println!("cargo:rustc-check-cfg=cfg(atomic_cas)");
+    println!("cargo:rustc-check-cfg=cfg(atomic_u64)");
     println!("cargo:rerun-if-changed=build.rs");
 }
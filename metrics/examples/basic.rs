@@ -5,55 +5,162 @@
 //!
 //! We demonstrate the various permutations of values that can be passed in the macro calls, all of
 //! which are documented in detail for the respective macro.
+use std::sync::Arc;
+
 use metrics::{
-    counter, gauge, histogram, increment, register_counter, register_gauge, register_histogram,
-    Key, Recorder, Unit,
+    absolute_counter, counter, decrement_gauge, describe_counter, describe_gauge,
+    describe_histogram, gauge, histogram, increment, increment_gauge, register_counter,
+    register_gauge, register_histogram, Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn,
+    Key, Recorder, SharedString, Unit,
 };
 
 #[allow(dead_code)]
 static RECORDER: PrintRecorder = PrintRecorder;
 
+struct PrintHandle(Key);
+
+impl CounterFn for PrintHandle {
+    fn increment(&self, value: u64) {
+        println!(
+            "(counter) got value {} for key {} via handle",
+            value, self.0
+        );
+    }
+
+    fn absolute(&self, value: u64) {
+        println!(
+            "(counter) got absolute value {} for key {} via handle",
+            value, self.0
+        );
+    }
+}
+
+impl GaugeFn for PrintHandle {
+    fn update(&self, value: f64) {
+        println!("(gauge) got value {} for key {} via handle", value, self.0);
+    }
+
+    fn increment(&self, value: f64) {
+        println!(
+            "(gauge) increment by {} for key {} via handle",
+            value, self.0
+        );
+    }
+
+    fn decrement(&self, value: f64) {
+        println!(
+            "(gauge) decrement by {} for key {} via handle",
+            value, self.0
+        );
+    }
+}
+
+impl HistogramFn for PrintHandle {
+    fn record(&self, value: f64) {
+        println!(
+            "(histogram) got value {} for key {} via handle",
+            value, self.0
+        );
+    }
+}
+
 #[derive(Default)]
 struct PrintRecorder;
 
 impl Recorder for PrintRecorder {
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
         println!(
             "(counter) registered key {} with unit {:?} and description {:?}",
             key, unit, description
         );
+        Counter::from_arc(Arc::new(PrintHandle(key)))
     }
 
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
         println!(
             "(gauge) registered key {} with unit {:?} and description {:?}",
             key, unit, description
         );
+        Gauge::from_arc(Arc::new(PrintHandle(key)))
     }
 
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>) {
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
         println!(
             "(histogram) registered key {} with unit {:?} and description {:?}",
             key, unit, description
         );
+        Histogram::from_arc(Arc::new(PrintHandle(key)))
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        println!(
+            "(counter) described name {} with unit {:?} and description {}",
+            name, unit, description
+        );
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        println!(
+            "(gauge) described name {} with unit {:?} and description {}",
+            name, unit, description
+        );
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        println!(
+            "(histogram) described name {} with unit {:?} and description {}",
+            name, unit, description
+        );
     }
 
     fn increment_counter(&self, key: Key, value: u64) {
         println!("(counter) got value {} for key {}", value, key);
     }
 
+    fn absolute_counter(&self, key: Key, value: u64) {
+        println!("(counter) got absolute value {} for key {}", value, key);
+    }
+
     fn update_gauge(&self, key: Key, value: f64) {
         println!("(gauge) got value {} for key {}", value, key);
     }
 
-    fn record_histogram(&self, key: Key, value: u64) {
+    fn increment_gauge(&self, key: Key, value: f64) {
+        println!("(gauge) increment by {} for key {}", value, key);
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        println!("(gauge) decrement by {} for key {}", value, key);
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
         println!("(histogram) got value {} for key {}", value, key);
     }
 }
 
 #[cfg(feature = "std")]
 fn init_print_logger() {
-    let recorder = PrintRecorder::default();
+    let recorder = PrintRecorder;
     metrics::set_boxed_recorder(Box::new(recorder)).unwrap()
 }
 
@@ -81,6 +188,30 @@ fn main() {
     register_gauge!("unused_gauge", "service" => "backend");
     register_histogram!("unused_histogram", Unit::Seconds, "unused histo", "service" => "middleware");
 
+    // Metadata can also be provided on its own, without registering anything:
+    describe_counter!("requests_processed", "number of requests processed");
+    describe_gauge!(
+        "connection_count",
+        Unit::Count,
+        "number of open connections"
+    );
+    describe_histogram!(
+        "svc.execution_time",
+        Unit::Milliseconds,
+        format!("execution time of request handler on {}", server_name)
+    );
+
+    // Registration returns a handle that can be used to update the metric directly:
+    let requests = register_counter!("requests_processed", "request_type" => "handle");
+    requests.increment(1);
+    requests.absolute(10);
+    let connections = register_gauge!("connection_count", "listener" => "handle");
+    connections.update(42.0);
+    connections.increment(1.0);
+    connections.decrement(1.0);
+    let execution_time = register_histogram!("svc.execution_time", "type" => "handle");
+    execution_time.record(70);
+
     // All the supported permutations of `increment!`:
     increment!("requests_processed");
     increment!("requests_processed", "request_type" => "admin");
@@ -93,12 +224,28 @@ fn main() {
     counter!("bytes_sent", 64, "listener" => "frontend", "server" => server_name.clone());
     counter!("bytes_sent", 64, common_labels);
 
+    // All the supported permutations of `absolute_counter!`:
+    absolute_counter!("bytes_sent", 1024);
+    absolute_counter!("bytes_sent", 1024, "listener" => "frontend");
+    absolute_counter!("bytes_sent", 1024, "listener" => "frontend", "server" => server_name.clone());
+    absolute_counter!("bytes_sent", 1024, common_labels);
+
     // All the supported permutations of `gauge!`:
     gauge!("connection_count", 300.0);
     gauge!("connection_count", 300.0, "listener" => "frontend");
     gauge!("connection_count", 300.0, "listener" => "frontend", "server" => server_name.clone());
     gauge!("connection_count", 300.0, common_labels);
 
+    // All the supported permutations of `increment_gauge!`/`decrement_gauge!`:
+    increment_gauge!("connection_count", 1.0);
+    increment_gauge!("connection_count", 1.0, "listener" => "frontend");
+    increment_gauge!("connection_count", 1.0, "listener" => "frontend", "server" => server_name.clone());
+    increment_gauge!("connection_count", 1.0, common_labels);
+    decrement_gauge!("connection_count", 1.0);
+    decrement_gauge!("connection_count", 1.0, "listener" => "frontend");
+    decrement_gauge!("connection_count", 1.0, "listener" => "frontend", "server" => server_name.clone());
+    decrement_gauge!("connection_count", 1.0, common_labels);
+
     // All the supported permutations of `histogram!`:
     histogram!("svc.execution_time", 70);
     histogram!("svc.execution_time", 70, "type" => "users");
@@ -0,0 +1,201 @@
+use crate::{MetricKind, Unit};
+use std::fmt;
+
+/// A call site of one of the registration or emission macros.
+///
+/// Call sites are collected at startup, regardless of whether they are ever reached, and can be
+/// listed with [`catalog`].
+#[derive(Debug)]
+pub struct Callsite {
+    name: &'static str,
+    kind: MetricKind,
+    label_keys: &'static [&'static str],
+    unit: Option<Unit>,
+    description: Option<&'static str>,
+    module_path: &'static str,
+    file: &'static str,
+    line: u32,
+}
+
+impl Callsite {
+    #[doc(hidden)]
+    #[allow(clippy::too_many_arguments)]
+    pub const fn new(
+        name: &'static str,
+        kind: MetricKind,
+        label_keys: &'static [&'static str],
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+        module_path: &'static str,
+        file: &'static str,
+        line: u32,
+    ) -> Self {
+        Self {
+            name,
+            kind,
+            label_keys,
+            unit,
+            description,
+            module_path,
+            file,
+            line,
+        }
+    }
+
+    /// Name of the metric.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Kind of the metric.
+    pub fn kind(&self) -> MetricKind {
+        self.kind
+    }
+
+    /// Keys of the labels given at the call site.
+    ///
+    /// Labels which are passed as an expression, rather than as key/value pairs, can't be known
+    /// ahead of time and so aren't listed.
+    pub fn label_keys(&self) -> &'static [&'static str] {
+        self.label_keys
+    }
+
+    /// Unit of the metric, if one was given at the call site.
+    pub fn unit(&self) -> Option<&Unit> {
+        self.unit.as_ref()
+    }
+
+    /// Description of the metric, if one was given at the call site.
+    pub fn description(&self) -> Option<&'static str> {
+        self.description
+    }
+
+    /// Path of the module containing the call site.
+    pub fn module_path(&self) -> &'static str {
+        self.module_path
+    }
+
+    /// Path of the file containing the call site.
+    pub fn file(&self) -> &'static str {
+        self.file
+    }
+
+    /// Line of the call site.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+}
+
+inventory::collect!(Callsite);
+
+/// Every call site of the registration and emission macros in the program.
+///
+/// Created with [`catalog`].
+#[derive(Debug)]
+pub struct Catalog {
+    callsites: Vec<&'static Callsite>,
+}
+
+impl Catalog {
+    /// Gets the call sites, ordered by metric name and then by location.
+    pub fn callsites(&self) -> &[&'static Callsite] {
+        &self.callsites
+    }
+
+    /// Gets every metric name which is used as more than one kind of metric.
+    ///
+    /// Exporters generally can't store the same metric name as more than one kind, so these are
+    /// bugs waiting to happen, and are best checked for at startup or in tests.
+    pub fn conflicts(&self) -> Vec<KindConflict> {
+        let mut conflicts = Vec::new();
+        let mut start = 0;
+        while start < self.callsites.len() {
+            // Call sites are sorted by name, so each name is a contiguous run.
+            let name = self.callsites[start].name;
+            let len = self.callsites[start..]
+                .iter()
+                .take_while(|c| c.name == name)
+                .count();
+            let callsites = &self.callsites[start..start + len];
+            start += len;
+
+            let kind = callsites[0].kind;
+            if callsites.iter().any(|c| c.kind != kind) {
+                conflicts.push(KindConflict {
+                    name,
+                    callsites: callsites.to_vec(),
+                });
+            }
+        }
+        conflicts
+    }
+}
+
+/// A metric name which is used as more than one kind of metric.
+#[derive(Debug)]
+pub struct KindConflict {
+    name: &'static str,
+    callsites: Vec<&'static Callsite>,
+}
+
+impl KindConflict {
+    /// Name of the metric.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Every call site using the metric name, no matter the kind.
+    pub fn callsites(&self) -> &[&'static Callsite] {
+        &self.callsites
+    }
+}
+
+impl fmt::Display for KindConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "metric '{}' is used as more than one kind: ", self.name)?;
+        for (i, callsite) in self.callsites.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(
+                f,
+                "{:?} at {}:{}",
+                callsite.kind, callsite.file, callsite.line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Gets every call site of the registration and emission macros in the program.
+///
+/// Call sites are registered before `main` runs, no matter whether they are ever reached, and
+/// across every crate linked into the program.  This makes the catalog a good fit for listing
+/// the metrics an application can emit, or for checking for names used as more than one kind of
+/// metric.  Call sites whose metric name is only known at runtime can't be listed, and are left
+/// out:
+///
+/// ```
+/// for conflict in metrics::catalog().conflicts() {
+///     eprintln!("{}", conflict);
+/// }
+/// ```
+///
+/// Requires the `catalog` feature.
+pub fn catalog() -> Catalog {
+    let mut callsites = inventory::iter::<Callsite>.into_iter().collect::<Vec<_>>();
+    callsites.sort_by(|a, b| (a.name, a.file, a.line).cmp(&(b.name, b.file, b.line)));
+    Catalog { callsites }
+}
+
+#[doc(hidden)]
+pub use inventory as __inventory;
+
+/// Registers a call site in the catalog, expanded by the registration and emission macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __catalog_callsite {
+    ($callsite:expr) => {
+        $crate::__inventory::submit! { $callsite }
+    };
+}
@@ -2,6 +2,7 @@
 use beef::lean::Cow;
 #[cfg(not(target_pointer_width = "64"))]
 use beef::Cow;
+use core::fmt;
 
 /// An allocation-optimized string.
 ///
@@ -127,7 +128,7 @@ impl Unit {
             Unit::Microseconds => "us",
             Unit::Nanoseconds => "ns",
             Unit::Terabytes => "TB",
-            Unit::Gigabytes => "Gb",
+            Unit::Gigabytes => "GB",
             Unit::Megabytes => "MB",
             Unit::Kilobytes => "KB",
             Unit::Bytes => "B",
@@ -153,6 +154,7 @@ impl Unit {
     /// Converts the string representation of a unit back into `Unit` if possible.
     ///
     /// The value passed here should match the output of [`Unit::as_str`].
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Unit> {
         match s {
             "count" => Some(Unit::Count),
@@ -188,80 +190,363 @@ impl Unit {
 
     /// Whether or not this unit relates to the measurement of time.
     pub fn is_time_based(&self) -> bool {
-        match self {
-            Unit::Seconds | Unit::Milliseconds | Unit::Microseconds | Unit::Nanoseconds => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            Unit::Seconds | Unit::Milliseconds | Unit::Microseconds | Unit::Nanoseconds
+        )
     }
 
     /// Whether or not this unit relates to the measurement of data.
     pub fn is_data_based(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Unit::Terabytes
-            | Unit::Gigabytes
-            | Unit::Megabytes
-            | Unit::Kilobytes
-            | Unit::Bytes
-            | Unit::Terabits
-            | Unit::Gigabits
-            | Unit::Megabits
-            | Unit::Kilobits
-            | Unit::Bits
-            | Unit::TerabytesPerSecond
-            | Unit::GigabytesPerSecond
-            | Unit::MegabytesPerSecond
-            | Unit::KilobytesPerSecond
-            | Unit::BytesPerSecond
-            | Unit::TerabitsPerSecond
-            | Unit::GigabitsPerSecond
-            | Unit::MegabitsPerSecond
-            | Unit::KilobitsPerSecond
-            | Unit::BitsPerSecond => true,
-            _ => false,
-        }
+                | Unit::Gigabytes
+                | Unit::Megabytes
+                | Unit::Kilobytes
+                | Unit::Bytes
+                | Unit::Terabits
+                | Unit::Gigabits
+                | Unit::Megabits
+                | Unit::Kilobits
+                | Unit::Bits
+                | Unit::TerabytesPerSecond
+                | Unit::GigabytesPerSecond
+                | Unit::MegabytesPerSecond
+                | Unit::KilobytesPerSecond
+                | Unit::BytesPerSecond
+                | Unit::TerabitsPerSecond
+                | Unit::GigabitsPerSecond
+                | Unit::MegabitsPerSecond
+                | Unit::KilobitsPerSecond
+                | Unit::BitsPerSecond
+        )
     }
 
     /// Whether or not this unit relates to the measurement of data rates.
     pub fn is_data_rate_based(&self) -> bool {
+        matches!(
+            self,
+            Unit::TerabytesPerSecond
+                | Unit::GigabytesPerSecond
+                | Unit::MegabytesPerSecond
+                | Unit::KilobytesPerSecond
+                | Unit::BytesPerSecond
+                | Unit::TerabitsPerSecond
+                | Unit::GigabitsPerSecond
+                | Unit::MegabitsPerSecond
+                | Unit::KilobitsPerSecond
+                | Unit::BitsPerSecond
+        )
+    }
+
+    /// Gets the base unit of the family this unit belongs to.
+    ///
+    /// Time is based on seconds, data on bytes, and data rates on bytes per second.  Bits and bytes
+    /// are part of the same family, so the base unit of [`Unit::Kilobits`] is [`Unit::Bytes`].
+    /// Any other unit is its own base unit.
+    pub fn base_unit(&self) -> Unit {
+        if self.is_time_based() {
+            Unit::Seconds
+        } else if self.is_data_rate_based() {
+            Unit::BytesPerSecond
+        } else if self.is_data_based() {
+            Unit::Bytes
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Gets the factor to multiply a value in this unit by to get the value in the base unit.
+    ///
+    /// Multiples of bytes are binary, such that a kilobyte is 1024 bytes, while multiples of bits
+    /// are decimal, such that a kilobit is 1000 bits, following common usage for storage and
+    /// network throughput respectively.
+    pub fn conversion_factor(&self) -> f64 {
+        let (numerator, denominator) = self.ratio();
+        numerator / denominator
+    }
+
+    /// Converts a value in this unit to the given unit.
+    ///
+    /// Returns `None` if the units don't share the same base unit, such as when converting
+    /// seconds to bytes.
+    pub fn convert(&self, value: f64, to: &Unit) -> Option<f64> {
+        if self.base_unit() != to.base_unit() {
+            return None;
+        }
+
+        // Keeping the numerators and denominators apart, rather than multiplying by a fractional
+        // factor, avoids turning 300 milliseconds into 0.30000000000000004 seconds.
+        let (from_num, from_den) = self.ratio();
+        let (to_num, to_den) = to.ratio();
+        Some(value * from_num * to_den / (from_den * to_num))
+    }
+
+    /// Converts a value in this unit to the base unit.
+    pub fn convert_to_base(&self, value: f64) -> f64 {
+        let (numerator, denominator) = self.ratio();
+        value * numerator / denominator
+    }
+
+    /// Scales a value in this unit to the most readable unit of the same kind.
+    ///
+    /// The largest unit in which the value is still at least one is chosen, so 1536 bytes become
+    /// 1.5 kilobytes, and 0.25 seconds become 250 milliseconds.  Bits are only scaled to other
+    /// multiples of bits, and likewise for bytes.  Units without multiples, and zero values, are
+    /// returned as-is.
+    pub fn scale(&self, value: f64) -> (f64, Unit) {
+        let ladder = self.ladder();
+        if value == 0.0 {
+            return (value, self.clone());
+        }
+
+        for unit in ladder.iter().rev() {
+            let scaled = self.convert(value, unit).unwrap_or(value);
+            if scaled >= 1.0 || scaled <= -1.0 {
+                return (scaled, unit.clone());
+            }
+        }
+
+        match ladder.first() {
+            Some(unit) => (self.convert(value, unit).unwrap_or(value), unit.clone()),
+            None => (value, self.clone()),
+        }
+    }
+
+    /// Scales a value in this unit for display.
+    ///
+    /// The value is scaled as with [`Unit::scale`], and is displayed with its canonical label,
+    /// rounded to two decimal places unless a precision is given, such as `1.5 KB` for 1536 bytes.
+    pub fn display_scaled(&self, value: f64) -> ScaledValue {
+        let (value, unit) = self.scale(value);
+        ScaledValue { value, unit }
+    }
+
+    /// The factor to get from this unit to the base unit, as a numerator and denominator.
+    fn ratio(&self) -> (f64, f64) {
+        const KIB: f64 = 1024.0;
         match self {
+            Unit::Seconds => (1.0, 1.0),
+            Unit::Milliseconds => (1.0, 1_000.0),
+            Unit::Microseconds => (1.0, 1_000_000.0),
+            Unit::Nanoseconds => (1.0, 1_000_000_000.0),
+            Unit::Terabytes | Unit::TerabytesPerSecond => (KIB * KIB * KIB * KIB, 1.0),
+            Unit::Gigabytes | Unit::GigabytesPerSecond => (KIB * KIB * KIB, 1.0),
+            Unit::Megabytes | Unit::MegabytesPerSecond => (KIB * KIB, 1.0),
+            Unit::Kilobytes | Unit::KilobytesPerSecond => (KIB, 1.0),
+            Unit::Bytes | Unit::BytesPerSecond => (1.0, 1.0),
+            Unit::Terabits | Unit::TerabitsPerSecond => (1_000_000_000_000.0, 8.0),
+            Unit::Gigabits | Unit::GigabitsPerSecond => (1_000_000_000.0, 8.0),
+            Unit::Megabits | Unit::MegabitsPerSecond => (1_000_000.0, 8.0),
+            Unit::Kilobits | Unit::KilobitsPerSecond => (1_000.0, 8.0),
+            Unit::Bits | Unit::BitsPerSecond => (1.0, 8.0),
+            Unit::Count | Unit::Percent | Unit::CountPerSecond => (1.0, 1.0),
+        }
+    }
+
+    /// The units this unit can be scaled between for display, from smallest to largest.
+    fn ladder(&self) -> &'static [Unit] {
+        const TIME: &[Unit] = &[
+            Unit::Nanoseconds,
+            Unit::Microseconds,
+            Unit::Milliseconds,
+            Unit::Seconds,
+        ];
+        const BYTES: &[Unit] = &[
+            Unit::Bytes,
+            Unit::Kilobytes,
+            Unit::Megabytes,
+            Unit::Gigabytes,
+            Unit::Terabytes,
+        ];
+        const BITS: &[Unit] = &[
+            Unit::Bits,
+            Unit::Kilobits,
+            Unit::Megabits,
+            Unit::Gigabits,
+            Unit::Terabits,
+        ];
+        const BYTE_RATES: &[Unit] = &[
+            Unit::BytesPerSecond,
+            Unit::KilobytesPerSecond,
+            Unit::MegabytesPerSecond,
+            Unit::GigabytesPerSecond,
+            Unit::TerabytesPerSecond,
+        ];
+        const BIT_RATES: &[Unit] = &[
+            Unit::BitsPerSecond,
+            Unit::KilobitsPerSecond,
+            Unit::MegabitsPerSecond,
+            Unit::GigabitsPerSecond,
+            Unit::TerabitsPerSecond,
+        ];
+
+        match self {
+            Unit::Seconds | Unit::Milliseconds | Unit::Microseconds | Unit::Nanoseconds => TIME,
+            Unit::Terabytes | Unit::Gigabytes | Unit::Megabytes | Unit::Kilobytes | Unit::Bytes => {
+                BYTES
+            }
+            Unit::Terabits | Unit::Gigabits | Unit::Megabits | Unit::Kilobits | Unit::Bits => BITS,
             Unit::TerabytesPerSecond
             | Unit::GigabytesPerSecond
             | Unit::MegabytesPerSecond
             | Unit::KilobytesPerSecond
-            | Unit::BytesPerSecond
-            | Unit::TerabitsPerSecond
-            | Unit::Gigabits
+            | Unit::BytesPerSecond => BYTE_RATES,
+            Unit::TerabitsPerSecond
+            | Unit::GigabitsPerSecond
             | Unit::MegabitsPerSecond
             | Unit::KilobitsPerSecond
-            | Unit::BitsPerSecond => true,
-            _ => false,
+            | Unit::BitsPerSecond => BIT_RATES,
+            Unit::Count | Unit::Percent | Unit::CountPerSecond => &[],
+        }
+    }
+}
+
+/// A value scaled to a human-readable unit, as returned by [`Unit::display_scaled`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScaledValue {
+    value: f64,
+    unit: Unit,
+}
+
+impl ScaledValue {
+    /// Gets the scaled value.
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Gets the unit the value was scaled to.
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+}
+
+impl fmt::Display for ScaledValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{:.*}", precision, self.value)?,
+            None => {
+                // `f64::round` isn't available without `std`, so round by hand.  Values this large
+                // have no meaningful decimal places anyway.
+                if self.value.abs() < 1e15 {
+                    let offset = if self.value < 0.0 { -0.5 } else { 0.5 };
+                    let rounded = (self.value * 100.0 + offset) as i64;
+                    write!(f, "{}", rounded as f64 / 100.0)?;
+                } else {
+                    write!(f, "{}", self.value)?;
+                }
+            }
+        }
+
+        let label = self.unit.as_canonical_label();
+        match label.chars().next() {
+            None => Ok(()),
+            Some(c) if c.is_alphabetic() => write!(f, " {}", label),
+            Some(_) => f.write_str(label),
         }
     }
 }
 
-/// An object which can be converted into a `u64` representation.
+/// Metric kinds.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy, Ord, PartialOrd)]
+pub enum MetricKind {
+    /// Counter.
+    Counter,
+
+    /// Gauge.
+    Gauge,
+
+    /// Histogram.
+    Histogram,
+}
+
+/// An object which can be converted into a `f64` representation.
 ///
 /// This trait provides a mechanism for existing types, which have a natural representation
-/// as an unsigned 64-bit integer, to be transparently passed in when recording a histogram.
-pub trait IntoU64 {
-    /// Converts this object to its `u64` representation.
-    fn into_u64(self) -> u64;
+/// as a 64-bit floating-point number, to be transparently passed in when recording a histogram.
+pub trait IntoF64 {
+    /// Converts this object to its `f64` representation.
+    fn into_f64(self) -> f64;
 }
 
-impl IntoU64 for u64 {
-    fn into_u64(self) -> u64 {
+impl IntoF64 for f64 {
+    fn into_f64(self) -> f64 {
         self
     }
 }
 
-impl IntoU64 for core::time::Duration {
-    fn into_u64(self) -> u64 {
-        self.as_nanos() as u64
+impl IntoF64 for u64 {
+    fn into_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl IntoF64 for core::time::Duration {
+    fn into_f64(self) -> f64 {
+        self.as_nanos() as f64
     }
 }
 
 /// Helper method to allow monomorphization of values passed to the `histogram!` macro.
 #[doc(hidden)]
-pub fn __into_u64<V: IntoU64>(value: V) -> u64 {
-    value.into_u64()
+pub fn __into_f64<V: IntoF64>(value: V) -> f64 {
+    value.into_f64()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Unit;
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(Unit::Milliseconds.convert(300.0, &Unit::Seconds), Some(0.3));
+        assert_eq!(
+            Unit::Seconds.convert(1.5, &Unit::Nanoseconds),
+            Some(1_500_000_000.0)
+        );
+        assert_eq!(Unit::Kilobits.convert(8.0, &Unit::Bytes), Some(1000.0));
+        assert_eq!(Unit::Kilobytes.convert(1.0, &Unit::Bits), Some(8192.0));
+        assert_eq!(
+            Unit::MegabitsPerSecond.convert(1.0, &Unit::BytesPerSecond),
+            Some(125_000.0)
+        );
+        assert_eq!(Unit::Seconds.convert(1.0, &Unit::Bytes), None);
+        assert_eq!(Unit::Bytes.convert(1.0, &Unit::BytesPerSecond), None);
+        assert_eq!(Unit::Count.convert(3.0, &Unit::Count), Some(3.0));
+
+        assert_eq!(Unit::Microseconds.base_unit(), Unit::Seconds);
+        assert_eq!(Unit::Gigabits.base_unit(), Unit::Bytes);
+        assert_eq!(Unit::GigabitsPerSecond.base_unit(), Unit::BytesPerSecond);
+        assert_eq!(Unit::Percent.base_unit(), Unit::Percent);
+        assert_eq!(Unit::Megabytes.convert_to_base(2.0), 2_097_152.0);
+        assert_eq!(Unit::Kilobytes.conversion_factor(), 1024.0);
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(Unit::Bytes.scale(1536.0), (1.5, Unit::Kilobytes));
+        assert_eq!(Unit::Seconds.scale(0.25), (250.0, Unit::Milliseconds));
+        assert_eq!(
+            Unit::Nanoseconds.scale(-2_000.0),
+            (-2.0, Unit::Microseconds)
+        );
+        assert_eq!(Unit::Bits.scale(1_500.0), (1.5, Unit::Kilobits));
+        assert_eq!(Unit::Nanoseconds.scale(0.5), (0.5, Unit::Nanoseconds));
+        assert_eq!(Unit::Seconds.scale(0.0), (0.0, Unit::Seconds));
+        assert_eq!(Unit::Count.scale(12345.0), (12345.0, Unit::Count));
+
+        assert_eq!(Unit::Bytes.display_scaled(1536.0).to_string(), "1.5 KB");
+        assert_eq!(
+            Unit::Nanoseconds.display_scaled(1_234_567.0).to_string(),
+            "1.23 ms"
+        );
+        assert_eq!(
+            format!("{:.3}", Unit::Seconds.display_scaled(2.0)),
+            "2.000 s"
+        );
+        assert_eq!(Unit::Count.display_scaled(42.0).to_string(), "42");
+        assert_eq!(Unit::Percent.display_scaled(99.5).to_string(), "99.5%");
+        assert_eq!(Unit::CountPerSecond.display_scaled(7.0).to_string(), "7/s");
+    }
 }
@@ -0,0 +1,283 @@
+use crate::IntoF64;
+use alloc::sync::Arc;
+
+/// A counter handler.
+///
+/// Implemented by the storage that backs a [`Counter`], typically provided by an exporter.
+pub trait CounterFn {
+    /// Increments the counter by the given amount.
+    fn increment(&self, value: u64);
+
+    /// Sets the counter to at least the given value.
+    ///
+    /// This is meant for mirroring counters that are tracked externally, and so the counter must
+    /// never go backwards: if the given value is lower than the current value, the counter is left
+    /// as-is.
+    fn absolute(&self, value: u64);
+}
+
+/// A gauge handler.
+///
+/// Implemented by the storage that backs a [`Gauge`], typically provided by an exporter.
+pub trait GaugeFn {
+    /// Updates the gauge to the given value.
+    fn update(&self, value: f64);
+
+    /// Increments the gauge by the given amount.
+    fn increment(&self, value: f64);
+
+    /// Decrements the gauge by the given amount.
+    fn decrement(&self, value: f64);
+}
+
+/// A histogram handler.
+///
+/// Implemented by the storage that backs a [`Histogram`], typically provided by an exporter.
+pub trait HistogramFn {
+    /// Records a value into the histogram.
+    fn record(&self, value: f64);
+}
+
+/// A counter.
+///
+/// Counters are returned when registering a counter, and can be held on to in order to update the
+/// counter directly, without having to look up the metric by key for every operation.
+///
+/// Handles are cheap to clone, and all clones refer to the same underlying counter.
+#[derive(Clone)]
+pub struct Counter {
+    inner: Option<Arc<dyn CounterFn + Send + Sync>>,
+}
+
+/// A gauge.
+///
+/// Gauges are returned when registering a gauge, and can be held on to in order to update the
+/// gauge directly, without having to look up the metric by key for every operation.
+///
+/// Handles are cheap to clone, and all clones refer to the same underlying gauge.
+#[derive(Clone)]
+pub struct Gauge {
+    inner: Option<Arc<dyn GaugeFn + Send + Sync>>,
+}
+
+/// A histogram.
+///
+/// Histograms are returned when registering a histogram, and can be held on to in order to update
+/// the histogram directly, without having to look up the metric by key for every operation.
+///
+/// Handles are cheap to clone, and all clones refer to the same underlying histogram.
+#[derive(Clone)]
+pub struct Histogram {
+    inner: Option<Arc<dyn HistogramFn + Send + Sync>>,
+}
+
+impl Counter {
+    /// Creates a no-op `Counter` which does nothing.
+    ///
+    /// Suitable when a handle must be provided that does nothing i.e. a no-op recorder or a layer
+    /// that disables specific metrics, and so on.
+    pub fn noop() -> Self {
+        Self { inner: None }
+    }
+
+    /// Creates a `Counter` based on a shared handler.
+    pub fn from_arc<F: CounterFn + Send + Sync + 'static>(a: Arc<F>) -> Self {
+        Self { inner: Some(a) }
+    }
+
+    /// Increments the counter.
+    pub fn increment(&self, value: u64) {
+        if let Some(c) = &self.inner {
+            c.increment(value)
+        }
+    }
+
+    /// Sets the counter to an absolute value.
+    ///
+    /// The counter is only updated if the given value is greater than the current value.
+    pub fn absolute(&self, value: u64) {
+        if let Some(c) = &self.inner {
+            c.absolute(value)
+        }
+    }
+}
+
+impl Gauge {
+    /// Creates a no-op `Gauge` which does nothing.
+    ///
+    /// Suitable when a handle must be provided that does nothing i.e. a no-op recorder or a layer
+    /// that disables specific metrics, and so on.
+    pub fn noop() -> Self {
+        Self { inner: None }
+    }
+
+    /// Creates a `Gauge` based on a shared handler.
+    pub fn from_arc<F: GaugeFn + Send + Sync + 'static>(a: Arc<F>) -> Self {
+        Self { inner: Some(a) }
+    }
+
+    /// Updates the gauge.
+    pub fn update(&self, value: f64) {
+        if let Some(g) = &self.inner {
+            g.update(value)
+        }
+    }
+
+    /// Increments the gauge.
+    pub fn increment(&self, value: f64) {
+        if let Some(g) = &self.inner {
+            g.increment(value)
+        }
+    }
+
+    /// Decrements the gauge.
+    pub fn decrement(&self, value: f64) {
+        if let Some(g) = &self.inner {
+            g.decrement(value)
+        }
+    }
+}
+
+impl Histogram {
+    /// Creates a no-op `Histogram` which does nothing.
+    ///
+    /// Suitable when a handle must be provided that does nothing i.e. a no-op recorder or a layer
+    /// that disables specific metrics, and so on.
+    pub fn noop() -> Self {
+        Self { inner: None }
+    }
+
+    /// Creates a `Histogram` based on a shared handler.
+    pub fn from_arc<F: HistogramFn + Send + Sync + 'static>(a: Arc<F>) -> Self {
+        Self { inner: Some(a) }
+    }
+
+    /// Records a value into the histogram.
+    ///
+    /// Values go through the same conversion as values passed to [`histogram!`](crate::histogram),
+    /// so anything implementing [`IntoF64`] can be recorded.
+    pub fn record<V: IntoF64>(&self, value: V) {
+        if let Some(h) = &self.inner {
+            h.record(value.into_f64())
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Histogram {
+    /// Starts a timer which records the elapsed time into this histogram when dropped.
+    ///
+    /// See [`HistogramTimer`] for more information.
+    pub fn start_timer(&self) -> HistogramTimer {
+        HistogramTimer::new(self.clone())
+    }
+}
+
+/// A timer which records the time elapsed since it was started into a histogram.
+///
+/// The elapsed time is recorded when the timer is dropped, or when calling [`stop`], as a
+/// [`Duration`](std::time::Duration) and so in nanoseconds.  The histogram should be registered
+/// with [`Unit::Nanoseconds`](crate::Unit::Nanoseconds), so that exporters know what unit the
+/// samples are in.  This makes it easy to time a block of code, including any early returns or
+/// panics within it:
+///
+/// ```
+/// # use metrics::{register_histogram, Unit};
+/// # fn run_query() {}
+/// let histogram = register_histogram!("process.query_time", Unit::Nanoseconds);
+/// {
+///     let _timer = histogram.start_timer();
+///     run_query();
+/// }
+/// ```
+///
+/// A timer can also be dropped without recording anything by calling [`discard`].
+///
+/// [`stop`]: HistogramTimer::stop
+/// [`discard`]: HistogramTimer::discard
+#[cfg(feature = "std")]
+#[must_use = "the timer records when dropped, so dropping it immediately records nothing useful"]
+pub struct HistogramTimer {
+    histogram: Option<Histogram>,
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl HistogramTimer {
+    /// Starts a timer which records into the given histogram.
+    pub fn new(histogram: Histogram) -> Self {
+        Self {
+            histogram: Some(histogram),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Stops the timer, recording and returning the time elapsed since it was started.
+    pub fn stop(mut self) -> std::time::Duration {
+        let elapsed = self.start.elapsed();
+        if let Some(histogram) = self.histogram.take() {
+            histogram.record(elapsed);
+        }
+        elapsed
+    }
+
+    /// Stops the timer without recording anything.
+    pub fn discard(mut self) {
+        self.histogram = None;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        if let Some(histogram) = self.histogram.take() {
+            histogram.record(self.start.elapsed());
+        }
+    }
+}
+
+/// Calls a function when dropped, unless the thread is panicking.
+///
+/// Used by the [`timed`](crate::timed) and [`counted`](crate::counted) attributes in order to record
+/// every return from an instrumented function, including early returns and the `?` operator.
+#[doc(hidden)]
+#[cfg(feature = "std")]
+pub struct __OnReturn<F: FnMut()>(pub F);
+
+#[cfg(feature = "std")]
+impl<F: FnMut()> Drop for __OnReturn<F> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            (self.0)()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{Histogram, HistogramFn};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Samples(Mutex<Vec<f64>>);
+
+    impl HistogramFn for Samples {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    #[test]
+    fn test_histogram_timer() {
+        let samples = Arc::new(Samples::default());
+        let histogram = Histogram::from_arc(samples.clone());
+
+        drop(histogram.start_timer());
+        let elapsed = histogram.start_timer().stop();
+        histogram.start_timer().discard();
+
+        let recorded = samples.0.lock().unwrap().clone();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[1], elapsed.as_nanos() as f64);
+    }
+}
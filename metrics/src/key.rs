@@ -11,10 +11,20 @@ use core::{
 ///
 /// While [`Key`] is the type that users will interact with via [`crate::Recorder`], [`KeyData`] is
 /// responsible for the actual storage of the name and label data.
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+///
+/// The hash of the name and labels is computed when a [`KeyData`] is created, or the first time
+/// it's needed for static keys, as hashing can't be done in a `const fn` on every supported version
+/// of Rust.  Hashing a [`KeyData`] only feeds that precomputed hash to the hasher, which allows
+/// maps keyed on keys to use a pass-through hasher, and avoids rehashing keys on every lookup.
+///
+/// Holding on to the hash of a static key takes 64-bit atomics.  On targets without them, static
+/// keys are hashed whenever their hash is needed.  Either way, the hash never changes once it's
+/// computed, so keys can be used in maps even though clippy's `mutable_key_type` lint flags them.
+#[derive(Clone)]
 pub struct KeyData {
     name: SharedString,
     labels: Cow<'static, [Label]>,
+    hash: KeyHash,
 }
 
 impl KeyData {
@@ -32,9 +42,13 @@ impl KeyData {
         N: Into<SharedString>,
         L: IntoLabels,
     {
+        let name = name.into();
+        let labels = labels.into_labels();
+        let hash = KeyHash::new(key_hash(&name, &labels));
         Self {
-            name: name.into(),
-            labels: labels.into_labels().into(),
+            name,
+            labels: labels.into(),
+            hash,
         }
     }
 
@@ -45,6 +59,7 @@ impl KeyData {
         Self {
             name: SharedString::const_str(name),
             labels: Cow::Owned(Vec::new()),
+            hash: KeyHash::unhashed(),
         }
     }
 
@@ -55,6 +70,7 @@ impl KeyData {
         Self {
             name: SharedString::const_str(name),
             labels: Cow::Borrowed(labels),
+            hash: KeyHash::unhashed(),
         }
     }
 
@@ -64,7 +80,7 @@ impl KeyData {
     }
 
     /// Labels of this key, if they exist.
-    pub fn labels(&self) -> Iter<Label> {
+    pub fn labels(&self) -> Iter<'_, Label> {
         self.labels.iter()
     }
 
@@ -77,9 +93,18 @@ impl KeyData {
     {
         let new_name = f(self.name);
         self.name = new_name.into();
+        self.hash = KeyHash::new(key_hash(&self.name, &self.labels));
         self
     }
 
+    /// Precomputed hash of the name and labels of this key.
+    ///
+    /// The hash doesn't depend on where the key was created, so equal keys always have the same
+    /// hash, but it may change between versions of this crate.
+    pub fn get_hash(&self) -> u64 {
+        self.hash.get(|| key_hash(&self.name, &self.labels))
+    }
+
     /// Consumes this [`Key`], returning the name and any labels.
     pub fn into_parts(self) -> (SharedString, Vec<Label>) {
         (self.name, self.labels.into_owned())
@@ -95,13 +120,40 @@ impl KeyData {
         let mut labels = self.labels.clone().into_owned();
         labels.extend(extra_labels);
 
+        let hash = KeyHash::new(key_hash(&name, &labels));
         Self {
             name,
             labels: labels.into(),
+            hash,
         }
     }
 }
 
+impl PartialEq for KeyData {
+    fn eq(&self, other: &Self) -> bool {
+        self.get_hash() == other.get_hash()
+            && self.name == other.name
+            && self.labels == other.labels
+    }
+}
+
+impl Eq for KeyData {}
+
+impl Hash for KeyData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.get_hash());
+    }
+}
+
+impl fmt::Debug for KeyData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyData")
+            .field("name", &self.name)
+            .field("labels", &self.labels)
+            .finish()
+    }
+}
+
 impl fmt::Display for KeyData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.labels.is_empty() {
@@ -144,6 +196,102 @@ where
     }
 }
 
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Stands in for the hash of a static key or label which hasn't been computed yet.
+pub(crate) const UNHASHED: u64 = 0;
+
+/// Hash of a [`KeyData`], which static keys compute on first use.
+struct KeyHash(
+    #[cfg(atomic_u64)] core::sync::atomic::AtomicU64,
+    #[cfg(not(atomic_u64))] u64,
+);
+
+impl KeyHash {
+    const fn unhashed() -> Self {
+        #[cfg(atomic_u64)]
+        return KeyHash(core::sync::atomic::AtomicU64::new(UNHASHED));
+        #[cfg(not(atomic_u64))]
+        return KeyHash(UNHASHED);
+    }
+
+    fn new(hash: u64) -> Self {
+        #[cfg(atomic_u64)]
+        return KeyHash(core::sync::atomic::AtomicU64::new(hash));
+        #[cfg(not(atomic_u64))]
+        return KeyHash(hash);
+    }
+
+    fn load(&self) -> u64 {
+        #[cfg(atomic_u64)]
+        return self.0.load(core::sync::atomic::Ordering::Relaxed);
+        #[cfg(not(atomic_u64))]
+        return self.0;
+    }
+
+    /// Gets the hash, computing it with `f` if it hasn't been computed yet.
+    fn get<F: FnOnce() -> u64>(&self, f: F) -> u64 {
+        match self.load() {
+            UNHASHED => {
+                let hash = f();
+                // Whoever computes the hash gets the same one, so racing stores are harmless.
+                #[cfg(atomic_u64)]
+                self.0.store(hash, core::sync::atomic::Ordering::Relaxed);
+                hash
+            }
+            hash => hash,
+        }
+    }
+}
+
+impl Clone for KeyHash {
+    fn clone(&self) -> Self {
+        KeyHash::new(self.load())
+    }
+}
+
+/// Hashes a string with FNV-1a, followed by a terminator so that adjacent strings can't run
+/// together.
+fn hash_str(mut hash: u64, s: &str) -> u64 {
+    for byte in s.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    (hash ^ 0xff).wrapping_mul(FNV_PRIME)
+}
+
+/// Mixes a hash so that every bit of it depends on every bit of the input, as FNV-1a on its own
+/// leaves the upper bits poorly mixed.  This is the finalizer from MurmurHash3.
+///
+/// [`UNHASHED`] is left out, so that it can stand for a hash which hasn't been computed.
+fn finalize_hash(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^= hash >> 33;
+    if hash == UNHASHED {
+        1
+    } else {
+        hash
+    }
+}
+
+/// Hashes a label key and value.
+pub(crate) fn label_hash(key: &str, value: &str) -> u64 {
+    finalize_hash(hash_str(hash_str(FNV_OFFSET_BASIS, key), value))
+}
+
+/// Hashes a key name along with the hashes of its labels.
+fn key_hash(name: &str, labels: &[Label]) -> u64 {
+    let mut hash = hash_str(FNV_OFFSET_BASIS, name);
+    for label in labels {
+        hash = (hash ^ label.get_hash()).wrapping_mul(FNV_PRIME);
+    }
+    finalize_hash(hash)
+}
+
 /// A metric identifier.
 ///
 /// While [`KeyData`] holds the actual name and label data for a metric, [`Key`] works similar to
@@ -195,6 +343,7 @@ impl Key {
     }
 }
 
+#[allow(unused_attributes)]
 impl ops::Deref for Key {
     type Target = KeyData;
 
@@ -207,6 +356,7 @@ impl ops::Deref for Key {
     }
 }
 
+#[allow(unused_attributes)]
 impl AsRef<KeyData> for Key {
     #[must_use]
     fn as_ref(&self) -> &KeyData {
@@ -238,16 +388,358 @@ impl From<&'static KeyData> for Key {
     }
 }
 
+/// Key for a call site whose name is a constant, and whose labels, if any, are static.
+///
+/// Used by the emission macros when the name can only be read at runtime.  The key is built the
+/// first time the call site emits a metric, and lives for the rest of the program.  The name is
+/// compared on every call, since a local variable named like a constant may hold a different name
+/// each time, and any name other than the cached one gets a new key.  Without atomic
+/// compare-and-swap, a new key is built every time instead.
+#[doc(hidden)]
+pub struct StaticKey {
+    #[cfg(atomic_cas)]
+    key: core::sync::atomic::AtomicPtr<KeyData>,
+}
+
+impl StaticKey {
+    /// Creates an empty [`StaticKey`].
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(atomic_cas)]
+            key: core::sync::atomic::AtomicPtr::new(core::ptr::null_mut()),
+        }
+    }
+
+    /// Gets the key for the given name and labels, which must be the same on every call.
+    pub fn get(&self, name: &str, labels: &'static [Label]) -> Key {
+        #[cfg(atomic_cas)]
+        {
+            use alloc::boxed::Box;
+            use core::sync::atomic::Ordering;
+
+            let mut key = self.key.load(Ordering::Acquire);
+            if key.is_null() {
+                let new = Box::into_raw(Box::new(Self::key_data(name, labels)));
+                key = match self.key.compare_exchange(
+                    core::ptr::null_mut(),
+                    new,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => new,
+                    Err(existing) => {
+                        // Somebody else got there first, so we throw ours away.
+                        drop(unsafe { Box::from_raw(new) });
+                        existing
+                    }
+                };
+            }
+
+            // SAFETY: The pointer came from a leaked box which is never freed or replaced.
+            let key = unsafe { &*key };
+            if key.name() == name {
+                return Key::Borrowed(key);
+            }
+        }
+
+        Key::Owned(Self::key_data(name, labels))
+    }
+
+    fn key_data(name: &str, labels: &'static [Label]) -> KeyData {
+        let name = SharedString::from(String::from(name));
+        KeyData {
+            hash: KeyHash::new(key_hash(&name, labels)),
+            name,
+            labels: Cow::Borrowed(labels),
+        }
+    }
+}
+
+impl Default for StaticKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keys for a call site whose name and label keys are static, but whose label values aren't.
+///
+/// Used by the emission macros in order to avoid allocating a new [`KeyData`] every time such a
+/// call site emits a metric.  The first [`KeyCache::CAPACITY`] distinct sets of label values seen
+/// are turned into keys which live for the rest of the program, and are handed out by reference
+/// from then on.  Keys are looked up by the hash of the label values, without locking.
+///
+/// Once the cache is full, the keys already in it are still handed out, but any other set of label
+/// values gets a freshly allocated key, as every set of label values does without atomic
+/// compare-and-swap.
+#[doc(hidden)]
+pub struct KeyCache {
+    name: &'static str,
+    label_keys: &'static [&'static str],
+    #[cfg(atomic_cas)]
+    slots: core::sync::atomic::AtomicPtr<Vec<core::sync::atomic::AtomicPtr<KeyData>>>,
+    #[cfg(atomic_cas)]
+    len: core::sync::atomic::AtomicUsize,
+}
+
+impl KeyCache {
+    /// Maximum number of keys cached per call site.
+    pub const CAPACITY: usize = 32;
+
+    /// Number of slots in the table, which is kept at most half full so that lookups are short.
+    #[cfg(atomic_cas)]
+    const SLOTS: usize = Self::CAPACITY * 2;
+
+    /// Creates an empty [`KeyCache`].
+    pub const fn new(name: &'static str, label_keys: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            label_keys,
+            #[cfg(atomic_cas)]
+            slots: core::sync::atomic::AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(atomic_cas)]
+            len: core::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the key for the given label values, which are in the same order as the label keys.
+    pub fn get(&self, values: &[&str]) -> Key {
+        #[cfg(atomic_cas)]
+        {
+            use alloc::boxed::Box;
+            use core::sync::atomic::Ordering;
+
+            let hash = self.hash(values);
+            let slots = self.slots();
+            let mut i = hash as usize % Self::SLOTS;
+            let mut reserved = false;
+
+            // The table never holds more than half as many keys as it has slots, so we always
+            // reach either our key or an empty slot.
+            loop {
+                let mut key = slots[i].load(Ordering::Acquire);
+                if key.is_null() {
+                    // Our key isn't cached.  Reserve room for it before claiming the slot, so that
+                    // racing callers can't overfill the table, and give up if the table is full.
+                    if !reserved {
+                        if self.len.load(Ordering::Relaxed) >= Self::CAPACITY {
+                            break;
+                        }
+                        if self.len.fetch_add(1, Ordering::Relaxed) >= Self::CAPACITY {
+                            self.len.fetch_sub(1, Ordering::Relaxed);
+                            break;
+                        }
+                        reserved = true;
+                    }
+
+                    let new = Box::into_raw(Box::new(self.key_data(values)));
+                    match slots[i].compare_exchange(
+                        core::ptr::null_mut(),
+                        new,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    ) {
+                        // SAFETY: The box is leaked, and never freed or replaced from now on.
+                        Ok(_) => return Key::Borrowed(unsafe { &*new }),
+                        Err(existing) => {
+                            // Somebody else got the slot first, so we throw ours away.
+                            drop(unsafe { Box::from_raw(new) });
+                            key = existing;
+                        }
+                    }
+                }
+
+                // SAFETY: Slots only ever hold leaked boxes, which are never freed or replaced.
+                let key = unsafe { &*key };
+                if key.get_hash() == hash
+                    && key
+                        .labels()
+                        .map(|label| label.value())
+                        .eq(values.iter().copied())
+                {
+                    if reserved {
+                        self.len.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    return Key::Borrowed(key);
+                }
+                i = (i + 1) % Self::SLOTS;
+            }
+        }
+
+        Key::Owned(self.key_data(values))
+    }
+
+    /// Gets the slots of the table, allocating them on first use.
+    #[cfg(atomic_cas)]
+    fn slots(&self) -> &[core::sync::atomic::AtomicPtr<KeyData>] {
+        use alloc::boxed::Box;
+        use core::sync::atomic::{AtomicPtr, Ordering};
+
+        let mut slots = self.slots.load(Ordering::Acquire);
+        if slots.is_null() {
+            let new = (0..Self::SLOTS)
+                .map(|_| AtomicPtr::new(core::ptr::null_mut()))
+                .collect::<Vec<_>>();
+            let new = Box::into_raw(Box::new(new));
+            slots = match self.slots.compare_exchange(
+                core::ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => new,
+                Err(existing) => {
+                    drop(unsafe { Box::from_raw(new) });
+                    existing
+                }
+            };
+        }
+
+        // SAFETY: The slots are leaked, and never freed or replaced once set.
+        unsafe { &*slots }
+    }
+
+    /// Hashes the key for the given label values, the same way [`KeyData`] does, but without
+    /// building the key.
+    #[cfg(atomic_cas)]
+    fn hash(&self, values: &[&str]) -> u64 {
+        let mut hash = hash_str(FNV_OFFSET_BASIS, self.name);
+        for (key, value) in self.label_keys.iter().zip(values) {
+            hash = (hash ^ label_hash(key, value)).wrapping_mul(FNV_PRIME);
+        }
+        finalize_hash(hash)
+    }
+
+    fn key_data(&self, values: &[&str]) -> KeyData {
+        let labels = self
+            .label_keys
+            .iter()
+            .zip(values)
+            .map(|(key, value)| Label::new(*key, String::from(*value)))
+            .collect::<Vec<_>>();
+        KeyData::from_parts(SharedString::const_str(self.name), labels)
+    }
+}
+
+/// Wrapper for a constant name passed to the emission macros, used to find out its type.
+///
+/// Calling `__name` on a reference to the wrapper resolves to [`__StrName`] when the name is a
+/// `&str`, and to [`__OtherName`] for any other type, so the macros don't need to know the type of
+/// the name up front.
+#[doc(hidden)]
+pub struct __ConstName<'a, T>(pub &'a T);
+
+/// Constant name passed to the emission macros, as found by [`__ConstName`].
+#[doc(hidden)]
+#[derive(Debug, PartialEq)]
+pub enum __Name<'a> {
+    /// The name is a `&str`, of any lifetime.
+    Str(&'a str),
+    /// The name is of any other type, and has been converted into an owned name.
+    Owned(SharedString),
+}
+
+/// Picked for constant names which are a `&str`, of any lifetime.
+#[doc(hidden)]
+pub trait __StrName {
+    fn __name(&self) -> __Name<'_>;
+}
+
+impl<'a, 'b> __StrName for __ConstName<'a, &'b str> {
+    fn __name(&self) -> __Name<'_> {
+        __Name::Str(self.0)
+    }
+}
+
+/// Picked for constant names of any other type.
+#[doc(hidden)]
+pub trait __OtherName {
+    fn __name(&self) -> __Name<'_>;
+}
+
+impl<'a, 'b, T: Clone + Into<SharedString>> __OtherName for &'b __ConstName<'a, T> {
+    fn __name(&self) -> __Name<'_> {
+        __Name::Owned(self.0.clone().into())
+    }
+}
+
+/// Helper method to turn a constant name passed to the emission macros into an owned name.
+#[doc(hidden)]
+pub fn __owned_name<T: Clone + Into<SharedString>>(name: &T) -> SharedString {
+    name.clone().into()
+}
+
+/// Helper method to allow borrowing label values passed to the emission macros.
+#[doc(hidden)]
+pub fn __label_value<V: AsRef<str> + ?Sized>(value: &V) -> &str {
+    value.as_ref()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Key, KeyData};
-    use crate::Label;
+    use super::{
+        __ConstName, __Name, __OtherName, __StrName, Key, KeyCache, KeyData, StaticKey, UNHASHED,
+    };
+    use crate::{Label, SharedString};
     use std::collections::HashMap;
 
     static BORROWED_BASIC: KeyData = KeyData::from_static_name("name");
     static LABELS: [Label; 1] = [Label::from_static_parts("key", "value")];
     static BORROWED_LABELS: KeyData = KeyData::from_static_parts("name", &LABELS);
 
+    #[test]
+    fn test_const_name() {
+        const STATIC: &str = "static";
+        const OWNED: String = String::new();
+
+        let local = String::from("local");
+        let local: &str = &local;
+
+        assert_eq!(__ConstName(&STATIC).__name(), __Name::Str("static"));
+        assert_eq!(__ConstName(&local).__name(), __Name::Str("local"));
+        assert_eq!(
+            (&__ConstName(&OWNED)).__name(),
+            __Name::Owned(SharedString::from(OWNED))
+        );
+    }
+
+    #[test]
+    fn test_static_key() {
+        static LABELS: [Label; 1] = [Label::from_static_parts("kind", "timeout")];
+        static KEY: StaticKey = StaticKey::new();
+
+        let first = KEY.get("name", &LABELS);
+        let second = KEY.get("name", &LABELS);
+        assert_eq!(
+            first,
+            Key::from(KeyData::from_static_parts("name", &LABELS))
+        );
+        match (first, second) {
+            (Key::Borrowed(a), Key::Borrowed(b)) => assert!(core::ptr::eq(a, b)),
+            _ => panic!("expected borrowed keys"),
+        }
+
+        let other = String::from("other");
+        let third = KEY.get(&other, &LABELS);
+        assert_eq!(
+            third,
+            Key::from(KeyData::from_static_parts("other", &LABELS))
+        );
+        assert!(matches!(third, Key::Owned(_)));
+        assert!(matches!(KEY.get("name", &LABELS), Key::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_static_key_hash() {
+        static LABELS: [Label; 1] = [Label::from_static_parts("kind", "timeout")];
+        static KEY: KeyData = KeyData::from_static_parts("name", &LABELS);
+
+        let hash = KeyData::from_parts("name", vec![Label::new("kind", "timeout")]).get_hash();
+        assert_eq!(KEY.hash.load(), UNHASHED);
+        assert_eq!(KEY.get_hash(), hash);
+        #[cfg(atomic_u64)]
+        assert_eq!(KEY.hash.load(), hash);
+    }
+
     #[test]
     fn test_keydata_eq_and_hash() {
         let mut keys = HashMap::new();
@@ -298,6 +790,22 @@ mod tests {
         assert_eq!(previous, Some(&43));
     }
 
+    #[test]
+    fn test_keydata_get_hash() {
+        let owned_labels = KeyData::from_parts("name", LABELS.to_vec());
+        assert_eq!(owned_labels.get_hash(), BORROWED_LABELS.get_hash());
+        assert_eq!(owned_labels.clone().get_hash(), owned_labels.get_hash());
+        assert_ne!(BORROWED_BASIC.get_hash(), BORROWED_LABELS.get_hash());
+
+        // Moving a label value into the name is a different key, with a different hash.
+        let moved = KeyData::from_parts("namevalue", vec![Label::new("key", "")]);
+        assert_ne!(moved.get_hash(), BORROWED_LABELS.get_hash());
+
+        let renamed = owned_labels.map_name(|name| format!("{}2", name));
+        let expected = KeyData::from_parts("name2", LABELS.to_vec());
+        assert_eq!(renamed.get_hash(), expected.get_hash());
+    }
+
     #[test]
     fn test_key_data_proper_display() {
         let key1 = KeyData::from_name("foobar");
@@ -355,4 +863,48 @@ mod tests {
         assert_ne!(Key::Owned(owned_a.clone()), Key::Borrowed(&STATIC_B));
         assert_ne!(Key::Owned(owned_b.clone()), Key::Borrowed(&STATIC_A));
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_key_cache() {
+        static KEYS: KeyCache = KeyCache::new("name", &["method", "status"]);
+
+        let get = KEYS.get(&["GET", "200"]);
+        let expected = KeyData::from_parts("name", &[("method", "GET"), ("status", "200")]);
+        assert_eq!(get.as_ref(), &expected);
+        assert!(matches!(get, Key::Borrowed(_)));
+
+        // The same label values get the very same key.
+        let method = String::from("GET");
+        match (KEYS.get(&[method.as_str(), "200"]), get) {
+            (Key::Borrowed(a), Key::Borrowed(b)) => assert!(std::ptr::eq(a, b)),
+            _ => panic!("expected borrowed keys"),
+        }
+
+        // Every key handed out matches the key built from scratch, hash included.
+        for status in 1..KeyCache::CAPACITY {
+            let status = status.to_string();
+            let key = KEYS.get(&["POST", &status]);
+            let labels = vec![
+                Label::new("method", "POST"),
+                Label::new("status", status.clone()),
+            ];
+            let expected = KeyData::from_parts("name", labels);
+            assert_eq!(key.get_hash(), expected.get_hash());
+            assert_eq!(key.as_ref(), &expected);
+            assert!(matches!(key, Key::Borrowed(_)));
+        }
+
+        // Once the cache is full, new keys are allocated instead, but cached keys are still used.
+        let overflow = KEYS.get(&["PUT", "200"]);
+        let expected = KeyData::from_parts("name", &[("method", "PUT"), ("status", "200")]);
+        assert_eq!(overflow.as_ref(), &expected);
+        assert!(matches!(overflow, Key::Owned(_)));
+        assert!(matches!(KEYS.get(&["PUT", "200"]), Key::Owned(_)));
+        assert!(matches!(KEYS.get(&["GET", "200"]), Key::Borrowed(_)));
+        assert_eq!(
+            KEYS.len.load(std::sync::atomic::Ordering::Relaxed),
+            KeyCache::CAPACITY
+        );
+    }
 }
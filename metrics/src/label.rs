@@ -1,5 +1,12 @@
-use crate::SharedString;
+use crate::{
+    key::{label_hash, UNHASHED},
+    SharedString,
+};
 use alloc::vec::Vec;
+use core::{
+    fmt,
+    hash::{Hash, Hasher},
+};
 
 /// Metadata for a metric key in the for of a key/value pair.
 ///
@@ -11,8 +18,16 @@ use alloc::vec::Vec;
 /// the request currently being processed, or the request path being processedd.  If a codepath
 /// branched internally -- for example, an optimized path and a fallback path -- you may wish to
 /// add a label that tracks which codepath was taken.
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
-pub struct Label(pub(crate) SharedString, pub(crate) SharedString);
+///
+/// The hash of the key and value is computed when a [`Label`] is created, so that keys can be
+/// hashed without hashing every label again.  Static labels are hashed when the key holding them
+/// is.
+#[derive(Clone)]
+pub struct Label(
+    pub(crate) SharedString,
+    pub(crate) SharedString,
+    pub(crate) u64,
+);
 
 impl Label {
     /// Creates a [`Label`] from a key and value.
@@ -21,12 +36,18 @@ impl Label {
         K: Into<SharedString>,
         V: Into<SharedString>,
     {
-        Label(key.into(), value.into())
+        let (key, value) = (key.into(), value.into());
+        let hash = label_hash(&key, &value);
+        Label(key, value, hash)
     }
 
     /// Creates a [`Label`] from a static key and value.
     pub const fn from_static_parts(key: &'static str, value: &'static str) -> Self {
-        Label(SharedString::const_str(key), SharedString::const_str(value))
+        Label(
+            SharedString::const_str(key),
+            SharedString::const_str(value),
+            UNHASHED,
+        )
     }
 
     /// Key of this label.
@@ -39,12 +60,43 @@ impl Label {
         self.1.as_ref()
     }
 
+    /// Gets the hash of this label's key and value.
+    pub(crate) fn get_hash(&self) -> u64 {
+        match self.2 {
+            UNHASHED => label_hash(&self.0, &self.1),
+            hash => hash,
+        }
+    }
+
     /// Consumes this [`Label`], returning the key and value.
     pub fn into_parts(self) -> (SharedString, SharedString) {
         (self.0, self.1)
     }
 }
 
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0 && self.1 == other.1
+    }
+}
+
+impl Eq for Label {}
+
+impl Hash for Label {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.get_hash());
+    }
+}
+
+impl fmt::Debug for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Label")
+            .field(&self.0)
+            .field(&self.1)
+            .finish()
+    }
+}
+
 impl<K, V> From<&(K, V)> for Label
 where
     K: Into<SharedString> + Clone,
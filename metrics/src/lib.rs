@@ -11,10 +11,20 @@
 //! Metrics are emitted by utilizing the registration or emission macros.  There is a macro for
 //! registering and emitting each fundamental metric type:
 //! - [`register_counter!`] and [`increment_counter!`] for counters
-//! - [`register_gauge!`] and [`update_gauge!`] for gauges
+//! - [`register_gauge!`] and [`update_gauge!`] for gauges, as well as [`increment_gauge!`] and
+//!   [`decrement_gauge!`] for adjusting a gauge relative to its current value
 //! - [`register_histogram!`] and [`record_histogram!`] for histograms
 //!
-//! There is also an [`increment!`] macro, which is shorthand for incrementing a counter by one.
+//! There is also an [`increment!`] macro, which is shorthand for incrementing a counter by one,
+//! and an [`absolute_counter!`] macro, which sets a counter to a total that is tracked elsewhere.
+//!
+//! Whole functions can be instrumented with the [`timed`] and [`counted`] attributes, and blocks of
+//! code can be timed with a [`HistogramTimer`], started from a registered histogram.
+//!
+//! With the `catalog` feature enabled, every call site of these macros is also listed, at startup,
+//! by `catalog`, along with the kind, label keys, unit and description of the metric.  This can be
+//! used to document the metrics emitted by an application, or to check that no metric name is used
+//! as more than one kind of metric.
 //!
 //! In order to register or emit a metric, you need a way to record these events, which is where
 //! [`Recorder`] comes into play.
@@ -96,27 +106,90 @@
 //!
 //! ```rust
 //! use log::info;
-//! use metrics::{Key, Recorder, Unit};
-//! use metrics::SetRecorderError;
+//! use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, Recorder, Unit};
+//! use metrics::{SetRecorderError, SharedString};
+//! use std::sync::Arc;
+//!
+//! struct LogHandle(Key);
+//!
+//! impl CounterFn for LogHandle {
+//!     fn increment(&self, value: u64) {
+//!         info!("counter '{}' -> {}", self.0, value);
+//!     }
+//!
+//!     fn absolute(&self, value: u64) {
+//!         info!("counter '{}' -> ={}", self.0, value);
+//!     }
+//! }
+//!
+//! impl GaugeFn for LogHandle {
+//!     fn update(&self, value: f64) {
+//!         info!("gauge '{}' -> {}", self.0, value);
+//!     }
+//!
+//!     fn increment(&self, value: f64) {
+//!         info!("gauge '{}' -> +{}", self.0, value);
+//!     }
+//!
+//!     fn decrement(&self, value: f64) {
+//!         info!("gauge '{}' -> -{}", self.0, value);
+//!     }
+//! }
+//!
+//! impl HistogramFn for LogHandle {
+//!     fn record(&self, value: f64) {
+//!         info!("histogram '{}' -> {}", self.0, value);
+//!     }
+//! }
 //!
 //! struct LogRecorder;
 //!
 //! impl Recorder for LogRecorder {
-//!     fn register_counter(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {}
+//!     fn register_counter(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) -> Counter {
+//!         Counter::from_arc(Arc::new(LogHandle(key)))
+//!     }
 //!
-//!     fn register_gauge(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {}
+//!     fn register_gauge(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) -> Gauge {
+//!         Gauge::from_arc(Arc::new(LogHandle(key)))
+//!     }
 //!
-//!     fn register_histogram(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {}
+//!     fn register_histogram(&self, key: Key, _unit: Option<Unit>, _description: Option<&'static str>) -> Histogram {
+//!         Histogram::from_arc(Arc::new(LogHandle(key)))
+//!     }
+//!
+//!     fn describe_counter(&self, name: SharedString, _unit: Option<Unit>, description: SharedString) {
+//!         info!("counter '{}' -> {}", name, description);
+//!     }
+//!
+//!     fn describe_gauge(&self, name: SharedString, _unit: Option<Unit>, description: SharedString) {
+//!         info!("gauge '{}' -> {}", name, description);
+//!     }
+//!
+//!     fn describe_histogram(&self, name: SharedString, _unit: Option<Unit>, description: SharedString) {
+//!         info!("histogram '{}' -> {}", name, description);
+//!     }
 //!
 //!     fn increment_counter(&self, key: Key, value: u64) {
 //!         info!("counter '{}' -> {}", key, value);
 //!     }
 //!
+//!     fn absolute_counter(&self, key: Key, value: u64) {
+//!         info!("counter '{}' -> ={}", key, value);
+//!     }
+//!
 //!     fn update_gauge(&self, key: Key, value: f64) {
 //!         info!("gauge '{}' -> {}", key, value);
 //!     }
 //!
-//!     fn record_histogram(&self, key: Key, value: u64) {
+//!     fn increment_gauge(&self, key: Key, value: f64) {
+//!         info!("gauge '{}' -> +{}", key, value);
+//!     }
+//!
+//!     fn decrement_gauge(&self, key: Key, value: f64) {
+//!         info!("gauge '{}' -> -{}", key, value);
+//!     }
+//!
+//!     fn record_histogram(&self, key: Key, value: f64) {
 //!         info!("histogram '{}' -> {}", key, value);
 //!     }
 //! }
@@ -141,20 +214,37 @@
 //! identifier, which we handle by using [`Key`].
 //!
 //! [`Key`] itself is a wrapper for [`KeyData`], which holds not only the name of a metric, but
-//! potentially holds labels for it as well.  The name of a metric must always be a literal string.
-//! The labels are a key/value pair, where both components are strings as well.
+//! potentially holds labels for it as well.  The name of a metric is a string, and the labels are
+//! a key/value pair, where both components are strings as well.
 //!
 //! Internally, `metrics` uses a clone-on-write "smart pointer" for these values to optimize cases
 //! where the values are static strings, which can provide significant performance benefits.  These
 //! smart pointers can also hold owned `String` values, though, so users can mix and match static
-//! strings and owned strings for labels without issue. Metric names, as mentioned above, are always
-//! static strings.
+//! strings and owned strings for names and labels without issue.
+//!
+//! The macros accept any expression which converts into a [`SharedString`] as the name of a metric.
+//! Names which are known at compile time -- string literals, `concat!` and `stringify!` -- are
+//! cached statically alongside their labels, when possible, so that emitting the metric doesn't
+//! allocate.  Paths to constants or statics named in `SCREAMING_SNAKE_CASE` are cached the first
+//! time the metric is emitted, as long as they're a `&str`.  Any other name is evaluated
+//! every time the metric is emitted, and only string literals are checked for being valid names
+//! when compiling.
+//!
+//! Label values given inline, as in `"method" => request.method()`, only need to be borrowable as
+//! a `&str` when the name is known at compile time.  The key for each distinct set of label values
+//! is then cached by the call site, up to a limit, so that emitting the metric doesn't allocate
+//! either.
 //!
 //! Two [`Key`] objects can be checked for equality and considered to point to the same metric if
 //! they are equal.  Equality checks both the name of the key and the labels of a key.  Labels are
 //! _not_ sorted prior to checking for equality, but insertion order is maintained, so any [`Key`]
 //! constructed from the same set of labels in the same order should be equal.
 //!
+//! A [`KeyData`] computes the hash of its name and labels when it's created, or on first use for
+//! static keys, so keys are never rehashed.  Recorders which store metrics in a map can take
+//! advantage of this by using a hasher which only mixes in the precomputed hash, such as
+//! `KeyHasher` in `metrics-util`.
+//!
 //! It is an implementation detail if a recorder wishes to do an deeper equality check that ignores
 //! the order of labels, but practically speaking, metric emission, and thus labels, should be
 //! fixed in ordering in nearly all cases, and so it isn't typically a problem.
@@ -179,6 +269,21 @@
 //! value, so, for example, a counter should be initialized to zero, a histogram would have no
 //! values, and so on.
 //!
+//! Registration also hands back a handle -- [`Counter`], [`Gauge`], or [`Histogram`] -- which is
+//! bound to the registered metric.  Callers in hot loops can hold on to these handles and update
+//! the metric directly, skipping the key lookup that the recorder would otherwise perform for every
+//! emission.  Recorders provide the storage behind a handle by implementing [`CounterFn`],
+//! [`GaugeFn`], or [`HistogramFn`], typically by handing out the same atomic storage they use
+//! internally.
+//!
+//! ## Description
+//!
+//! Metadata can also be provided separately from registration, via [`describe_counter!`],
+//! [`describe_gauge!`], and [`describe_histogram!`].  These set the unit and description for every
+//! metric with the given name, regardless of its labels, and don't create a time series on their
+//! own.  Unlike registration, the description can be built at runtime, as it's taken as a
+//! [`SharedString`] rather than a static string.
+//!
 //! ## Emission
 //!
 //! Likewise, records must handle the emission of metrics as well.
@@ -210,6 +315,17 @@
 //! should only be used on platforms which do not support atomic operations, such as embedded
 //! environments.
 //!
+//! Recorders installed with any of the above are fixed for the lifetime of the process.  If the
+//! exporter needs to be reconfigured at runtime, such as when reloading configuration, use
+//! [`replace_recorder`] instead, which can be called again later to swap in a new recorder.
+//!
+//! ## Thread-local recorders
+//!
+//! A recorder can also be installed for the current thread only, for the duration of a closure,
+//! via [`with_local_recorder`].  It takes precedence over the global recorder, and doesn't require
+//! a static lifetime, which makes it a good fit for unit tests that want to assert on the metrics
+//! emitted by the code under test without interfering with other tests running in parallel.
+//!
 //! [metrics-exporter-tcp]: https://docs.rs/metrics-exporter-tcp
 //! [metrics-exporter-prometheus]: https://docs.rs/metrics-exporter-prometheus
 //! [metrics-util]: https://docs.rs/metrics-util
@@ -224,6 +340,9 @@ use proc_macro_hack::proc_macro_hack;
 mod common;
 pub use self::common::*;
 
+mod handles;
+pub use self::handles::*;
+
 mod key;
 pub use self::key::*;
 
@@ -233,6 +352,19 @@ pub use self::label::*;
 mod recorder;
 pub use self::recorder::*;
 
+#[cfg(feature = "catalog")]
+mod catalog;
+#[cfg(feature = "catalog")]
+pub use self::catalog::*;
+
+/// Registers a call site in the catalog, which does nothing without the `catalog` feature.
+#[cfg(not(feature = "catalog"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __catalog_callsite {
+    ($callsite:expr) => {};
+}
+
 /// Registers a counter.
 ///
 /// Counters represent a single monotonic value, which means the value can only be incremented, not
@@ -242,6 +374,9 @@ pub use self::recorder::*;
 /// recorder does anything with the description is implementation defined.  Labels can also be
 /// specified when registering a metric.
 ///
+/// Returns a [`Counter`] handle bound to the registered metric.  If no recorder is installed, a
+/// no-op handle is returned.
+///
 /// # Example
 /// ```
 /// # use metrics::register_counter;
@@ -272,6 +407,10 @@ pub use self::recorder::*;
 /// let dynamic_val = "woo";
 /// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
 /// register_counter!("some_metric_name", &labels);
+///
+/// // The returned handle can be held on to and updated directly:
+/// let counter = register_counter!("some_metric_name", "service" => "http");
+/// counter.increment(1);
 /// # }
 /// ```
 #[proc_macro_hack]
@@ -286,6 +425,9 @@ pub use metrics_macros::register_counter;
 /// recorder does anything with the description is implementation defined.  Labels can also be
 /// specified when registering a metric.
 ///
+/// Returns a [`Gauge`] handle bound to the registered metric.  If no recorder is installed, a
+/// no-op handle is returned.
+///
 /// # Example
 /// ```
 /// # use metrics::register_gauge;
@@ -316,12 +458,16 @@ pub use metrics_macros::register_counter;
 /// let dynamic_val = "woo";
 /// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
 /// register_gauge!("some_metric_name", &labels);
+///
+/// // The returned handle can be held on to and updated directly:
+/// let gauge = register_gauge!("some_metric_name", "service" => "http");
+/// gauge.update(42.0);
 /// # }
 /// ```
 #[proc_macro_hack]
 pub use metrics_macros::register_gauge;
 
-/// Records a histogram.
+/// Registers a histogram.
 ///
 /// Histograms measure the distribution of values for a given set of measurements, and start with no
 /// initial values.
@@ -330,6 +476,9 @@ pub use metrics_macros::register_gauge;
 /// recorder does anything with the description is implementation defined.  Labels can also be
 /// specified when registering a metric.
 ///
+/// Returns a [`Histogram`] handle bound to the registered metric.  If no recorder is installed, a
+/// no-op handle is returned.
+///
 /// # Example
 /// ```
 /// # use metrics::register_histogram;
@@ -360,11 +509,102 @@ pub use metrics_macros::register_gauge;
 /// let dynamic_val = "woo";
 /// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
 /// register_histogram!("some_metric_name", &labels);
+///
+/// // The returned handle can be held on to and updated directly:
+/// let histogram = register_histogram!("some_metric_name", "service" => "http");
+/// histogram.record(42);
 /// # }
 /// ```
 #[proc_macro_hack]
 pub use metrics_macros::register_histogram;
 
+/// Describes a counter.
+///
+/// Sets the description, and optionally the unit, of every counter with the given name, regardless
+/// of its labels.  Describing a counter does not register it, so nothing shows up in the output of
+/// the installed exporter until the counter is registered or emitted.
+///
+/// The description can be anything that converts into a [`SharedString`], so it can be built at
+/// runtime.  Whether or not the installed recorder does anything with the unit or description is
+/// implementation defined.
+///
+/// # Example
+/// ```
+/// # use metrics::describe_counter;
+/// # use metrics::Unit;
+/// # fn main() {
+/// // Describing a counter:
+/// describe_counter!("some_metric_name", "total number of bytes");
+///
+/// // Providing a unit as well:
+/// describe_counter!("some_metric_name", Unit::Bytes, "total number of bytes");
+///
+/// // Descriptions don't need to be static:
+/// let component = "http";
+/// describe_counter!("some_metric_name", format!("total number of bytes for {}", component));
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::describe_counter;
+
+/// Describes a gauge.
+///
+/// Sets the description, and optionally the unit, of every gauge with the given name, regardless
+/// of its labels.  Describing a gauge does not register it, so nothing shows up in the output of
+/// the installed exporter until the gauge is registered or emitted.
+///
+/// The description can be anything that converts into a [`SharedString`], so it can be built at
+/// runtime.  Whether or not the installed recorder does anything with the unit or description is
+/// implementation defined.
+///
+/// # Example
+/// ```
+/// # use metrics::describe_gauge;
+/// # use metrics::Unit;
+/// # fn main() {
+/// // Describing a gauge:
+/// describe_gauge!("some_metric_name", "current size of the buffer pool");
+///
+/// // Providing a unit as well:
+/// describe_gauge!("some_metric_name", Unit::Bytes, "current size of the buffer pool");
+///
+/// // Descriptions don't need to be static:
+/// let component = "http";
+/// describe_gauge!("some_metric_name", format!("current size of the buffer pool for {}", component));
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::describe_gauge;
+
+/// Describes a histogram.
+///
+/// Sets the description, and optionally the unit, of every histogram with the given name, regardless
+/// of its labels.  Describing a histogram does not register it, so nothing shows up in the output of
+/// the installed exporter until the histogram is registered or emitted.
+///
+/// The description can be anything that converts into a [`SharedString`], so it can be built at
+/// runtime.  Whether or not the installed recorder does anything with the unit or description is
+/// implementation defined.
+///
+/// # Example
+/// ```
+/// # use metrics::describe_histogram;
+/// # use metrics::Unit;
+/// # fn main() {
+/// // Describing a histogram:
+/// describe_histogram!("some_metric_name", "request handler duration");
+///
+/// // Providing a unit as well:
+/// describe_histogram!("some_metric_name", Unit::Nanoseconds, "request handler duration");
+///
+/// // Descriptions don't need to be static:
+/// let component = "http";
+/// describe_histogram!("some_metric_name", format!("request handler duration for {}", component));
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::describe_histogram;
+
 /// Increments a counter by one.
 ///
 /// Counters represent a single monotonic value, which means the value can only be incremented, not
@@ -408,11 +648,47 @@ pub use metrics_macros::increment;
 /// let dynamic_val = "woo";
 /// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
 /// counter!("some_metric_name", 12, &labels);
+///
+/// // Names can come from constants, or be built at runtime:
+/// const REQUESTS: &str = "http.requests";
+/// counter!(REQUESTS, 12);
+///
+/// let prefix = "http";
+/// counter!(format!("{}.responses", prefix), 12);
 /// # }
 /// ```
 #[proc_macro_hack]
 pub use metrics_macros::counter;
 
+/// Sets a counter to an absolute value.
+///
+/// Counters represent a single monotonic value, which means the value can only be incremented, not
+/// decremented, and always starts out with an initial value of zero.
+///
+/// This is useful for mirroring counters which are tracked externally, such as interface
+/// statistics from the operating system, where only the running total is available.  As counters
+/// can never go backwards, the counter is only updated if the given value is greater than its
+/// current value.
+///
+/// # Example
+/// ```
+/// # use metrics::absolute_counter;
+/// # fn main() {
+/// // A basic counter:
+/// absolute_counter!("some_metric_name", 12);
+///
+/// // Specifying labels:
+/// absolute_counter!("some_metric_name", 12, "service" => "http");
+///
+/// // We can also pass labels by giving a vector or slice of key/value pairs:
+/// let dynamic_val = "woo";
+/// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
+/// absolute_counter!("some_metric_name", 12, &labels);
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::absolute_counter;
+
 /// Updates a gauge.
 ///
 /// Gauges represent a single value that can go up or down over time, and always starts out with an
@@ -437,18 +713,68 @@ pub use metrics_macros::counter;
 #[proc_macro_hack]
 pub use metrics_macros::gauge;
 
+/// Increments a gauge.
+///
+/// Gauges represent a single value that can go up or down over time, and always starts out with an
+/// initial value of zero.  Increments are applied atomically by the recorder, which makes this
+/// suitable for tracking values such as in-flight requests from many threads at once.
+///
+/// # Example
+/// ```
+/// # use metrics::increment_gauge;
+/// # fn main() {
+/// // A basic gauge:
+/// increment_gauge!("some_metric_name", 42.2222);
+///
+/// // Specifying labels:
+/// increment_gauge!("some_metric_name", 66.6666, "service" => "http");
+///
+/// // We can also pass labels by giving a vector or slice of key/value pairs:
+/// let dynamic_val = "woo";
+/// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
+/// increment_gauge!("some_metric_name", 42.42, &labels);
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::increment_gauge;
+
+/// Decrements a gauge.
+///
+/// Gauges represent a single value that can go up or down over time, and always starts out with an
+/// initial value of zero.  Decrements are applied atomically by the recorder, which makes this
+/// suitable for tracking values such as in-flight requests from many threads at once.
+///
+/// # Example
+/// ```
+/// # use metrics::decrement_gauge;
+/// # fn main() {
+/// // A basic gauge:
+/// decrement_gauge!("some_metric_name", 42.2222);
+///
+/// // Specifying labels:
+/// decrement_gauge!("some_metric_name", 66.6666, "service" => "http");
+///
+/// // We can also pass labels by giving a vector or slice of key/value pairs:
+/// let dynamic_val = "woo";
+/// let labels = [("dynamic_key", format!("{}!", dynamic_val))];
+/// decrement_gauge!("some_metric_name", 42.42, &labels);
+/// # }
+/// ```
+#[proc_macro_hack]
+pub use metrics_macros::decrement_gauge;
+
 /// Records a histogram.
 ///
 /// Histograms measure the distribution of values for a given set of measurements, and start with no
 /// initial values.
 ///
 /// # Implicit conversions
-/// Histograms are represented as `u64` values, but often come from another source, such as a time
-/// measurement.  By default, `histogram!` will accept a `u64` directly or a
+/// Histograms are represented as `f64` values, but often come from another source, such as a time
+/// measurement.  By default, `histogram!` will accept a `f64` or `u64` directly or a
 /// [`Duration`](std::time::Duration), which uses the nanoseconds total as the converted value.
 ///
 /// External libraries and applications can create their own conversions by implementing the
-/// [`IntoU64`] trait for their types, which is required for the value being passed to `histogram!`.
+/// [`IntoF64`] trait for their types, which is required for the value being passed to `histogram!`.
 ///
 /// # Example
 /// ```
@@ -458,6 +784,9 @@ pub use metrics_macros::gauge;
 /// // A basic histogram:
 /// histogram!("some_metric_name", 34);
 ///
+/// // Fractional values are supported as well:
+/// histogram!("some_metric_name", 0.25);
+///
 /// // An implicit conversion from `Duration`:
 /// let d = Duration::from_millis(17);
 /// histogram!("some_metric_name", d);
@@ -473,3 +802,68 @@ pub use metrics_macros::gauge;
 /// ```
 #[proc_macro_hack]
 pub use metrics_macros::histogram;
+
+/// Times every call to a function, recording the duration into a histogram.
+///
+/// The duration covers the entire call, from entry until the function returns, and is recorded as a
+/// [`Duration`](std::time::Duration), and so in nanoseconds.  The histogram is registered with
+/// [`Unit::Nanoseconds`] the first time the function returns, so that exporters know what unit the
+/// samples are in.  Async functions are supported, in which case the duration covers the entire
+/// lifetime of the returned future until it completes.
+///
+/// By default, the histogram is named after the path of the function, which is the path of the
+/// module it is defined in followed by its name, with a `_duration` suffix, i.e.
+/// `my_crate::db::run_query_duration`.  A different name can be given with `name = "..."`.
+///
+/// For functions returning a [`Result`], passing `result` adds a `result` label to the histogram,
+/// set to `ok` or `error` depending on the outcome of the call.
+///
+/// # Example
+/// ```
+/// # use metrics::timed;
+/// # use std::io;
+/// // Recorded under the path of the function, as `..::run_query_duration`:
+/// #[timed]
+/// fn run_query(query: &str) -> u64 {
+///     query.len() as u64
+/// }
+///
+/// // Recorded under a custom name, with a label for the outcome:
+/// #[timed(name = "db.connect_time", result)]
+/// async fn connect(addr: &str) -> io::Result<()> {
+///     Ok(())
+/// }
+/// # fn main() {}
+/// ```
+pub use metrics_macros::timed;
+
+/// Counts every call to a function, incrementing a counter by one.
+///
+/// Calls are counted once they return, such that calls which panic are not counted.  Async
+/// functions are supported, in which case calls are counted once the returned future completes.
+///
+/// By default, the counter is named after the path of the function, which is the path of the
+/// module it is defined in followed by its name, with a `_calls` suffix, i.e.
+/// `my_crate::db::run_query_calls`.  A different name can be given with `name = "..."`.
+///
+/// For functions returning a [`Result`], passing `result` adds a `result` label to the counter, set
+/// to `ok` or `error` depending on the outcome of the call.
+///
+/// # Example
+/// ```
+/// # use metrics::counted;
+/// # use std::io;
+/// // Counted under the path of the function, as `..::run_query_calls`:
+/// #[counted]
+/// fn run_query(query: &str) -> u64 {
+///     query.len() as u64
+/// }
+///
+/// // Counted under a custom name, with a label for the outcome:
+/// #[counted(name = "db.connections", result)]
+/// async fn connect(addr: &str) -> io::Result<()> {
+///     Ok(())
+/// }
+/// # fn main() {}
+/// ```
+pub use metrics_macros::counted;
@@ -1,13 +1,30 @@
-use crate::{Key, Unit};
+use crate::{Counter, Gauge, Histogram, Key, SharedString, Unit};
+use alloc::boxed::Box;
 use core::fmt;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+#[cfg(feature = "std")]
+use arc_swap::ArcSwapOption;
+#[cfg(feature = "std")]
+use std::{cell::Cell, ptr::NonNull, sync::Arc};
+
 static mut RECORDER: &'static dyn Recorder = &NoopRecorder;
 static STATE: AtomicUsize = AtomicUsize::new(0);
 
+#[cfg(feature = "std")]
+static REPLACEABLE_RECORDER: ArcSwapOption<Box<dyn Recorder + Send + Sync>> =
+    ArcSwapOption::const_empty();
+
+#[cfg(feature = "std")]
+thread_local! {
+    static LOCAL_RECORDER: Cell<Option<NonNull<dyn Recorder>>> = Cell::new(None);
+}
+
 const UNINITIALIZED: usize = 0;
 const INITIALIZING: usize = 1;
 const INITIALIZED: usize = 2;
+#[cfg(feature = "std")]
+const REPLACEABLE: usize = 3;
 
 static SET_RECORDER_ERROR: &str =
     "attempted to set a recorder after the metrics system was already initialized";
@@ -23,7 +40,15 @@ pub trait Recorder {
     /// not a metric can be reregistered to provide a unit/description, if one was already passed
     /// or not, as well as how units/descriptions are used by the underlying recorder, is an
     /// implementation detail.
-    fn register_counter(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>);
+    ///
+    /// Returns a [`Counter`] handle which can be used to update the counter directly, without going
+    /// through the recorder for every operation.
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter;
 
     /// Registers a gauge.
     ///
@@ -31,7 +56,15 @@ pub trait Recorder {
     /// not a metric can be reregistered to provide a unit/description, if one was already passed
     /// or not, as well as how units/descriptions are used by the underlying recorder, is an
     /// implementation detail.
-    fn register_gauge(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>);
+    ///
+    /// Returns a [`Gauge`] handle which can be used to update the gauge directly, without going
+    /// through the recorder for every operation.
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge;
 
     /// Registers a histogram.
     ///
@@ -39,34 +72,176 @@ pub trait Recorder {
     /// not a metric can be reregistered to provide a unit/description, if one was already passed
     /// or not, as well as how units/descriptions are used by the underlying recorder, is an
     /// implementation detail.
-    fn register_histogram(&self, key: Key, unit: Option<Unit>, description: Option<&'static str>);
+    ///
+    /// Returns a [`Histogram`] handle which can be used to update the histogram directly, without
+    /// going through the recorder for every operation.
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram;
+
+    /// Describes a counter.
+    ///
+    /// Sets the unit and description of every counter with the given name, regardless of its
+    /// labels.  Describing a metric does not register it, so no time series is created until the
+    /// counter is registered or emitted.  Describing the same name again should replace the
+    /// previous unit and description.
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString);
+
+    /// Describes a gauge.
+    ///
+    /// Sets the unit and description of every gauge with the given name, regardless of its
+    /// labels.  Describing a metric does not register it, so no time series is created until the
+    /// gauge is registered or emitted.  Describing the same name again should replace the
+    /// previous unit and description.
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString);
+
+    /// Describes a histogram.
+    ///
+    /// Sets the unit and description of every histogram with the given name, regardless of its
+    /// labels.  Describing a metric does not register it, so no time series is created until the
+    /// histogram is registered or emitted.  Describing the same name again should replace the
+    /// previous unit and description.
+    fn describe_histogram(&self, name: SharedString, unit: Option<Unit>, description: SharedString);
 
     /// Increments a counter.
     fn increment_counter(&self, key: Key, value: u64);
 
+    /// Sets a counter to an absolute value.
+    ///
+    /// This is meant for mirroring counters that are tracked externally, such as counters read
+    /// from the operating system, which already provide a monotonic total.  Counters must never go
+    /// backwards, so implementations should only update the counter if the given value is greater
+    /// than the current value.
+    fn absolute_counter(&self, key: Key, value: u64);
+
     /// Updates a gauge.
     fn update_gauge(&self, key: Key, value: f64);
 
+    /// Increments a gauge.
+    ///
+    /// Implementations must apply the increment atomically, such that concurrent increments and
+    /// decrements of the same gauge are never lost.
+    fn increment_gauge(&self, key: Key, value: f64);
+
+    /// Decrements a gauge.
+    ///
+    /// Implementations must apply the decrement atomically, such that concurrent increments and
+    /// decrements of the same gauge are never lost.
+    fn decrement_gauge(&self, key: Key, value: f64);
+
     /// Records a histogram.
-    fn record_histogram(&self, key: Key, value: u64);
+    fn record_histogram(&self, key: Key, value: f64);
 }
 
 struct NoopRecorder;
 
 impl Recorder for NoopRecorder {
-    fn register_counter(&self, _key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {
+    fn register_counter(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _description: Option<&'static str>,
+    ) -> Counter {
+        Counter::noop()
+    }
+    fn register_gauge(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _description: Option<&'static str>,
+    ) -> Gauge {
+        Gauge::noop()
     }
-    fn register_gauge(&self, _key: Key, _unit: Option<Unit>, _description: Option<&'static str>) {}
     fn register_histogram(
         &self,
         _key: Key,
         _unit: Option<Unit>,
         _description: Option<&'static str>,
-    ) {
+    ) -> Histogram {
+        Histogram::noop()
     }
+    fn describe_counter(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
+    fn describe_gauge(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
+    fn describe_histogram(&self, _name: SharedString, _unit: Option<Unit>, _desc: SharedString) {}
     fn increment_counter(&self, _key: Key, _value: u64) {}
+    fn absolute_counter(&self, _key: Key, _value: u64) {}
     fn update_gauge(&self, _key: Key, _value: f64) {}
-    fn record_histogram(&self, _key: Key, _value: u64) {}
+    fn increment_gauge(&self, _key: Key, _value: f64) {}
+    fn decrement_gauge(&self, _key: Key, _value: f64) {}
+    fn record_histogram(&self, _key: Key, _value: f64) {}
+}
+
+impl<R: Recorder + ?Sized> Recorder for Box<R> {
+    fn register_counter(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Counter {
+        (**self).register_counter(key, unit, description)
+    }
+
+    fn register_gauge(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Gauge {
+        (**self).register_gauge(key, unit, description)
+    }
+
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        description: Option<&'static str>,
+    ) -> Histogram {
+        (**self).register_histogram(key, unit, description)
+    }
+
+    fn describe_counter(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        (**self).describe_counter(name, unit, description)
+    }
+
+    fn describe_gauge(&self, name: SharedString, unit: Option<Unit>, description: SharedString) {
+        (**self).describe_gauge(name, unit, description)
+    }
+
+    fn describe_histogram(
+        &self,
+        name: SharedString,
+        unit: Option<Unit>,
+        description: SharedString,
+    ) {
+        (**self).describe_histogram(name, unit, description)
+    }
+
+    fn increment_counter(&self, key: Key, value: u64) {
+        (**self).increment_counter(key, value)
+    }
+
+    fn absolute_counter(&self, key: Key, value: u64) {
+        (**self).absolute_counter(key, value)
+    }
+
+    fn update_gauge(&self, key: Key, value: f64) {
+        (**self).update_gauge(key, value)
+    }
+
+    fn increment_gauge(&self, key: Key, value: f64) {
+        (**self).increment_gauge(key, value)
+    }
+
+    fn decrement_gauge(&self, key: Key, value: f64) {
+        (**self).decrement_gauge(key, value)
+    }
+
+    fn record_histogram(&self, key: Key, value: f64) {
+        (**self).record_histogram(key, value)
+    }
 }
 
 /// Sets the global recorder to a `&'static Recorder`.
@@ -79,7 +254,8 @@ impl Recorder for NoopRecorder {
 ///
 /// # Errors
 ///
-/// An error is returned if a recorder has already been set.
+/// An error is returned if a recorder has already been set, including one installed via
+/// [`replace_recorder`].
 #[cfg(atomic_cas)]
 pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), SetRecorderError> {
     set_recorder_inner(|| recorder)
@@ -95,29 +271,82 @@ pub fn set_recorder(recorder: &'static dyn Recorder) -> Result<(), SetRecorderEr
 ///
 /// # Errors
 ///
-/// An error is returned if a recorder has already been set.
+/// An error is returned if a recorder has already been set, including one installed via
+/// [`replace_recorder`].
 #[cfg(all(feature = "std", atomic_cas))]
 pub fn set_boxed_recorder(recorder: Box<dyn Recorder>) -> Result<(), SetRecorderError> {
     set_recorder_inner(|| unsafe { &*Box::into_raw(recorder) })
 }
 
+/// Sets the global recorder to a `Box<Recorder>`, replacing any recorder previously installed by
+/// this function.
+///
+/// Unlike [`set_boxed_recorder`], this function may be called any number of times, which allows
+/// reconfiguring the exporter at runtime, such as when reloading configuration, without
+/// restarting the process.  The swap is atomic: calls already in-flight finish on the old
+/// recorder, which is dropped once the last of them completes, and all subsequent calls go to the
+/// new recorder.
+///
+/// Handles returned from registering a metric are bound to the recorder that created them, so any
+/// handles obtained before the replacement will keep updating the old recorder.  Callers that hold
+/// on to handles should register them again after replacing the recorder.
+///
+/// As the installed recorder can be dropped at any point, it is not visible to [`try_recorder`] or
+/// [`recorder`], which both hand out a `'static` reference.  Use [`with_recorder`] instead.
+///
+/// Requires the `std` feature.
+///
+/// # Errors
+///
+/// An error is returned if a recorder has already been set via [`set_recorder`],
+/// [`set_boxed_recorder`], or [`set_recorder_racy`], as those cannot be replaced.
+#[cfg(all(feature = "std", atomic_cas))]
+pub fn replace_recorder(recorder: Box<dyn Recorder + Send + Sync>) -> Result<(), SetRecorderError> {
+    let recorder = Some(Arc::new(recorder));
+    loop {
+        match STATE.compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => {
+                REPLACEABLE_RECORDER.store(recorder);
+                STATE.store(REPLACEABLE, Ordering::SeqCst);
+                return Ok(());
+            }
+            Err(INITIALIZING) => while STATE.load(Ordering::SeqCst) == INITIALIZING {},
+            Err(REPLACEABLE) => {
+                REPLACEABLE_RECORDER.store(recorder);
+                return Ok(());
+            }
+            Err(_) => return Err(SetRecorderError(())),
+        }
+    }
+}
+
 #[cfg(atomic_cas)]
 fn set_recorder_inner<F>(make_recorder: F) -> Result<(), SetRecorderError>
 where
     F: FnOnce() -> &'static dyn Recorder,
 {
     unsafe {
-        match STATE.compare_and_swap(UNINITIALIZED, INITIALIZING, Ordering::SeqCst) {
-            UNINITIALIZED => {
+        match STATE.compare_exchange(
+            UNINITIALIZED,
+            INITIALIZING,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        ) {
+            Ok(_) => {
                 RECORDER = make_recorder();
                 STATE.store(INITIALIZED, Ordering::SeqCst);
                 Ok(())
             }
-            INITIALIZING => {
+            Err(INITIALIZING) => {
                 while STATE.load(Ordering::SeqCst) == INITIALIZING {}
                 Err(SetRecorderError(()))
             }
-            _ => Err(SetRecorderError(())),
+            Err(_) => Err(SetRecorderError(())),
         }
     }
 }
@@ -158,11 +387,15 @@ pub unsafe fn set_recorder_racy(recorder: &'static dyn Recorder) -> Result<(), S
 ///
 /// As we give out a reference to the recorder with a static lifetime, we cannot safely reclaim
 /// and drop the installed recorder when clearing.  Thus, any existing recorder will stay leaked.
+/// Recorders installed via [`replace_recorder`] are the exception, and are dropped once any
+/// in-flight calls complete.
 ///
 /// This method is typically only useful for testing or benchmarking.
 #[doc(hidden)]
 pub fn clear_recorder() {
     STATE.store(UNINITIALIZED, Ordering::SeqCst);
+    #[cfg(feature = "std")]
+    REPLACEABLE_RECORDER.store(None);
 }
 
 /// The type returned by [`set_recorder`] if [`set_recorder`] has already been called.
@@ -186,6 +419,12 @@ impl std::error::Error for SetRecorderError {
 /// Returns a reference to the recorder.
 ///
 /// If a recorder has not been set, a no-op implementation is returned.
+///
+/// As with [`try_recorder`], only a recorder installed via [`set_recorder`],
+/// [`set_boxed_recorder`] or [`set_recorder_racy`] is returned.  Recorders installed for the
+/// current thread via [`with_local_recorder`], or via [`replace_recorder`], are not visible, so
+/// the no-op implementation is returned while they are in effect.  Use [`with_recorder`] to
+/// access whichever recorder is currently in effect.
 pub fn recorder() -> &'static dyn Recorder {
     static NOOP: NoopRecorder = NoopRecorder;
     try_recorder().unwrap_or(&NOOP)
@@ -194,6 +433,11 @@ pub fn recorder() -> &'static dyn Recorder {
 /// Returns a reference to the recorder.
 ///
 /// If a recorder has not been set, returns `None`.
+///
+/// Only the global recorder is considered here: recorders installed for the current thread via
+/// [`with_local_recorder`] are not visible, as they do not live for `'static`.  Use
+/// [`with_recorder`] to access whichever recorder is currently in effect.  The same goes for
+/// recorders installed via [`replace_recorder`], which may be dropped when replaced.
 pub fn try_recorder() -> Option<&'static dyn Recorder> {
     unsafe {
         if STATE.load(Ordering::Relaxed) != INITIALIZED {
@@ -203,3 +447,192 @@ pub fn try_recorder() -> Option<&'static dyn Recorder> {
         }
     }
 }
+
+/// Runs the closure with a reference to the current recorder.
+///
+/// A recorder installed for the current thread via [`with_local_recorder`] takes precedence over
+/// the global recorder.  If neither has been set, the closure is not run and `None` is returned.
+///
+/// This is what the registration and emission macros use to find the recorder to talk to.
+pub fn with_recorder<T, F>(f: F) -> Option<T>
+where
+    F: FnOnce(&dyn Recorder) -> T,
+{
+    #[cfg(feature = "std")]
+    {
+        if let Some(recorder) = LOCAL_RECORDER.with(|local| local.get()) {
+            // SAFETY: The pointer is only set for the duration of `with_local_recorder`, which
+            // holds a borrow of the recorder, so it is valid for as long as it is installed.
+            return Some(f(unsafe { recorder.as_ref() }));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    {
+        if STATE.load(Ordering::Relaxed) == REPLACEABLE {
+            // Holding the guard keeps the recorder alive until the closure returns, even if it is
+            // replaced in the meantime.
+            let recorder = REPLACEABLE_RECORDER.load();
+            return recorder
+                .as_ref()
+                .map(|recorder| f(recorder.as_ref().as_ref()));
+        }
+    }
+
+    try_recorder().map(f)
+}
+
+/// Runs the closure with the given recorder installed for the current thread.
+///
+/// While the closure runs, all metrics emitted from the current thread go to `recorder`, ahead of
+/// any globally installed recorder.  Other threads are unaffected, which makes this suitable for
+/// tests that run in parallel and each want to assert on the metrics emitted by the code under
+/// test.  Calls may be nested, in which case the innermost recorder is used, and the previous
+/// recorder is restored once the closure returns, even if it panics.
+///
+/// Requires the `std` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # use metrics::{increment, with_local_recorder, Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
+/// # use std::sync::atomic::{AtomicU64, Ordering};
+/// #[derive(Default)]
+/// struct CountingRecorder(AtomicU64);
+///
+/// impl Recorder for CountingRecorder {
+/// #   fn register_counter(&self, _: Key, _: Option<Unit>, _: Option<&'static str>) -> Counter { Counter::noop() }
+/// #   fn register_gauge(&self, _: Key, _: Option<Unit>, _: Option<&'static str>) -> Gauge { Gauge::noop() }
+/// #   fn register_histogram(&self, _: Key, _: Option<Unit>, _: Option<&'static str>) -> Histogram { Histogram::noop() }
+/// #   fn describe_counter(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+/// #   fn describe_gauge(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+/// #   fn describe_histogram(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+///     fn increment_counter(&self, _key: Key, value: u64) {
+///         self.0.fetch_add(value, Ordering::Relaxed);
+///     }
+///     // ...
+/// #   fn absolute_counter(&self, _: Key, _: u64) {}
+/// #   fn update_gauge(&self, _: Key, _: f64) {}
+/// #   fn increment_gauge(&self, _: Key, _: f64) {}
+/// #   fn decrement_gauge(&self, _: Key, _: f64) {}
+/// #   fn record_histogram(&self, _: Key, _: f64) {}
+/// }
+///
+/// let recorder = CountingRecorder::default();
+/// with_local_recorder(&recorder, || {
+///     increment!("requests_processed");
+/// });
+/// assert_eq!(recorder.0.load(Ordering::Relaxed), 1);
+/// ```
+#[cfg(feature = "std")]
+pub fn with_local_recorder<T, F>(recorder: &dyn Recorder, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    struct Guard(Option<NonNull<dyn Recorder>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            LOCAL_RECORDER.with(|local| local.set(self.0.take()));
+        }
+    }
+
+    // SAFETY: We erase the lifetime of the recorder in order to stash it in a thread local, but
+    // the guard removes it again before this function returns, so it never outlives the borrow.
+    let recorder =
+        unsafe { core::mem::transmute::<&dyn Recorder, &'static dyn Recorder>(recorder) };
+    let previous = LOCAL_RECORDER.with(|local| local.replace(Some(NonNull::from(recorder))));
+    let _guard = Guard(previous);
+
+    f()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{with_local_recorder, with_recorder, NoopRecorder, Recorder};
+    use crate::{Counter, Gauge, Histogram, Key, SharedString, Unit};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Default)]
+    struct CountingRecorder(AtomicU64);
+
+    impl Recorder for CountingRecorder {
+        fn register_counter(
+            &self,
+            key: Key,
+            unit: Option<Unit>,
+            d: Option<&'static str>,
+        ) -> Counter {
+            NoopRecorder.register_counter(key, unit, d)
+        }
+        fn register_gauge(&self, key: Key, unit: Option<Unit>, d: Option<&'static str>) -> Gauge {
+            NoopRecorder.register_gauge(key, unit, d)
+        }
+        fn register_histogram(
+            &self,
+            key: Key,
+            unit: Option<Unit>,
+            d: Option<&'static str>,
+        ) -> Histogram {
+            NoopRecorder.register_histogram(key, unit, d)
+        }
+        fn describe_counter(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+        fn describe_gauge(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+        fn describe_histogram(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+        fn increment_counter(&self, _key: Key, value: u64) {
+            self.0.fetch_add(value, Ordering::Relaxed);
+        }
+        fn absolute_counter(&self, _key: Key, _value: u64) {}
+        fn update_gauge(&self, _key: Key, _value: f64) {}
+        fn increment_gauge(&self, _key: Key, _value: f64) {}
+        fn decrement_gauge(&self, _key: Key, _value: f64) {}
+        fn record_histogram(&self, _key: Key, _value: f64) {}
+    }
+
+    fn increment() {
+        with_recorder(|recorder| recorder.increment_counter(Key::Owned("test".into()), 1));
+    }
+
+    #[test]
+    fn test_local_recorder_nesting() {
+        let outer = CountingRecorder::default();
+        let inner = CountingRecorder::default();
+
+        with_local_recorder(&outer, || {
+            increment();
+            with_local_recorder(&inner, increment);
+            increment();
+        });
+
+        assert_eq!(outer.0.load(Ordering::Relaxed), 2);
+        assert_eq!(inner.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_local_recorder_is_thread_local() {
+        let recorder = CountingRecorder::default();
+
+        with_local_recorder(&recorder, || {
+            std::thread::spawn(increment).join().unwrap();
+        });
+
+        assert_eq!(recorder.0.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_local_recorder_restored_on_panic() {
+        let outer = CountingRecorder::default();
+        let inner = CountingRecorder::default();
+
+        with_local_recorder(&outer, || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                with_local_recorder(&inner, || panic!("boom"))
+            }));
+            assert!(result.is_err());
+            increment();
+        });
+
+        assert_eq!(outer.0.load(Ordering::Relaxed), 1);
+        assert_eq!(inner.0.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,64 @@
+use metrics::{
+    counted, counter, gauge, histogram, increment, register_histogram, MetricKind, Unit,
+};
+
+fn emit(method: &str) {
+    increment!("requests", "method" => method.to_string());
+    counter!("bytes", 42);
+    histogram!("latency", 1.0, "stage" => "read");
+}
+
+#[allow(dead_code)]
+fn never_called() {
+    // Call sites are cataloged even if they're never reached.
+    gauge!("requests", 1.0);
+    let _ = register_histogram!("latency", Unit::Seconds, "request latency");
+}
+
+#[counted(name = "handled", result)]
+fn handle() -> Result<(), ()> {
+    Ok(())
+}
+
+#[test]
+fn test_catalog() {
+    emit("GET");
+    let _ = handle();
+
+    let catalog = metrics::catalog();
+    let callsites = catalog
+        .callsites()
+        .iter()
+        .map(|c| (c.name(), c.kind(), c.label_keys()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        callsites,
+        vec![
+            ("bytes", MetricKind::Counter, &[][..]),
+            ("handled", MetricKind::Counter, &["result"][..]),
+            ("latency", MetricKind::Histogram, &["stage"][..]),
+            ("latency", MetricKind::Histogram, &[][..]),
+            ("requests", MetricKind::Counter, &["method"][..]),
+            ("requests", MetricKind::Gauge, &[][..]),
+        ]
+    );
+
+    let latency = catalog.callsites()[3];
+    assert_eq!(latency.unit(), Some(&Unit::Seconds));
+    assert_eq!(latency.description(), Some("request latency"));
+    assert_eq!(latency.module_path(), "catalog");
+    assert!(latency.file().ends_with("catalog.rs"));
+    assert_eq!(catalog.callsites()[2].unit(), None);
+
+    let conflicts = catalog.conflicts();
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].name(), "requests");
+    assert_eq!(conflicts[0].callsites().len(), 2);
+    assert_eq!(
+        conflicts[0].to_string(),
+        concat!(
+            "metric 'requests' is used as more than one kind: ",
+            "Counter at metrics/tests/catalog.rs:6, Gauge at metrics/tests/catalog.rs:14"
+        )
+    );
+}
@@ -0,0 +1,231 @@
+use metrics::{
+    counted, timed, with_local_recorder, Counter, Gauge, Histogram, Key, Recorder, SharedString,
+    Unit,
+};
+use std::future::Future;
+use std::num::ParseIntError;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// Records every counter increment and histogram sample, as `name{labels}`, along with the units
+/// histograms are registered with.
+#[derive(Default)]
+struct CapturingRecorder {
+    counters: Mutex<Vec<(String, u64)>>,
+    histograms: Mutex<Vec<(String, f64)>>,
+    histogram_units: Mutex<Vec<(String, Option<Unit>)>>,
+}
+
+fn render(key: &Key) -> String {
+    let labels = key
+        .labels()
+        .map(|l| format!("{}={}", l.key(), l.value()))
+        .collect::<Vec<_>>();
+    format!("{}{{{}}}", key.name(), labels.join(","))
+}
+
+impl Recorder for CapturingRecorder {
+    fn register_counter(&self, _: Key, _: Option<Unit>, _: Option<&'static str>) -> Counter {
+        Counter::noop()
+    }
+    fn register_gauge(&self, _: Key, _: Option<Unit>, _: Option<&'static str>) -> Gauge {
+        Gauge::noop()
+    }
+    fn register_histogram(
+        &self,
+        key: Key,
+        unit: Option<Unit>,
+        _: Option<&'static str>,
+    ) -> Histogram {
+        self.histogram_units
+            .lock()
+            .unwrap()
+            .push((render(&key), unit));
+        Histogram::noop()
+    }
+    fn describe_counter(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: SharedString, _: Option<Unit>, _: SharedString) {}
+    fn increment_counter(&self, key: Key, value: u64) {
+        self.counters.lock().unwrap().push((render(&key), value));
+    }
+    fn absolute_counter(&self, _: Key, _: u64) {}
+    fn update_gauge(&self, _: Key, _: f64) {}
+    fn increment_gauge(&self, _: Key, _: f64) {}
+    fn decrement_gauge(&self, _: Key, _: f64) {}
+    fn record_histogram(&self, key: Key, value: f64) {
+        self.histograms.lock().unwrap().push((render(&key), value));
+    }
+}
+
+/// Polls a future which never waits on anything to completion.
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[timed]
+#[counted]
+fn plain(value: u64) -> u64 {
+    value + 1
+}
+
+#[timed(name = "parse_time", result)]
+#[counted(name = "parse_calls", result)]
+fn parse(input: &str) -> Result<u64, ParseIntError> {
+    if input.is_empty() {
+        return Ok(0);
+    }
+    let value = input.parse::<u64>()?;
+    Ok(value)
+}
+
+struct Values {
+    values: Vec<u32>,
+}
+
+impl Values {
+    #[timed(name = "first_time")]
+    #[counted(name = "first_calls")]
+    fn first(&mut self) -> &mut u32 {
+        &mut self.values[0]
+    }
+
+    #[counted(name = "first_async_calls")]
+    async fn first_async(&self) -> &u32 {
+        &self.values[0]
+    }
+
+    #[timed(name = "get_time", result)]
+    #[counted(name = "get_calls", result)]
+    fn get(&self, index: &str) -> Result<&u32, String> {
+        if index.is_empty() {
+            return Err("empty".to_string());
+        }
+        let index = index.parse::<usize>().map_err(|e| e.to_string())?;
+        self.values.get(index).ok_or_else(|| "missing".to_string())
+    }
+}
+
+#[timed(name = "async_time", result)]
+#[counted]
+async fn parse_async(input: &str) -> Result<u64, ParseIntError> {
+    input.parse::<u64>()
+}
+
+#[test]
+fn test_instrumented_fns() {
+    let recorder = CapturingRecorder::default();
+    with_local_recorder(&recorder, || {
+        assert_eq!(plain(1), 2);
+        assert_eq!(parse(""), Ok(0));
+        assert_eq!(parse("42"), Ok(42));
+        assert!(parse("nope").is_err());
+
+        // Nothing is recorded until the future completes.
+        let future = parse_async("7");
+        assert_eq!(recorder.histograms.lock().unwrap().len(), 4);
+        assert_eq!(block_on(future), Ok(7));
+        assert!(block_on(parse_async("nope")).is_err());
+    });
+
+    let counters = recorder.counters.into_inner().unwrap();
+    assert_eq!(
+        counters,
+        vec![
+            ("instrumented::plain_calls{}".to_string(), 1),
+            ("parse_calls{result=ok}".to_string(), 1),
+            ("parse_calls{result=ok}".to_string(), 1),
+            ("parse_calls{result=error}".to_string(), 1),
+            ("instrumented::parse_async_calls{}".to_string(), 1),
+            ("instrumented::parse_async_calls{}".to_string(), 1),
+        ]
+    );
+
+    // Every call site lets the recorder know that timings are in nanoseconds.
+    let units = recorder.histogram_units.into_inner().unwrap();
+    assert_eq!(units.len(), 5);
+    assert!(units
+        .iter()
+        .all(|(_, unit)| *unit == Some(Unit::Nanoseconds)));
+
+    let histograms = recorder.histograms.into_inner().unwrap();
+    let names = histograms
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "instrumented::plain_duration{}",
+            "parse_time{result=ok}",
+            "parse_time{result=ok}",
+            "parse_time{result=error}",
+            "async_time{result=ok}",
+            "async_time{result=error}",
+        ]
+    );
+    assert!(histograms.iter().all(|(_, nanos)| *nanos >= 0.0));
+}
+
+#[test]
+fn test_instrumented_methods() {
+    let recorder = CapturingRecorder::default();
+    let mut values = Values { values: vec![1, 2] };
+    with_local_recorder(&recorder, || {
+        *values.first() += 1;
+        assert_eq!(block_on(values.first_async()), &2);
+        assert_eq!(values.get("0"), Ok(&2));
+        assert_eq!(values.get(""), Err("empty".to_string()));
+        assert_eq!(
+            values.get("x"),
+            Err("invalid digit found in string".to_string())
+        );
+        assert_eq!(values.get("5"), Err("missing".to_string()));
+    });
+
+    let counters = recorder.counters.into_inner().unwrap();
+    assert_eq!(
+        counters,
+        vec![
+            ("first_calls{}".to_string(), 1),
+            ("first_async_calls{}".to_string(), 1),
+            ("get_calls{result=ok}".to_string(), 1),
+            ("get_calls{result=error}".to_string(), 1),
+            ("get_calls{result=error}".to_string(), 1),
+            ("get_calls{result=error}".to_string(), 1),
+        ]
+    );
+
+    let histograms = recorder.histograms.into_inner().unwrap();
+    let names = histograms
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            "first_time{}",
+            "get_time{result=ok}",
+            "get_time{result=error}",
+            "get_time{result=error}",
+            "get_time{result=error}",
+        ]
+    );
+}
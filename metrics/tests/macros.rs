@@ -3,4 +3,7 @@ pub fn macros() {
     let t = trybuild::TestCases::new();
     t.pass("tests/macros/01_trailing_comma.rs");
     t.compile_fail("tests/macros/02_metric_name.rs");
+    t.pass("tests/macros/03_instrumented_fns.rs");
+    t.pass("tests/macros/04_non_literal_names.rs");
+    t.pass("tests/macros/05_dynamic_label_values.rs");
 }
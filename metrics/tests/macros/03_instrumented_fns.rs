@@ -0,0 +1,61 @@
+use metrics::{counted, timed};
+use std::num::ParseIntError;
+
+#[timed]
+fn plain(value: u64) -> u64 {
+    value + 1
+}
+
+#[timed(name = "parse_time", result)]
+#[counted(name = "parse_calls", result)]
+fn early_return(input: &str) -> Result<u64, ParseIntError> {
+    if input.is_empty() {
+        return Ok(0);
+    }
+    let value = input.parse::<u64>()?;
+    Ok(value)
+}
+
+#[counted]
+fn impl_trait(values: Vec<u64>) -> impl Iterator<Item = u64> {
+    values.into_iter()
+}
+
+#[timed(result, name = "async_time")]
+async fn in_async(input: String) -> Result<u64, Box<dyn std::error::Error>> {
+    let value = input.parse::<u64>()?;
+    Ok(value)
+}
+
+struct Widget(u64);
+
+impl Widget {
+    #[counted]
+    fn by_ref(&self) -> u64 {
+        self.0
+    }
+
+    #[timed]
+    fn by_mut(&mut self, value: u64) {
+        self.0 = value;
+    }
+
+    #[timed]
+    async fn by_ref_async(&self) -> u64 {
+        self.0
+    }
+}
+
+fn main() {
+    assert_eq!(plain(1), 2);
+    assert_eq!(early_return(""), Ok(0));
+    assert_eq!(early_return("42"), Ok(42));
+    assert!(early_return("nope").is_err());
+    assert_eq!(impl_trait(vec![1, 2]).sum::<u64>(), 3);
+    let _ = in_async("42".to_string());
+
+    let mut widget = Widget(1);
+    widget.by_mut(2);
+    assert_eq!(widget.by_ref(), 2);
+    let _ = widget.by_ref_async();
+}
@@ -0,0 +1,53 @@
+use metrics::{
+    counter, describe_counter, histogram, increment, register_counter, register_gauge, SharedString,
+    Unit,
+};
+
+const REQUESTS: &str = "requests";
+static LATENCY: &str = "latency";
+static SHARED: SharedString = SharedString::const_str("shared");
+const OWNED: String = String::new();
+
+mod names {
+    pub const ERRORS: &str = "errors";
+}
+
+fn const_names() {
+    increment!(REQUESTS);
+    increment!(names::ERRORS, "kind" => "timeout");
+    histogram!(LATENCY, 1.0);
+    counter!(concat!("app.", "requests"), 1);
+    describe_counter!(REQUESTS, Unit::Count, "number of requests");
+}
+
+fn const_names_of_other_types() {
+    increment!(SHARED);
+    increment!(SHARED, "kind" => "timeout");
+    counter!(OWNED, 1, "kind" => "timeout");
+    histogram!(OWNED, 1.0);
+    let _ = register_counter!(SHARED);
+}
+
+fn runtime_names(prefix: &str, name: &'static str) {
+    increment!(name);
+    increment!(format!("{}.requests", prefix), "kind" => "timeout");
+    counter!(prefix.to_string(), 1, &[("kind", "timeout")]);
+    let _ = register_gauge!(format!("{}.queue_depth", prefix), "queue depth");
+    describe_counter!(format!("{}.requests", prefix), "number of requests");
+}
+
+#[allow(non_snake_case)]
+fn locals_named_like_consts(REQUESTS_NAME: &str, prefix: &str) {
+    let name = format!("{}.errors", prefix);
+    let ERRORS_NAME: &str = &name;
+    increment!(REQUESTS_NAME);
+    increment!(ERRORS_NAME, "kind" => "timeout");
+    histogram!(ERRORS_NAME, 1.0);
+}
+
+fn main() {
+    const_names();
+    const_names_of_other_types();
+    runtime_names("app", "static_name");
+    locals_named_like_consts("app.requests", "app");
+}
@@ -0,0 +1,26 @@
+use metrics::{counter, histogram, increment};
+
+struct Request {
+    method: String,
+    status: u16,
+}
+
+impl Request {
+    fn method(&self) -> &str {
+        &self.method
+    }
+}
+
+fn record(request: &Request, route: &'static str) {
+    increment!("requests", "method" => request.method());
+    counter!("bytes", 42, "method" => &request.method, "route" => route);
+    histogram!("latency", 1.0, "status" => request.status.to_string(), "route" => "/");
+}
+
+fn main() {
+    let request = Request {
+        method: "GET".to_string(),
+        status: 200,
+    };
+    record(&request, "/index");
+}
@@ -0,0 +1,75 @@
+use metrics::{Counter, Gauge, Histogram, Key, Recorder, SharedString, Unit};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// The global recorder is shared by every test in a binary, so this test lives in its own binary
+// to keep it from racing with anything else.
+
+#[derive(Clone, Default)]
+struct CountingRecorder(Arc<AtomicU64>);
+
+impl Recorder for CountingRecorder {
+    fn register_counter(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _d: Option<&'static str>,
+    ) -> Counter {
+        Counter::noop()
+    }
+    fn register_gauge(&self, _key: Key, _unit: Option<Unit>, _d: Option<&'static str>) -> Gauge {
+        Gauge::noop()
+    }
+    fn register_histogram(
+        &self,
+        _key: Key,
+        _unit: Option<Unit>,
+        _d: Option<&'static str>,
+    ) -> Histogram {
+        Histogram::noop()
+    }
+    fn describe_counter(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+    fn describe_gauge(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+    fn describe_histogram(&self, _name: SharedString, _unit: Option<Unit>, _d: SharedString) {}
+    fn increment_counter(&self, _key: Key, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+    fn absolute_counter(&self, _key: Key, _value: u64) {}
+    fn update_gauge(&self, _key: Key, _value: f64) {}
+    fn increment_gauge(&self, _key: Key, _value: f64) {}
+    fn decrement_gauge(&self, _key: Key, _value: f64) {}
+    fn record_histogram(&self, _key: Key, _value: f64) {}
+}
+
+fn increment() {
+    metrics::with_recorder(|recorder| recorder.increment_counter(Key::Owned("test".into()), 1));
+}
+
+#[test]
+fn test_replace_recorder() {
+    let first = CountingRecorder::default();
+    let second = CountingRecorder::default();
+
+    metrics::replace_recorder(Box::new(first.clone())).expect("failed to install recorder");
+    increment();
+    metrics::replace_recorder(Box::new(second.clone())).expect("failed to replace recorder");
+    increment();
+    increment();
+
+    assert_eq!(first.0.load(Ordering::Relaxed), 1);
+    assert_eq!(second.0.load(Ordering::Relaxed), 2);
+
+    // The replaced recorder has been dropped, a fixed recorder can't take over, and the current
+    // recorder isn't visible to `try_recorder`.
+    assert_eq!(Arc::strong_count(&first.0), 1);
+    assert!(metrics::set_boxed_recorder(Box::new(CountingRecorder::default())).is_err());
+    assert!(metrics::try_recorder().is_none());
+
+    // Nor is it visible to `recorder`, which hands out the no-op recorder instead.
+    metrics::recorder().increment_counter(Key::Owned("test".into()), 1);
+    assert_eq!(second.0.load(Ordering::Relaxed), 2);
+
+    metrics::clear_recorder();
+    assert_eq!(Arc::strong_count(&second.0), 1);
+    assert!(metrics::with_recorder(|_| ()).is_none());
+}